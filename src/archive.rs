@@ -0,0 +1,161 @@
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::cartridge::CartridgeError;
+
+const ROM_EXTENSIONS: [&str; 5] = ["nes", "unf", "fds", "nsf", "nsfe"];
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ROM_EXTENSIONS.iter().any(|r| e.eq_ignore_ascii_case(r)))
+}
+
+fn base_name(name: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .map_or_else(|| name.to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+// Returns the ROM's own file name together with its contents. Plain images are read as is,
+// zip archives yield `entry` (or the first ROM they contain) and gzip files their single member.
+pub fn read_rom(path: &Path, entry: Option<&str>) -> Result<(String, Vec<u8>), CartridgeError> {
+    unpack(path, fs::read(path)?, entry)
+}
+
+fn unpack(
+    path: &Path,
+    data: Vec<u8>,
+    entry: Option<&str>,
+) -> Result<(String, Vec<u8>), CartridgeError> {
+    if data.starts_with(b"PK\x03\x04") {
+        read_zip(&data, entry)
+    } else if data.starts_with(&[0x1F, 0x8B]) {
+        read_gzip(path, &data)
+    } else {
+        Ok((base_name(&path.to_string_lossy()), data))
+    }
+}
+
+fn read_zip(compressed: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), CartridgeError> {
+    let mut archive = ZipArchive::new(Cursor::new(compressed))?;
+
+    let index = match entry {
+        Some(name) => (0..archive.len()).find(|&i| {
+            archive
+                .by_index(i)
+                .is_ok_and(|f| f.name() == name || base_name(f.name()) == name)
+        }),
+        None => {
+            (0..archive.len()).find(|&i| archive.by_index(i).is_ok_and(|f| is_rom_name(f.name())))
+        }
+    }
+    .ok_or(CartridgeError::NoRomInArchive)?;
+
+    let mut file = archive.by_index(index)?;
+    let mut data = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut data)?;
+
+    Ok((base_name(file.name()), data))
+}
+
+fn read_gzip(path: &Path, compressed: &[u8]) -> Result<(String, Vec<u8>), CartridgeError> {
+    let mut decoder = GzDecoder::new(compressed);
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data)?;
+
+    // Prefer the name stored in the gzip header, otherwise "game.nes.gz" -> "game.nes"
+    let name = match decoder.header().and_then(|h| h.filename()) {
+        Some(name) => base_name(&String::from_utf8_lossy(name)),
+        None => path
+            .file_stem()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+    };
+
+    Ok((name, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression, GzBuilder};
+    use std::io::Write;
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn zip_entries() {
+        let archive = zip(&[
+            ("readme.txt", b"not a rom"),
+            ("roms/first.nes", b"first"),
+            ("roms/second.nes", b"second"),
+        ]);
+        let path = Path::new("games/pack.zip");
+
+        // The first ROM by default, skipping other files
+        let (name, data) = unpack(path, archive.clone(), None).unwrap();
+        assert_eq!(
+            (name.as_str(), data.as_slice()),
+            ("first.nes", &b"first"[..])
+        );
+
+        // --entry matches the full path or just the file name
+        for entry in ["roms/second.nes", "second.nes"].iter() {
+            let (name, data) = unpack(path, archive.clone(), Some(entry)).unwrap();
+            assert_eq!(
+                (name.as_str(), data.as_slice()),
+                ("second.nes", &b"second"[..])
+            );
+        }
+
+        assert!(matches!(
+            unpack(path, archive, Some("third.nes")),
+            Err(CartridgeError::NoRomInArchive)
+        ));
+        assert!(matches!(
+            unpack(path, zip(&[("readme.txt", b"")]), None),
+            Err(CartridgeError::NoRomInArchive)
+        ));
+    }
+
+    #[test]
+    fn gzip_names() {
+        // The name stored in the header wins
+        let mut encoder = GzBuilder::new()
+            .filename("stored.nes")
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(b"rom").unwrap();
+        let (name, data) = unpack(Path::new("x.gz"), encoder.finish().unwrap(), None).unwrap();
+        assert_eq!(
+            (name.as_str(), data.as_slice()),
+            ("stored.nes", &b"rom"[..])
+        );
+
+        // Otherwise the file name without .gz
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"rom").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let (name, _) = unpack(Path::new("dir/game.nes.gz"), compressed, None).unwrap();
+        assert_eq!(name, "game.nes");
+
+        // Anything else is read as is
+        let (name, data) = unpack(Path::new("dir/game.nes"), b"NES".to_vec(), None).unwrap();
+        assert_eq!((name.as_str(), data.as_slice()), ("game.nes", &b"NES"[..]));
+    }
+}
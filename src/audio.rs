@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+
+// Sample rate of the sound the core produces
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// The console's output capacitor high-passes at about 90Hz, 1 - 2pi * 90 / SAMPLE_RATE
+const HIGH_PASS: f32 = 0.987;
+
+// Samples held when nothing drains them, such as when running headless
+const MAX_QUEUED: usize = SAMPLE_RATE as usize;
+
+// Turns the level sound sources reach on every CPU cycle into samples at SAMPLE_RATE. Each
+// sample is the average of the cycles it covers, which also filters what lies above the
+// output rate.
+pub struct Resampler {
+    cycles_per_sample: f64,
+    elapsed: f64,
+    sum: f32,
+    count: u32,
+    previous_input: f32,
+    previous_output: f32,
+    samples: VecDeque<f32>,
+}
+
+impl Resampler {
+    pub fn new(cpu_rate: f64) -> Self {
+        Resampler {
+            cycles_per_sample: cpu_rate / SAMPLE_RATE as f64,
+            elapsed: 0.0,
+            sum: 0.0,
+            count: 0,
+            previous_input: 0.0,
+            previous_output: 0.0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn set_cpu_rate(&mut self, cpu_rate: f64) {
+        self.cycles_per_sample = cpu_rate / SAMPLE_RATE as f64;
+    }
+
+    // Level for one CPU cycle, 0.0..=1.0
+    pub fn push(&mut self, level: f32) {
+        self.sum += level;
+        self.count += 1;
+        self.elapsed += 1.0;
+
+        if self.elapsed < self.cycles_per_sample {
+            return;
+        }
+        self.elapsed -= self.cycles_per_sample;
+
+        let input = self.sum / self.count as f32;
+        let output = input - self.previous_input + HIGH_PASS * self.previous_output;
+        self.previous_input = input;
+        self.previous_output = output;
+        self.sum = 0.0;
+        self.count = 0;
+
+        if self.samples.len() == MAX_QUEUED {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(output);
+    }
+
+    // Takes the samples made since the last call, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = f32> + '_ {
+        self.samples.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Region;
+
+    #[test]
+    fn resample() {
+        let mut resampler = Resampler::new(SAMPLE_RATE as f64 * 4.0);
+
+        // Four cycles to a sample, averaged
+        for level in [0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0].iter() {
+            resampler.push(*level);
+        }
+        let samples: Vec<f32> = resampler.drain().collect();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0], 0.5);
+        assert!((samples[1] - (0.5 + 0.5 * HIGH_PASS)).abs() < 1e-6);
+        assert_eq!(resampler.drain().count(), 0);
+
+        // A constant level decays to silence
+        for _ in 0..SAMPLE_RATE * 4 {
+            resampler.push(1.0);
+        }
+        let samples: Vec<f32> = resampler.drain().collect();
+        assert_eq!(samples.len(), MAX_QUEUED);
+        assert!(samples.last().unwrap().abs() < 1e-3);
+
+        // Fractional rates keep the long run average
+        resampler.set_cpu_rate(Region::Pal.cpu_rate());
+        for _ in 0..Region::Pal.cpu_rate() as usize {
+            resampler.push(0.0);
+        }
+        let count = resampler.drain().count() as i64;
+        assert!((count - SAMPLE_RATE as i64).abs() <= 1);
+    }
+}
@@ -1,9 +1,22 @@
-use crate::{Cartridge, Cpu, Ppu};
+use crate::{
+    audio::Resampler,
+    cpu::CpuBus,
+    debugger::{AccessKind, AccessLog},
+    region::Region,
+    Cartridge, Cpu, Ppu,
+};
 
 pub struct Bus {
     pub cpu_ram: [u8; 2 * 1024],
     pub controller: [u8; 2],
-    system_clock_counter: i32,
+    // CPU reads and writes, for watchpoints
+    pub access_log: AccessLog,
+    // Sound at the output rate, for the front end to play
+    pub audio: Resampler,
+    region: Region,
+    // Where the CPU is in its PPU:CPU divider, and CPU cycles since reset for DMA alignment
+    cpu_phase: u8,
+    cpu_clock_counter: u32,
     controller_state: [u8; 2],
     dma_page: u8,
     dma_addr: u8,
@@ -17,7 +30,11 @@ impl Bus {
         Bus {
             cpu_ram: [0; 2 * 1024],
             controller: [0; 2],
-            system_clock_counter: 0,
+            access_log: AccessLog::default(),
+            audio: Resampler::new(Region::Ntsc.cpu_rate()),
+            region: Region::Ntsc,
+            cpu_phase: 0,
+            cpu_clock_counter: 0,
             controller_state: [0; 2],
             dma_page: 0x00,
             dma_addr: 0x00,
@@ -34,6 +51,8 @@ impl Bus {
         addr: u16,
         data: u8,
     ) {
+        self.access_log.record(AccessKind::Write, addr, data);
+
         if cart.cpu_write(addr, data) {
         } else if addr <= 0x1FFF {
             self.cpu_ram[(addr & 0x07FF) as usize] = data;
@@ -58,15 +77,21 @@ impl Bus {
     ) -> u8 {
         let mut data: u8 = 0x00;
 
-        if cart.cpu_read(addr, &mut data) {
+        if cart.cpu_read(addr, &mut data, read_only) {
         } else if addr <= 0x1FFF {
-            return self.cpu_ram[(addr & 0x07FF) as usize];
+            data = self.cpu_ram[(addr & 0x07FF) as usize];
         } else if addr >= 0x2000 && addr <= 0x3FFF {
             data = ppu.cpu_read(cart, addr & 0x0007, read_only);
         } else if addr >= 0x4016 && addr <= 0x4017 {
             data = ((self.controller_state[(addr & 0x0001) as usize] & 0x80) > 0) as u8;
-            self.controller_state[(addr & 0x0001) as usize] =
-                self.controller_state[(addr & 0x0001) as usize].wrapping_shl(1);
+            if !read_only {
+                self.controller_state[(addr & 0x0001) as usize] =
+                    self.controller_state[(addr & 0x0001) as usize].wrapping_shl(1);
+            }
+        }
+
+        if !read_only {
+            self.access_log.record(AccessKind::Read, addr, data);
         }
 
         data
@@ -74,9 +99,10 @@ impl Bus {
 
     pub fn reset(&mut self, cpu: &mut Cpu, ppu: &mut Ppu, cart: &mut Cartridge) {
         cart.reset();
-        cpu.reset(self, ppu, cart);
+        cpu.reset();
         ppu.reset();
-        self.system_clock_counter = 0;
+        self.cpu_phase = 0;
+        self.cpu_clock_counter = 0;
         self.dma_page = 0x00;
         self.dma_addr = 0x00;
         self.dma_data = 0x00;
@@ -84,60 +110,84 @@ impl Bus {
         self.dma_transfer = false;
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.audio.set_cpu_rate(region.cpu_rate());
+    }
+
     pub fn clock(&mut self, cpu: &mut Cpu, ppu: &mut Ppu, cart: &mut Cartridge) {
         ppu.clock(cart);
 
-        if self.system_clock_counter % 3 == 0 {
+        // The CPU runs every third dot, or five times in sixteen on PAL
+        let (dots, cycles) = self.region.dots_per_cpu_cycle();
+        let cpu_tick = self.cpu_phase < cycles;
+        self.cpu_phase = (self.cpu_phase + cycles) % dots;
+
+        if cpu_tick {
             if self.dma_transfer {
                 if self.dma_dummy {
-                    if self.system_clock_counter % 2 == 1 {
+                    if !self.cpu_clock_counter.is_multiple_of(2) {
                         self.dma_dummy = false;
                     }
-                } else if self.system_clock_counter % 2 == 0 {
-                        self.dma_data = self.cpu_read(
-                            ppu,
-                            cart,
-                            (self.dma_page as u16).wrapping_shl(8) | self.dma_addr as u16,
-                            false,
-                        )
-                    } else {
-                        match self.dma_addr % 4 {
-                            0 => {
-                            ppu.oam[(self.dma_addr / 4) as usize].y = self.dma_data;
-                            }
-                            1 => {
-                                ppu.oam[(self.dma_addr / 4) as usize].id = self.dma_data;
-                            }
-                            2 => {
-                                ppu.oam[(self.dma_addr / 4) as usize].attribute = self.dma_data;
-                            }
-                            3 => {
-                                ppu.oam[(self.dma_addr / 4) as usize].x = self.dma_data;
-                            }
-                            _ => (),
-                        }
-                        self.dma_addr = self.dma_addr.wrapping_add(1);
-
-                        if self.dma_addr == 0x00 {
-                            self.dma_transfer = false;
-                            self.dma_dummy = true;
+                } else if self.cpu_clock_counter.is_multiple_of(2) {
+                    self.dma_data = self.cpu_read(
+                        ppu,
+                        cart,
+                        (self.dma_page as u16).wrapping_shl(8) | self.dma_addr as u16,
+                        false,
+                    )
+                } else {
+                    // DMA writes through $2004, so it starts wherever OAMADDR points
+                    self.cpu_write(ppu, cart, 0x2004, self.dma_data);
+                    self.dma_addr = self.dma_addr.wrapping_add(1);
+
+                    if self.dma_addr == 0x00 {
+                        self.dma_transfer = false;
+                        self.dma_dummy = true;
                     }
                 }
             } else {
-                cpu.clock(self, ppu, cart);
+                // Every IRQ source shares one line. Only the mapper (the FDS adapter
+                // included) drives it for now, there is no APU for frame counter or DMC IRQs.
+                cpu.set_nmi(ppu.nmi_line());
+                cpu.set_irq(cart.get_mapper().borrow().irq_state());
+                cpu.clock(&mut SystemBus {
+                    bus: self,
+                    ppu,
+                    cart,
+                });
             }
-        }
 
-        if ppu.nmi {
-            ppu.nmi = false;
-            cpu.nmi(self, ppu, cart);
+            let mapper = cart.get_mapper();
+            mapper.borrow_mut().cpu_clock();
+            // Expansion audio is the only source until there is an APU to mix it with
+            self.audio.push(mapper.borrow().audio_output());
+            self.cpu_clock_counter = self.cpu_clock_counter.wrapping_add(1);
         }
+    }
+}
 
-        if cart.get_mapper().borrow().irq_state() {
-            cart.get_mapper().borrow_mut().irq_clear();
-            cpu.irq(self, ppu, cart);
-        }
+// The NES as the CPU sees it, the bus along with the PPU and cartridge mapped on it
+pub struct SystemBus<'a> {
+    pub bus: &'a mut Bus,
+    pub ppu: &'a mut Ppu,
+    pub cart: &'a mut Cartridge,
+}
+
+impl CpuBus for SystemBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bus.cpu_read(self.ppu, self.cart, addr, false)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.bus.cpu_write(self.ppu, self.cart, addr, data);
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.bus.cpu_read(self.ppu, self.cart, addr, true)
+    }
 
-        self.system_clock_counter += 1;
+    fn ppu_position(&self) -> Option<(i16, i16)> {
+        Some(self.ppu.position())
     }
 }
@@ -1,17 +1,41 @@
 use std::{
-    cell::RefCell, fs::File, io::{Read, Seek, SeekFrom}, path::Path, rc::Rc
+    cell::RefCell,
+    convert::TryFrom,
+    fmt, fs,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 
-use crate::{mapper::RW, mapper_000::Mapper000, mapper_004::Mapper004};
+use crate::{
+    archive, fds,
+    mapper::RW,
+    mapper_000::Mapper000,
+    mapper_004::Mapper004,
+    mapper_020::Mapper020,
+    mapper_nsf::MapperNsf,
+    nsf::{self, Nsf},
+    patch::{self, PatchError},
+    region::Region,
+};
+
+// Famicom Disk System BIOS, looked up next to the disk image and then in the working directory
+const FDS_BIOS: &str = "disksys.rom";
 
 pub struct Cartridge {
     prg_memory: Vec<u8>,
     chr_memory: Vec<u8>,
     pub prg_banks: u8,
     pub chr_banks: u8,
-    pub image_valid: bool,
+    pub battery: bool,
     pub hw_mirror: Mirror,
+    pub rom_name: String,
+    save_path: PathBuf,
     mapper: Rc<RefCell<dyn RW>>,
+    disk_drive: Option<Rc<RefCell<Mapper020>>>,
+    nsf: Option<Nsf>,
+    // Timing the image asks for, None when it doesn't say
+    region: Option<Region>,
 }
 
 #[repr(C)]
@@ -24,7 +48,9 @@ struct INesHeader {
     prg_ram_size: u8,
     tv_system1: u8,
     tv_system2: u8,
-    unused: [u8; 5],
+    chr_ram_size: u8,
+    timing: u8,
+    unused: [u8; 3],
 }
 
 #[derive(Clone, PartialEq)]
@@ -36,14 +62,179 @@ pub enum Mirror {
     OneScreenHi,
 }
 
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    Archive(zip::result::ZipError),
+    NoRomInArchive,
+    Patch(PathBuf, PatchError),
+    UnknownFormat,
+    MissingChunk(&'static str),
+    BadUnif(&'static str),
+    UnknownBoard(String),
+    UnsupportedMapper(u16),
+    MissingBios,
+    BadNsf(&'static str),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "failed to read image: {}", e),
+            CartridgeError::Archive(e) => write!(f, "failed to read archive: {}", e),
+            CartridgeError::NoRomInArchive => {
+                write!(f, "archive has no matching .nes/.unf/.fds/.nsf entry")
+            }
+            CartridgeError::Patch(path, e) => write!(f, "{}: {}", path.display(), e),
+            CartridgeError::UnknownFormat => write!(f, "not an iNES, UNIF, FDS or NSF image"),
+            CartridgeError::MissingChunk(id) => write!(f, "UNIF image has no {} chunk", id),
+            CartridgeError::BadUnif(reason) => write!(f, "invalid UNIF: {}", reason),
+            CartridgeError::UnknownBoard(board) => {
+                write!(f, "UNIF board \"{}\" is not supported", board)
+            }
+            CartridgeError::UnsupportedMapper(id) => write!(f, "mapper {} not yet implemented", id),
+            CartridgeError::MissingBios => write!(
+                f,
+                "FDS images need the disk system BIOS, place {} next to the image",
+                FDS_BIOS
+            ),
+            CartridgeError::BadNsf(reason) => write!(f, "invalid NSF: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for CartridgeError {
+    fn from(e: zip::result::ZipError) -> Self {
+        CartridgeError::Archive(e)
+    }
+}
+
+// UNIF board names carry a vendor prefix that does not affect the hardware
+const UNIF_BOARD_PREFIXES: [&str; 5] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-"];
+
+fn unif_board_mapper(board: &str) -> Option<u16> {
+    let name = UNIF_BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+
+    match name {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => Some(0),
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TL1ROM" | "TNROM"
+        | "TR1ROM" | "TSROM" | "TVROM" | "HKROM" => Some(4),
+        _ => None,
+    }
+}
+
+fn create_mapper(
+    mapper_id: u16,
+    prg_banks: u8,
+    chr_banks: u8,
+) -> Result<Rc<RefCell<dyn RW>>, CartridgeError> {
+    match mapper_id {
+        0 => Ok(Rc::new(RefCell::new(Mapper000::new(prg_banks, chr_banks)))),
+        4 => Ok(Rc::new(RefCell::new(Mapper004::new(prg_banks, chr_banks)))),
+        _ => Err(CartridgeError::UnsupportedMapper(mapper_id)),
+    }
+}
+
+// Mirrors undersized memory (e.g. 8KB PRG boards) up to a whole number of banks
+fn fill_banks(memory: &mut Vec<u8>, bank_size: usize) -> Option<u8> {
+    let banks = u8::try_from(memory.len().div_ceil(bank_size)).ok()?;
+    let len = memory.len();
+
+    for i in len..(banks as usize * bank_size) {
+        memory.push(memory[i % len]);
+    }
+
+    Some(banks)
+}
+
 impl Cartridge {
-    pub fn new(file_name: &str) -> Self {
+    // Loads a plain image or one packed in a .zip/.gz archive. `entry` picks a file inside a
+    // zip, otherwise its first ROM is used. Saves live next to the file on disk but are named
+    // after the ROM itself, so "roms/smb3.zip" keeps "roms/Super Mario Bros. 3.sav".
+    //
+    // `patches` are applied in order before the header is parsed. Without any, an .ips, .bps
+    // or .ups named after the file or the ROM inside it is picked up automatically.
+    //
+    // Disk images keep their .sav as an IPS patch of everything written to the disks.
+    pub fn open(
+        file_name: &str,
+        entry: Option<&str>,
+        patches: &[String],
+    ) -> Result<Self, CartridgeError> {
         let file_path = Path::new(file_name);
-        let mut file = match File::open(&file_path) {
-            Ok(file) => file,
-            Err(e) => panic!("Failed to open file: {}", e),
+        let (rom_name, mut data) = archive::read_rom(file_path, entry)?;
+
+        let patch_paths: Vec<PathBuf> = if patches.is_empty() {
+            Cartridge::find_patch(file_path, &rom_name)
+                .into_iter()
+                .collect()
+        } else {
+            patches.iter().map(PathBuf::from).collect()
+        };
+
+        for patch_path in patch_paths {
+            let patch_data = fs::read(&patch_path)?;
+            data = patch::apply(&patch_data, &data)
+                .map_err(|e| CartridgeError::Patch(patch_path.clone(), e))?;
+        }
+
+        let bios_dir = file_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut cart = Cartridge::load(&rom_name, data, bios_dir)?;
+        cart.save_path = file_path.with_file_name(&rom_name).with_extension("sav");
+
+        if cart.battery || cart.disk_drive.is_some() {
+            cart.load_save()?;
+        }
+
+        Ok(cart)
+    }
+
+    fn find_patch(file_path: &Path, rom_name: &str) -> Option<PathBuf> {
+        let rom_path = file_path.with_file_name(rom_name);
+
+        ["ips", "bps", "ups"].iter().find_map(|ext| {
+            [file_path.with_extension(ext), rom_path.with_extension(ext)]
+                .iter()
+                .find(|path| path.is_file())
+                .cloned()
+        })
+    }
+
+    pub fn from_bytes(rom_name: &str, data: Vec<u8>) -> Result<Self, CartridgeError> {
+        Cartridge::load(rom_name, data, Path::new(""))
+    }
+
+    fn load(rom_name: &str, data: Vec<u8>, bios_dir: &Path) -> Result<Self, CartridgeError> {
+        let mut reader = Cursor::new(data);
+
+        let mut cart = match reader.get_ref().get(0..4) {
+            Some(b"NES\x1A") => Cartridge::load_ines(&mut reader)?,
+            Some(b"UNIF") => Cartridge::load_unif(&mut reader)?,
+            _ if fds::is_disk_image(reader.get_ref()) => {
+                Cartridge::load_fds(reader.into_inner(), bios_dir)?
+            }
+            _ if nsf::is_nsf(reader.get_ref()) => Cartridge::load_nsf(reader.get_ref())?,
+            _ => return Err(CartridgeError::UnknownFormat),
         };
 
+        cart.rom_name = rom_name.to_string();
+        cart.save_path = Path::new(rom_name).with_extension("sav");
+
+        Ok(cart)
+    }
+
+    fn load_ines<R: Read + Seek>(file: &mut R) -> Result<Self, CartridgeError> {
         let mut header = INesHeader {
             name: [0; 4],
             prg_rom_chunks: 0,
@@ -53,26 +244,30 @@ impl Cartridge {
             prg_ram_size: 0,
             tv_system1: 0,
             tv_system2: 0,
-            unused: [0; 5],
+            chr_ram_size: 0,
+            timing: 0,
+            unused: [0; 3],
         };
 
         let header_size = std::mem::size_of::<INesHeader>();
         unsafe {
             let header_slice =
                 std::slice::from_raw_parts_mut(&mut header as *mut _ as *mut u8, header_size);
-            file.read_exact(header_slice).unwrap();
+            file.read_exact(header_slice)?;
         }
 
         if (header.mapper1 & 0x04) > 0 {
-            file.seek(SeekFrom::Current(512)).unwrap();
+            file.seek(SeekFrom::Current(512))?;
         }
 
-        let mapper_id = header.mapper2.wrapping_shr(4).wrapping_shl(4) | header.mapper1.wrapping_shr(4);
+        let mapper_id = (header.mapper2.wrapping_shr(4).wrapping_shl(4)
+            | header.mapper1.wrapping_shr(4)) as u16;
         let hw_mirror = if (header.mapper1 & 0x01) > 0 {
             Mirror::Vertical
         } else {
             Mirror::Horizontal
         };
+        let battery = (header.mapper1 & 0x02) > 0;
 
         let mut file_type = 1;
 
@@ -101,13 +296,14 @@ impl Cartridge {
                 }
             }
             2 => {
-                prg_banks = ((header.prg_ram_size & 0x07).wrapping_shl(8) | header.prg_rom_chunks) as u8;
+                prg_banks = (header.prg_ram_size & 0x07).wrapping_shl(8) | header.prg_rom_chunks;
                 prg_memory.resize((prg_banks as usize) * (16 * 1024), 0);
                 if let Err(error) = file.read(&mut prg_memory) {
                     println!("{:?}", error);
                 }
 
-                chr_banks = ((header.prg_ram_size & 0x38).wrapping_shr(3).wrapping_shl(8) | header.chr_rom_chunks) as u8;
+                chr_banks = (header.prg_ram_size & 0x38).wrapping_shr(3).wrapping_shl(8)
+                    | header.chr_rom_chunks;
                 chr_memory.resize((chr_banks as usize).max(1) * (8 * 1024), 0);
                 if let Err(error) = file.read(&mut chr_memory) {
                     println!("{:?}", error);
@@ -116,29 +312,199 @@ impl Cartridge {
             _ => {}
         }
 
-        let mut mapper: Rc<RefCell<dyn RW>> = Rc::new(RefCell::new(Mapper000::new(0, 0)));
+        // NES 2.0 has a timing byte. iNES only has a rarely set PAL bit, which is trusted when
+        // the trailing bytes are clean rather than left over from a ripper's signature.
+        let region = if file_type == 2 {
+            match header.timing & 0x03 {
+                0x01 => Some(Region::Pal),
+                0x03 => Some(Region::Dendy),
+                _ => Some(Region::Ntsc),
+            }
+        } else if (header.tv_system1 & 0x01) > 0
+            && header.chr_ram_size == 0
+            && header.timing == 0
+            && header.unused == [0; 3]
+        {
+            Some(Region::Pal)
+        } else {
+            None
+        };
 
-        match mapper_id {
-            0 => {
-                mapper = Rc::new(RefCell::new(Mapper000::new(prg_banks, chr_banks)));
+        let mapper = create_mapper(mapper_id, prg_banks, chr_banks)?;
+
+        Ok(Cartridge {
+            prg_memory,
+            chr_memory,
+            prg_banks,
+            chr_banks,
+            battery,
+            hw_mirror,
+            rom_name: String::new(),
+            save_path: PathBuf::new(),
+            mapper,
+            disk_drive: None,
+            nsf: None,
+            region,
+        })
+    }
+
+    fn load_unif<R: Read + Seek>(file: &mut R) -> Result<Self, CartridgeError> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        // 32 byte header: "UNIF", revision, then reserved padding
+        file.seek(SeekFrom::Start(32))?;
+
+        let mut board: Option<String> = None;
+        let mut prg_chunks: [Vec<u8>; 16] = Default::default();
+        let mut chr_chunks: [Vec<u8>; 16] = Default::default();
+        let mut hw_mirror = Mirror::Horizontal;
+        let mut battery = false;
+
+        loop {
+            let mut chunk_header = [0_u8; 8];
+            match file.read_exact(&mut chunk_header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
             }
-            4 => {
-                mapper = Rc::new(RefCell::new(Mapper004::new(prg_banks, chr_banks)));
+
+            let length = u32::from_le_bytes([
+                chunk_header[4],
+                chunk_header[5],
+                chunk_header[6],
+                chunk_header[7],
+            ]);
+            // Checked before allocating, a corrupt length could ask for gigabytes
+            if length as u64 > file_len.saturating_sub(file.stream_position()?) {
+                return Err(CartridgeError::BadUnif("chunk is truncated"));
             }
-            _ => {
-                println!("Mapper {} not yet implemented", mapper_id);
+            let mut data = vec![0_u8; length as usize];
+            file.read_exact(&mut data)?;
+
+            match &chunk_header[0..4] {
+                b"MAPR" => {
+                    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                    board = Some(String::from_utf8_lossy(&data[..end]).trim().to_string());
+                }
+                b"MIRR" => {
+                    hw_mirror = match data.first() {
+                        Some(1) => Mirror::Vertical,
+                        Some(2) => Mirror::OneScreenLo,
+                        Some(3) => Mirror::OneScreenHi,
+                        _ => Mirror::Horizontal,
+                    };
+                }
+                b"BATR" => {
+                    battery = data.first().is_some_and(|&b| b != 0);
+                }
+                [b'P', b'R', b'G', n] => {
+                    if let Some(i) = (*n as char).to_digit(16) {
+                        prg_chunks[i as usize] = data;
+                    }
+                }
+                [b'C', b'H', b'R', n] => {
+                    if let Some(i) = (*n as char).to_digit(16) {
+                        chr_chunks[i as usize] = data;
+                    }
+                }
+                _ => {}
             }
         }
 
-        Cartridge {
+        let board = board.ok_or(CartridgeError::MissingChunk("MAPR"))?;
+        let mapper_id =
+            unif_board_mapper(&board).ok_or_else(|| CartridgeError::UnknownBoard(board.clone()))?;
+
+        let mut prg_memory: Vec<u8> = prg_chunks.concat();
+        if prg_memory.is_empty() {
+            return Err(CartridgeError::MissingChunk("PRG0"));
+        }
+        let prg_banks = fill_banks(&mut prg_memory, 16 * 1024)
+            .ok_or(CartridgeError::BadUnif("more than 255 PRG banks"))?;
+
+        let mut chr_memory: Vec<u8> = chr_chunks.concat();
+        let chr_banks = if chr_memory.is_empty() {
+            chr_memory.resize(8 * 1024, 0);
+            0
+        } else {
+            fill_banks(&mut chr_memory, 8 * 1024)
+                .ok_or(CartridgeError::BadUnif("more than 255 CHR banks"))?
+        };
+
+        let mapper = create_mapper(mapper_id, prg_banks, chr_banks)?;
+
+        Ok(Cartridge {
             prg_memory,
             chr_memory,
             prg_banks,
             chr_banks,
-            image_valid: true,
+            battery,
             hw_mirror,
+            rom_name: String::new(),
+            save_path: PathBuf::new(),
             mapper,
+            disk_drive: None,
+            nsf: None,
+            region: None,
+        })
+    }
+
+    fn load_fds(image: Vec<u8>, bios_dir: &Path) -> Result<Self, CartridgeError> {
+        let bios_path = [bios_dir.join(FDS_BIOS), PathBuf::from(FDS_BIOS)]
+            .iter()
+            .find(|path| path.is_file())
+            .cloned()
+            .ok_or(CartridgeError::MissingBios)?;
+        let bios = fs::read(bios_path)?;
+        if bios.len() < 8 * 1024 {
+            return Err(CartridgeError::MissingBios);
         }
+
+        // 32KB of RAM followed by the last 8KB of the BIOS
+        let mut prg_memory = vec![0_u8; 32 * 1024];
+        prg_memory.extend_from_slice(&bios[bios.len() - 8 * 1024..]);
+
+        let disk_drive = Rc::new(RefCell::new(Mapper020::new(image)));
+
+        Ok(Cartridge {
+            prg_memory,
+            chr_memory: vec![0_u8; 8 * 1024],
+            prg_banks: 0,
+            chr_banks: 0,
+            battery: false,
+            hw_mirror: Mirror::Horizontal,
+            rom_name: String::new(),
+            save_path: PathBuf::new(),
+            mapper: disk_drive.clone(),
+            disk_drive: Some(disk_drive),
+            nsf: None,
+            region: Some(Region::Ntsc),
+        })
+    }
+
+    // Music rips get a synthetic board holding the tune, see `NsfPlayer` for playback
+    fn load_nsf(data: &[u8]) -> Result<Self, CartridgeError> {
+        let nsf = Nsf::parse(data)?;
+        let (prg_memory, banks) = nsf.prg_image();
+        let mapper = Rc::new(RefCell::new(MapperNsf::new(
+            banks,
+            prg_memory.len() / 0x1000,
+            nsf.chips,
+        )));
+
+        Ok(Cartridge {
+            prg_memory,
+            chr_memory: vec![0_u8; 8 * 1024],
+            prg_banks: 0,
+            chr_banks: 0,
+            battery: false,
+            hw_mirror: Mirror::Horizontal,
+            rom_name: String::new(),
+            save_path: PathBuf::new(),
+            mapper,
+            disk_drive: None,
+            region: Some(if nsf.pal { Region::Pal } else { Region::Ntsc }),
+            nsf: Some(nsf),
+        })
     }
 
     pub fn cpu_write(&mut self, addr: u16, data: u8) -> bool {
@@ -156,9 +522,17 @@ impl Cartridge {
         }
     }
 
-    pub fn cpu_read(&self, addr: u16, data: &mut u8) -> bool {
+    // With `read_only` set, registers that change when read are left as they are
+    pub fn cpu_read(&self, addr: u16, data: &mut u8, read_only: bool) -> bool {
         let mut mapped_addr: u32 = 0;
-        if self.mapper.borrow().cpu_map_read(addr, &mut mapped_addr, data) {
+        let mapper = self.mapper.borrow();
+        let mapped = if read_only {
+            mapper.cpu_map_peek(addr, &mut mapped_addr, data)
+        } else {
+            mapper.cpu_map_read(addr, &mut mapped_addr, data)
+        };
+
+        if mapped {
             if mapped_addr == 0xFFFFFFFF{
                 return true;
             } else {
@@ -208,7 +582,163 @@ impl Cartridge {
         }
     }
 
+    pub fn save_path(&self) -> &Path {
+        &self.save_path
+    }
+
+    fn load_save(&mut self) -> Result<(), CartridgeError> {
+        let data = match fs::read(&self.save_path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(drive) = &self.disk_drive {
+            drive
+                .borrow_mut()
+                .load_changes(&data)
+                .map_err(|e| CartridgeError::Patch(self.save_path.clone(), e))?;
+        } else if let Some(ram) = self.mapper.borrow_mut().prg_ram() {
+            let len = ram.len().min(data.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+
+        Ok(())
+    }
+
+    // Writes battery RAM, or the disk changes of an FDS image
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(drive) = &self.disk_drive {
+            return match drive.borrow().changes() {
+                Some(diff) => fs::write(&self.save_path, diff),
+                None => Ok(()),
+            };
+        }
+
+        if !self.battery {
+            return Ok(());
+        }
+
+        match self.mapper.borrow_mut().prg_ram() {
+            Some(ram) => fs::write(&self.save_path, ram),
+            None => Ok(()),
+        }
+    }
+
+    // Side in the drive and the number of sides, for disk images
+    pub fn disk(&self) -> Option<(Option<usize>, usize)> {
+        self.disk_drive
+            .as_ref()
+            .map(|drive| (drive.borrow().side(), drive.borrow().sides()))
+    }
+
+    pub fn switch_disk_side(&self) {
+        if let Some(drive) = &self.disk_drive {
+            drive.borrow_mut().switch_side();
+        }
+    }
+
+    pub fn eject_disk(&self) {
+        if let Some(drive) = &self.disk_drive {
+            drive.borrow_mut().eject();
+        }
+    }
+
+    pub fn nsf(&self) -> Option<&Nsf> {
+        self.nsf.as_ref()
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
     pub fn get_mapper(&self) -> Rc<RefCell<dyn RW>> {
         self.mapper.clone()
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    // nestest.nes from the crate root, an NROM cartridge with CHR ROM
+    pub fn nestest() -> Cartridge {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let rom = fs::read(dir.join("nestest.nes")).unwrap();
+        Cartridge::from_bytes("nestest.nes", rom).unwrap()
+    }
+
+    fn unif(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut image = b"UNIF".to_vec();
+        image.extend_from_slice(&7_u32.to_le_bytes());
+        image.resize(32, 0);
+        for (id, data) in chunks {
+            image.extend_from_slice(*id);
+            image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            image.extend_from_slice(data);
+        }
+        image
+    }
+
+    #[test]
+    fn unif_boards() {
+        assert_eq!(unif_board_mapper("NES-NROM-256"), Some(0));
+        assert_eq!(unif_board_mapper("HVC-TLROM"), Some(4));
+        assert_eq!(unif_board_mapper("TSROM"), Some(4));
+        assert_eq!(unif_board_mapper("NES-SNROM"), None);
+
+        // An 8KB PRG chunk is mirrored up to a 16KB bank
+        let prg: Vec<u8> = (0..8 * 1024).map(|i| i as u8).collect();
+        let image = unif(&[
+            (b"MAPR", b"NES-NROM-128\0".to_vec()),
+            (b"MIRR", vec![1]),
+            (b"BATR", vec![1]),
+            (b"PRG0", prg),
+            (b"CHR0", vec![0x55; 8 * 1024]),
+        ]);
+        let cart = Cartridge::from_bytes("test.unf", image).unwrap();
+        assert_eq!((cart.prg_banks, cart.chr_banks), (1, 1));
+        assert!(cart.battery);
+        assert!(cart.mirror() == Mirror::Vertical);
+
+        let mut data = 0;
+        assert!(cart.cpu_read(0xA001, &mut data, false));
+        assert_eq!(data, 0x01);
+        assert!(cart.ppu_read(0x0010, &mut data));
+        assert_eq!(data, 0x55);
+    }
+
+    #[test]
+    fn unif_errors() {
+        let prg = || (b"PRG0", vec![0; 16 * 1024]);
+        let load = |image| Cartridge::from_bytes("test.unf", image).err().unwrap();
+
+        assert!(matches!(
+            load(unif(&[prg()])),
+            CartridgeError::MissingChunk("MAPR")
+        ));
+        assert!(matches!(
+            load(unif(&[(b"MAPR", b"NES-SNROM".to_vec()), prg()])),
+            CartridgeError::UnknownBoard(board) if board == "NES-SNROM"
+        ));
+
+        // A length past the end of the file is refused rather than allocated
+        let mut image = unif(&[(b"MAPR", b"NES-NROM".to_vec()), prg()]);
+        image.extend_from_slice(b"CHR0\xFF\xFF\xFF\xFF");
+        assert!(matches!(
+            load(image),
+            CartridgeError::BadUnif("chunk is truncated")
+        ));
+
+        // Bank counts have to fit the mapper's u8
+        let image = unif(&[
+            (b"MAPR", b"NES-NROM".to_vec()),
+            (b"PRG0", vec![0; 256 * 16 * 1024]),
+        ]);
+        assert!(matches!(
+            load(image),
+            CartridgeError::BadUnif("more than 255 PRG banks")
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    ntsc::Preset,
+    palette::NtscSettings,
+    region::Region,
+    scaler::{Overscan, Pipeline, Scaler},
+};
+use std::fs;
+
+pub const USAGE: &str =
+    "usage: nes_emulator [ROM] [--entry NAME] [--patch FILE]... [--trace FILE] [--break SPEC]... \
+     [--gdb PORT] [--script FILE] [--region ntsc|pal|dendy] [--no-sprite-limit] [--palette FILE|ntsc] \
+     [--hue DEGREES] [--saturation N] [--contrast N] [--gamma N] \
+     [--ntsc composite|svideo|rgb] [--scaler NAME] [--overscan TOP,BOTTOM,LEFT,RIGHT] \
+     [--aspect] [--config FILE]";
+
+pub enum PaletteSource {
+    Builtin,
+    // A .pal file
+    File(String),
+    Ntsc(NtscSettings),
+}
+
+pub struct Config {
+    pub rom: String,
+    pub entry: Option<String>,
+    pub patches: Vec<String>,
+    // nestest.log style CPU trace
+    pub trace: Option<String>,
+    // Debugger::add syntax, such as "C000", "w:0300 if A == #$10" or "nmi"
+    pub breakpoints: Vec<String>,
+    // Serve a remote debugger on this port instead of opening the window
+    pub gdb: Option<u16>,
+    // Run these debugger commands instead of opening the window, see script.rs
+    pub script: Option<String>,
+    // Overrides the timing the image asks for
+    pub region: Option<Region>,
+    // Draw more than eight sprites a line, to cut down on flicker
+    pub sprite_limit: bool,
+    pub palette: PaletteSource,
+    // Run the picture through the NTSC filter, which shares the palette's picture controls
+    pub ntsc: Option<Preset>,
+    // Cropping, scaling and aspect correction for the window and screenshots
+    pub display: Pipeline,
+}
+
+impl Config {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut config = Config {
+            rom: String::from("nestest.nes"),
+            entry: None,
+            patches: Vec::new(),
+            trace: None,
+            breakpoints: Vec::new(),
+            gdb: None,
+            script: None,
+            region: None,
+            sprite_limit: true,
+            palette: PaletteSource::Builtin,
+            ntsc: None,
+            display: Pipeline::default(),
+        };
+        config.parse(&mut args)?;
+
+        Ok(config)
+    }
+
+    // A config file holds the same options as the command line, spread over as many lines as
+    // suits. Lines starting with # are comments.
+    fn parse_file(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut args = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .flat_map(str::split_whitespace)
+            .map(String::from);
+
+        self.parse(&mut args)
+    }
+
+    // The picture controls switch to the generated palette
+    fn ntsc_settings(&mut self) -> &mut NtscSettings {
+        if !matches!(self.palette, PaletteSource::Ntsc(_)) {
+            self.palette = PaletteSource::Ntsc(NtscSettings::default());
+        }
+
+        match &mut self.palette {
+            PaletteSource::Ntsc(settings) => settings,
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse<I: Iterator<Item = String>>(&mut self, args: &mut I) -> Result<(), String> {
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", name))
+            };
+            let mut number = |name: &str| {
+                let text = value(name)?;
+                text.parse::<f32>()
+                    .map_err(|_| format!("bad number {} for {}", text, name))
+            };
+
+            match arg.as_str() {
+                "--entry" => self.entry = Some(value("--entry")?),
+                "--patch" => self.patches.push(value("--patch")?),
+                "--trace" => self.trace = Some(value("--trace")?),
+                "--break" => self.breakpoints.push(value("--break")?),
+                "--gdb" => {
+                    let port = value("--gdb")?;
+                    self.gdb = Some(port.parse().map_err(|_| format!("bad port {}", port))?);
+                }
+                "--script" => self.script = Some(value("--script")?),
+                "--region" => {
+                    let name = value("--region")?;
+                    self.region = Some(
+                        Region::parse(&name).ok_or_else(|| format!("unknown region {}", name))?,
+                    );
+                }
+                "--no-sprite-limit" => self.sprite_limit = false,
+                "--palette" => match value("--palette")?.as_str() {
+                    "ntsc" => self.palette = PaletteSource::Ntsc(NtscSettings::default()),
+                    path => self.palette = PaletteSource::File(path.to_string()),
+                },
+                "--hue" => self.ntsc_settings().hue = number("--hue")?,
+                "--saturation" => self.ntsc_settings().saturation = number("--saturation")?,
+                "--contrast" => self.ntsc_settings().contrast = number("--contrast")?,
+                "--gamma" => self.ntsc_settings().gamma = number("--gamma")?,
+                "--ntsc" => {
+                    let name = value("--ntsc")?;
+                    self.ntsc = Some(
+                        Preset::parse(&name).ok_or_else(|| format!("unknown preset {}", name))?,
+                    );
+                }
+                "--scaler" => {
+                    let name = value("--scaler")?;
+                    self.display.scaler =
+                        Scaler::parse(&name).ok_or_else(|| format!("unknown scaler {}", name))?;
+                }
+                "--overscan" => {
+                    let spec = value("--overscan")?;
+                    self.display.overscan =
+                        Overscan::parse(&spec).ok_or_else(|| format!("bad overscan {}", spec))?;
+                }
+                "--aspect" => self.display.aspect = true,
+                "--config" => self.parse_file(&value("--config")?)?,
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => self.rom = arg,
+            }
+        }
+
+        Ok(())
+    }
+}
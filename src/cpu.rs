@@ -1,14 +1,6 @@
-use macroquad::prelude::*;
 use std::collections::BTreeMap;
-
-use crate::{Bus, Cartridge, Ppu};
-
-const CYAN: macroquad::color::Color = Color {
-    r: 0.0,
-    g: 255.0,
-    b: 255.0,
-    a: 1.0,
-};
+use std::fmt;
+use std::io::Write;
 
 #[repr(u8)]
 enum Flags {
@@ -22,27 +14,165 @@ enum Flags {
     N = 1 << 7, // Negative
 }
 
+// The 2A03 in the NES is an NMOS 6502 with the decimal mode circuitry left out
+#[derive(Clone, Copy, PartialEq)]
+pub enum CpuModel {
+    Ricoh2A03,
+    Nmos6502,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddrMode {
+    Imp,
+    // ASL, LSR, ROL and ROR on the accumulator
+    Acc,
+    Imm,
+    Zp0,
+    Zpx,
+    Zpy,
+    Rel,
+    Abs,
+    Abx,
+    Aby,
+    Ind,
+    Izx,
+    Izy,
+}
+
+// How an instruction uses its operand, which decides the bus accesses on each cycle. The
+// operation itself works on `fetched`, reading the operand from it and leaving the value to
+// write back in it.
+#[derive(Clone, Copy)]
+enum Operation {
+    // Registers only, after a dummy read of the next byte
+    Implied(fn(&mut Cpu)),
+    Read(fn(&mut Cpu)),
+    Write(fn(&mut Cpu)),
+    // Reads, writes the unmodified value back, then writes the result. With implied
+    // addressing it works on the accumulator instead.
+    ReadModifyWrite(fn(&mut Cpu)),
+    // Branches, jumps and stack instructions, which have their own sequences
+    Control(fn(&mut Cpu)),
+}
+
+impl AddrMode {
+    // Instruction length in bytes, opcode included
+    pub fn length(self) -> u16 {
+        match self {
+            AddrMode::Imp | AddrMode::Acc => 1,
+            AddrMode::Imm
+            | AddrMode::Zp0
+            | AddrMode::Zpx
+            | AddrMode::Zpy
+            | AddrMode::Rel
+            | AddrMode::Izx
+            | AddrMode::Izy => 2,
+            AddrMode::Abs | AddrMode::Abx | AddrMode::Aby | AddrMode::Ind => 3,
+        }
+    }
+}
+
+// An instruction as found in memory, decoded without executing it
+pub struct DecodedInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub mode: AddrMode,
+    pub unofficial: bool,
+    // Operand byte or word as encoded
+    pub operand: u16,
+    // Memory the instruction reads or writes, after indexing and indirection
+    pub effective_address: Option<u16>,
+    // Where branches, JMP and JSR go
+    pub branch_target: Option<u16>,
+}
+
+impl fmt::Display for DecodedInstruction {
+    // Assembler syntax, e.g. "LDA ($12),Y" or "BNE $C0F3". Absolute operands below $0100 get
+    // ca65's "a:" prefix so they don't read as zero page.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let absolute = if self.operand < 0x0100 { "a:" } else { "" };
+
+        match self.mode {
+            AddrMode::Imp => write!(f, "{}", self.mnemonic),
+            AddrMode::Acc => write!(f, "{} A", self.mnemonic),
+            AddrMode::Imm => write!(f, "{} #${:02X}", self.mnemonic, self.operand),
+            AddrMode::Zp0 => write!(f, "{} ${:02X}", self.mnemonic, self.operand),
+            AddrMode::Zpx => write!(f, "{} ${:02X},X", self.mnemonic, self.operand),
+            AddrMode::Zpy => write!(f, "{} ${:02X},Y", self.mnemonic, self.operand),
+            AddrMode::Rel => write!(
+                f,
+                "{} ${:04X}",
+                self.mnemonic,
+                self.branch_target.unwrap_or_default()
+            ),
+            AddrMode::Abs => write!(f, "{} {}${:04X}", self.mnemonic, absolute, self.operand),
+            AddrMode::Abx => write!(f, "{} {}${:04X},X", self.mnemonic, absolute, self.operand),
+            AddrMode::Aby => write!(f, "{} {}${:04X},Y", self.mnemonic, absolute, self.operand),
+            AddrMode::Ind => write!(f, "{} (${:04X})", self.mnemonic, self.operand),
+            AddrMode::Izx => write!(f, "{} (${:02X},X)", self.mnemonic, self.operand),
+            AddrMode::Izy => write!(f, "{} (${:02X}),Y", self.mnemonic, self.operand),
+        }
+    }
+}
+
 struct Instruction {
     name: String,
-    operate: fn(&mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8,
-    addrmode: fn(&mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8,
-    cycles: u8,
+    operate: Operation,
+    addrmode: AddrMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interrupt {
+    Reset,
+    Nmi,
+    Irq,
+}
+
+// Everything the CPU reaches through its address and data pins
+pub trait CpuBus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    // Reads without the side effects of a real access, for tracing and debugging
+    fn peek(&mut self, addr: u16) -> u8;
+
+    // Scanline and dot the PPU last drew, when there is one, for the trace log and debugger
+    fn ppu_position(&self) -> Option<(i16, i16)> {
+        None
+    }
 }
 
 pub struct Cpu {
-    a: u8,       // Accumulator Register
-    x: u8,       //X register
-    y: u8,       // Y register,
+    pub a: u8,   // Accumulator Register
+    pub x: u8,   //X register
+    pub y: u8,   // Y register,
     stkp: u8,    // Stack Pointer (points to location on bus)
     pub pc: u16, // Program counter
     status: u8,  // Status Register
+    model: CpuModel,
     fetched: u8,
     addr_abs: u16,
-    addr_rel: u16,
+    // Zero page pointer, or the address before indexing
+    base: u16,
     opcode: u8,
-    cycles: u8,
+    // Cycles of the current instruction done so far, 0 between instructions
+    step: u8,
+    branch_taken: bool,
+    interrupt: Option<Interrupt>,
+    // Interrupt lines as driven by the rest of the system
+    nmi_line: bool,
+    irq_line: bool,
+    // NMI edge detector
+    nmi_previous: bool,
+    nmi_pending: bool,
+    // IRQ line while the I flag is clear
+    irq_pending: bool,
+    // Values of the two above at the end of the previous cycle
+    nmi_polled: bool,
+    irq_polled: bool,
     clock_count: u32,
     lookup: Vec<Instruction>,
+    trace: Option<Box<dyn Write>>,
 }
 impl Cpu {
     pub fn new() -> Self {
@@ -53,1566 +183,1314 @@ impl Cpu {
             stkp: 0x00,
             pc: 0x0000,
             status: 0x00,
+            model: CpuModel::Ricoh2A03,
             fetched: 0x00,
             addr_abs: 0x0000,
-            addr_rel: 0x0000,
+            base: 0x0000,
             opcode: 0x00,
-            cycles: 0,
+            step: 0,
+            branch_taken: false,
+            interrupt: None,
+            nmi_line: false,
+            irq_line: false,
+            nmi_previous: false,
+            nmi_pending: false,
+            irq_pending: false,
+            nmi_polled: false,
+            irq_polled: false,
             clock_count: 0,
+            trace: None,
             lookup: vec![
                 Instruction {
                     name: String::from("BRK"),
-                    operate: Cpu::brk,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    operate: Operation::Control(Cpu::brk),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("ASL"),
-                    operate: Cpu::asl,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::asl),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("PHP"),
-                    operate: Cpu::php,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    operate: Operation::Control(Cpu::php),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("ASL"),
-                    operate: Cpu::asl,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::ReadModifyWrite(Cpu::asl),
+                    addrmode: AddrMode::Acc,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("ANC"),
+                    operate: Operation::Read(Cpu::anc),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("ASL"),
-                    operate: Cpu::asl,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::asl),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BPL"),
-                    operate: Cpu::bpl,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bpl),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("ASL"),
-                    operate: Cpu::asl,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::asl),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("CLC"),
-                    operate: Cpu::clc,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::clc),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("ORA"),
-                    operate: Cpu::ora,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ora),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("ASL"),
-                    operate: Cpu::asl,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::asl),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("SLO"),
+                    operate: Operation::ReadModifyWrite(Cpu::slo),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("JSR"),
-                    operate: Cpu::jsr,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::Control(Cpu::jsr),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("BIT"),
-                    operate: Cpu::bit,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::bit),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("ROL"),
-                    operate: Cpu::rol,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::rol),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("PLP"),
-                    operate: Cpu::plp,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    operate: Operation::Control(Cpu::plp),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("ROL"),
-                    operate: Cpu::rol,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::ReadModifyWrite(Cpu::rol),
+                    addrmode: AddrMode::Acc,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("ANC"),
+                    operate: Operation::Read(Cpu::anc),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("BIT"),
-                    operate: Cpu::bit,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::bit),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("ROL"),
-                    operate: Cpu::rol,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::rol),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BMI"),
-                    operate: Cpu::bmi,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bmi),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("ROL"),
-                    operate: Cpu::rol,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::rol),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("SEC"),
-                    operate: Cpu::sec,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::sec),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("AND"),
-                    operate: Cpu::and,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::and),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("ROL"),
-                    operate: Cpu::rol,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::rol),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("RLA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rla),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("RTI"),
-                    operate: Cpu::rti,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    operate: Operation::Control(Cpu::rti),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("LSR"),
-                    operate: Cpu::lsr,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::lsr),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("PHA"),
-                    operate: Cpu::pha,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    operate: Operation::Control(Cpu::pha),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("LSR"),
-                    operate: Cpu::lsr,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::ReadModifyWrite(Cpu::lsr),
+                    addrmode: AddrMode::Acc,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("ALR"),
+                    operate: Operation::Read(Cpu::alr),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("JMP"),
-                    operate: Cpu::jmp,
-                    addrmode: Cpu::abs,
-                    cycles: 3,
+                    operate: Operation::Control(Cpu::jmp),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("LSR"),
-                    operate: Cpu::lsr,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::lsr),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BVC"),
-                    operate: Cpu::bvc,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bvc),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("LSR"),
-                    operate: Cpu::lsr,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::lsr),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("CLI"),
-                    operate: Cpu::cli,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::cli),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("EOR"),
-                    operate: Cpu::eor,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::eor),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("LSR"),
-                    operate: Cpu::lsr,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::lsr),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("SRE"),
+                    operate: Operation::ReadModifyWrite(Cpu::sre),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("RTS"),
-                    operate: Cpu::rts,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    operate: Operation::Control(Cpu::rts),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("ROR"),
-                    operate: Cpu::ror,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::ror),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("PLA"),
-                    operate: Cpu::pla,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    operate: Operation::Control(Cpu::pla),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("ROR"),
-                    operate: Cpu::ror,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::ReadModifyWrite(Cpu::ror),
+                    addrmode: AddrMode::Acc,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("ARR"),
+                    operate: Operation::Read(Cpu::arr),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("JMP"),
-                    operate: Cpu::jmp,
-                    addrmode: Cpu::ind,
-                    cycles: 5,
+                    operate: Operation::Control(Cpu::jmp),
+                    addrmode: AddrMode::Ind,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("ROR"),
-                    operate: Cpu::ror,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::ror),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BVS"),
-                    operate: Cpu::bvs,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bvs),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("ROR"),
-                    operate: Cpu::ror,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::ror),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("SEI"),
-                    operate: Cpu::sei,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::sei),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("ADC"),
-                    operate: Cpu::adc,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::adc),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("ROR"),
-                    operate: Cpu::ror,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::ror),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("RRA"),
+                    operate: Operation::ReadModifyWrite(Cpu::rra),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SAX"),
+                    operate: Operation::Write(Cpu::sax),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("STY"),
-                    operate: Cpu::sty,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Write(Cpu::sty),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("STX"),
-                    operate: Cpu::stx,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Write(Cpu::stx),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    name: String::from("SAX"),
+                    operate: Operation::Write(Cpu::sax),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("DEY"),
-                    operate: Cpu::dey,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::dey),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("TXA"),
-                    operate: Cpu::txa,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::txa),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("XAA"),
+                    operate: Operation::Read(Cpu::xaa),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("STY"),
-                    operate: Cpu::sty,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::sty),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("STX"),
-                    operate: Cpu::stx,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::stx),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("SAX"),
+                    operate: Operation::Write(Cpu::sax),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BCC"),
-                    operate: Cpu::bcc,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bcc),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::izy,
-                    cycles: 6,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("SHA"),
+                    operate: Operation::Write(Cpu::sha),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
                     name: String::from("STY"),
-                    operate: Cpu::sty,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::sty),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("STX"),
-                    operate: Cpu::stx,
-                    addrmode: Cpu::zpy,
-                    cycles: 4,
+                    operate: Operation::Write(Cpu::stx),
+                    addrmode: AddrMode::Zpy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("SAX"),
+                    operate: Operation::Write(Cpu::sax),
+                    addrmode: AddrMode::Zpy,
                 },
                 Instruction {
                     name: String::from("TYA"),
-                    operate: Cpu::tya,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::tya),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::aby,
-                    cycles: 5,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("TXS"),
-                    operate: Cpu::txs,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::txs),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("TAS"),
+                    operate: Operation::Write(Cpu::tas),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("SHY"),
+                    operate: Operation::Write(Cpu::shy),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("STA"),
-                    operate: Cpu::sta,
-                    addrmode: Cpu::abx,
-                    cycles: 5,
+                    operate: Operation::Write(Cpu::sta),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("SHX"),
+                    operate: Operation::Write(Cpu::shx),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("SHA"),
+                    operate: Operation::Write(Cpu::sha),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("LDY"),
-                    operate: Cpu::ldy,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::ldy),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("LDX"),
-                    operate: Cpu::ldx,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::ldx),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("LDY"),
-                    operate: Cpu::ldy,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::ldy),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("LDX"),
-                    operate: Cpu::ldx,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::ldx),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 3,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("TAY"),
-                    operate: Cpu::tay,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::tay),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("TAX"),
-                    operate: Cpu::tax,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::tax),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("LXA"),
+                    operate: Operation::Read(Cpu::lxa),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("LDY"),
-                    operate: Cpu::ldy,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldy),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("LDX"),
-                    operate: Cpu::ldx,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldx),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BCS"),
-                    operate: Cpu::bcs,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bcs),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
                     name: String::from("LDY"),
-                    operate: Cpu::ldy,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldy),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("LDX"),
-                    operate: Cpu::ldx,
-                    addrmode: Cpu::zpy,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldx),
+                    addrmode: AddrMode::Zpy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Zpy,
                 },
                 Instruction {
                     name: String::from("CLV"),
-                    operate: Cpu::clv,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::clv),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("TSX"),
-                    operate: Cpu::tsx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::tsx),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("LAS"),
+                    operate: Operation::Read(Cpu::las),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("LDY"),
-                    operate: Cpu::ldy,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldy),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("LDA"),
-                    operate: Cpu::lda,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::lda),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("LDX"),
-                    operate: Cpu::ldx,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::ldx),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("LAX"),
+                    operate: Operation::Read(Cpu::lax),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("CPY"),
-                    operate: Cpu::cpy,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::cpy),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("CPY"),
-                    operate: Cpu::cpy,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::cpy),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("DEC"),
-                    operate: Cpu::dec,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::dec),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("INY"),
-                    operate: Cpu::iny,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::iny),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("DEX"),
-                    operate: Cpu::dex,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::dex),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("AXS"),
+                    operate: Operation::Read(Cpu::axs),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("CPY"),
-                    operate: Cpu::cpy,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cpy),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("DEC"),
-                    operate: Cpu::dec,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::dec),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BNE"),
-                    operate: Cpu::bne,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::bne),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("DEC"),
-                    operate: Cpu::dec,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::dec),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("CLD"),
-                    operate: Cpu::cld,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::cld),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("NOP"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("CMP"),
-                    operate: Cpu::cmp,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cmp),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("DEC"),
-                    operate: Cpu::dec,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::dec),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("DCP"),
+                    operate: Operation::ReadModifyWrite(Cpu::dcp),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("CPX"),
-                    operate: Cpu::cpx,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::cpx),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::izx,
-                    cycles: 6,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Izx,
                 },
                 Instruction {
                     name: String::from("CPX"),
-                    operate: Cpu::cpx,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::cpx),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::zp0,
-                    cycles: 3,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("INC"),
-                    operate: Cpu::inc,
-                    addrmode: Cpu::zp0,
-                    cycles: 5,
+                    operate: Operation::ReadModifyWrite(Cpu::inc),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 5,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Zp0,
                 },
                 Instruction {
                     name: String::from("INX"),
-                    operate: Cpu::inx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::inx),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::imm,
-                    cycles: 2,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("NOP"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("SBC"),
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Imm,
                 },
                 Instruction {
                     name: String::from("CPX"),
-                    operate: Cpu::cpx,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::cpx),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::abs,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("INC"),
-                    operate: Cpu::inc,
-                    addrmode: Cpu::abs,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::inc),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Abs,
                 },
                 Instruction {
                     name: String::from("BEQ"),
-                    operate: Cpu::beq,
-                    addrmode: Cpu::rel,
-                    cycles: 2,
+                    operate: Operation::Control(Cpu::beq),
+                    addrmode: AddrMode::Rel,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::izy,
-                    cycles: 5,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    name: String::from("JAM"),
+                    operate: Operation::Implied(Cpu::jam),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 8,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Izy,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::zpx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("INC"),
-                    operate: Cpu::inc,
-                    addrmode: Cpu::zpx,
-                    cycles: 6,
+                    operate: Operation::ReadModifyWrite(Cpu::inc),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 6,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Zpx,
                 },
                 Instruction {
                     name: String::from("SED"),
-                    operate: Cpu::sed,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::sed),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::aby,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
                     name: String::from("NOP"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 2,
+                    operate: Operation::Implied(Cpu::nop),
+                    addrmode: AddrMode::Imp,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Aby,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::nop,
-                    addrmode: Cpu::imp,
-                    cycles: 4,
+                    name: String::from("NOP"),
+                    operate: Operation::Read(Cpu::nop),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("SBC"),
-                    operate: Cpu::sbc,
-                    addrmode: Cpu::abx,
-                    cycles: 4,
+                    operate: Operation::Read(Cpu::sbc),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
                     name: String::from("INC"),
-                    operate: Cpu::inc,
-                    addrmode: Cpu::abx,
-                    cycles: 7,
+                    operate: Operation::ReadModifyWrite(Cpu::inc),
+                    addrmode: AddrMode::Abx,
                 },
                 Instruction {
-                    name: String::from("???"),
-                    operate: Cpu::xxx,
-                    addrmode: Cpu::imp,
-                    cycles: 7,
+                    name: String::from("ISB"),
+                    operate: Operation::ReadModifyWrite(Cpu::isb),
+                    addrmode: AddrMode::Abx,
                 },
             ],
         }
     }
 
-    fn write(
-        &mut self,
-        bus: &mut Bus,
-        ppu: &mut Ppu,
-        cart: &mut Cartridge,
-        addr: u16,
-        data: u8,
-    ) {
-        bus.cpu_write(ppu, cart, addr, data);
+    fn write(&mut self, bus: &mut impl CpuBus, addr: u16, data: u8) {
+        bus.write(addr, data);
     }
 
-    fn read(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge, addr: u16) -> u8 {
-        bus.cpu_read(ppu, cart, addr, false)
+    fn read(&mut self, bus: &mut impl CpuBus, addr: u16) -> u8 {
+        bus.read(addr)
     }
 
     fn get_flag(&self, flag: Flags) -> u8 {
@@ -1631,1177 +1509,1628 @@ impl Cpu {
         }
     }
 
-    // Addressing Modes
-
-    fn imp(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        self.fetched = self.a;
-        0
-    }
-
-    fn imm(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        self.addr_abs = self.pc;
-        self.pc = self.pc.wrapping_add(1);
-        0
-    }
+    // Instruction sequencing. Every call to `clock` is one CPU cycle with exactly one bus
+    // access: cycle 1 fetches the opcode and `execute` performs the rest, following the
+    // per-cycle tables in 6502_cpu.txt, dummy reads and writes included.
 
-    fn zp0(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.addr_abs = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        self.addr_abs &= 0x00FF;
-        0
+    fn push(&mut self, bus: &mut impl CpuBus, data: u8) {
+        self.write(bus, 0x0100 | self.stkp as u16, data);
+        self.stkp = self.stkp.wrapping_sub(1);
     }
 
-    fn zpx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.addr_abs = self.read(bus, ppu, cart, self.pc).wrapping_add(self.x) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        self.addr_abs &= 0x00FF;
-        0
+    fn pull(&mut self, bus: &mut impl CpuBus) -> u8 {
+        self.stkp = self.stkp.wrapping_add(1);
+        self.read(bus, 0x0100 | self.stkp as u16)
     }
 
-    fn zpy(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.addr_abs = self.read(bus, ppu, cart, self.pc).wrapping_add(self.y) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        self.addr_abs &= 0x00FF;
-        0
+    // Whether indexing carried into the high byte of the address
+    fn page_crossed(&self) -> bool {
+        (self.base & 0xFF00) != (self.addr_abs & 0xFF00)
     }
 
-    fn rel(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.addr_rel = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
+    // Runs cycle `t` (2 onwards) of the current instruction, returning true on its last cycle
+    fn execute(&mut self, bus: &mut impl CpuBus, t: u8) -> bool {
+        let instruction = &self.lookup[self.opcode as usize];
+        let (mode, operation) = (instruction.addrmode, instruction.operate);
 
-        if self.addr_rel & 0x80 > 0 {
-            self.addr_rel |= 0xFF00;
+        match (mode, operation) {
+            (_, Operation::Control(op)) => return self.control_cycle(bus, t, op),
+            (AddrMode::Acc, Operation::ReadModifyWrite(op)) => {
+                self.read(bus, self.pc);
+                self.fetched = self.a;
+                op(self);
+                self.a = self.fetched;
+                return true;
+            }
+            (AddrMode::Imp, Operation::Implied(op)) => {
+                self.read(bus, self.pc);
+                op(self);
+                return true;
+            }
+            (AddrMode::Imm, Operation::Read(op)) => {
+                self.fetched = self.read(bus, self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                op(self);
+                return true;
+            }
+            _ => {}
         }
 
-        0
-    }
-
-    fn abs(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let lo: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        let hi: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        self.addr_abs = (hi.wrapping_shl(8)) | lo;
-        0
-    }
-
-    fn abx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let lo: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        let hi: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
+        // Reads skip the cycle that fixes up the high byte when indexing stays in the page
+        let fixup = !matches!(operation, Operation::Read(_)) || self.page_crossed();
+
+        // Cycle on which the operand is accessed, once the effective address is known
+        let access = match mode {
+            AddrMode::Zp0 => 3,
+            AddrMode::Zpx | AddrMode::Zpy | AddrMode::Abs => 4,
+            AddrMode::Abx | AddrMode::Aby => 4 + fixup as u8,
+            AddrMode::Izx => 6,
+            AddrMode::Izy => 5 + fixup as u8,
+            AddrMode::Imp | AddrMode::Acc | AddrMode::Imm | AddrMode::Rel | AddrMode::Ind => {
+                unreachable!("no memory operand")
+            }
+        };
 
-        self.addr_abs = (hi.wrapping_shl(8)) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.x as u16);
+        if t < access {
+            self.address_cycle(bus, t, mode);
+            return false;
+        }
 
-        if (self.addr_abs & 0xFF00) != (hi.wrapping_shl(8)) {
-            1
-        } else {
-            0
+        match (operation, t - access) {
+            (Operation::Read(op), _) => {
+                self.fetched = self.read(bus, self.addr_abs);
+                op(self);
+                true
+            }
+            (Operation::Write(op), _) => {
+                op(self);
+                self.write(bus, self.addr_abs, self.fetched);
+                true
+            }
+            (Operation::ReadModifyWrite(_), 0) => {
+                self.fetched = self.read(bus, self.addr_abs);
+                false
+            }
+            (Operation::ReadModifyWrite(op), 1) => {
+                self.write(bus, self.addr_abs, self.fetched);
+                op(self);
+                false
+            }
+            (Operation::ReadModifyWrite(_), _) => {
+                self.write(bus, self.addr_abs, self.fetched);
+                true
+            }
+            (Operation::Implied(_), _) | (Operation::Control(_), _) => {
+                unreachable!("no memory operand")
+            }
         }
     }
 
-    fn aby(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let lo: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-        let hi: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        self.addr_abs = (hi.wrapping_shl(8)) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.y as u16);
-
-        if (self.addr_abs & 0xFF00) != (hi.wrapping_shl(8)) {
-            1
-        } else {
-            0
+    // Cycles that form the effective address in `addr_abs`
+    fn address_cycle(&mut self, bus: &mut impl CpuBus, t: u8, mode: AddrMode) {
+        match (mode, t) {
+            // Zero page address, absolute low byte or zero page pointer
+            (_, 2) => {
+                self.base = self.read(bus, self.pc) as u16;
+                self.addr_abs = self.base;
+                self.pc = self.pc.wrapping_add(1);
+            }
+            // The index is added without carry while the unindexed address is read
+            (AddrMode::Zpx, 3) | (AddrMode::Zpy, 3) | (AddrMode::Izx, 3) => {
+                self.read(bus, self.base);
+                let index = if mode == AddrMode::Zpy {
+                    self.y
+                } else {
+                    self.x
+                };
+                self.base = (self.base + index as u16) & 0x00FF;
+                self.addr_abs = self.base;
+            }
+            (AddrMode::Abs, 3) | (AddrMode::Abx, 3) | (AddrMode::Aby, 3) => {
+                self.base |= (self.read(bus, self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
+                let index = match mode {
+                    AddrMode::Abx => self.x,
+                    AddrMode::Aby => self.y,
+                    _ => 0,
+                };
+                self.addr_abs = self.base.wrapping_add(index as u16);
+            }
+            (AddrMode::Izx, 4) | (AddrMode::Izy, 3) => {
+                self.addr_abs = self.read(bus, self.base) as u16;
+            }
+            // The pointer wraps around within the zero page
+            (AddrMode::Izx, 5) | (AddrMode::Izy, 4) => {
+                let hi = self.read(bus, (self.base + 1) & 0x00FF) as u16;
+                self.addr_abs |= hi << 8;
+
+                if mode == AddrMode::Izy {
+                    self.base = self.addr_abs;
+                    self.addr_abs = self.base.wrapping_add(self.y as u16);
+                }
+            }
+            // Read from the address before the carry reaches the high byte
+            (AddrMode::Abx, 4) | (AddrMode::Aby, 4) | (AddrMode::Izy, 5) => {
+                self.read(bus, (self.base & 0xFF00) | (self.addr_abs & 0x00FF));
+            }
+            _ => unreachable!("address already formed"),
         }
     }
 
-    fn ind(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let ptr_lo: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        let ptr_hi: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        let ptr: u16 = (ptr_hi.wrapping_shl(8)) | ptr_lo;
+    // Branches, jumps and stack instructions
+    fn control_cycle(&mut self, bus: &mut impl CpuBus, t: u8, op: fn(&mut Cpu)) -> bool {
+        match (self.opcode, t) {
+            // BRK skips the byte after it
+            (0x00, 2) => {
+                self.read(bus, self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                op(self);
+                false
+            }
+            (0x00, _) => self.interrupt_cycle(bus, t, 0xFFFE),
+            // JSR
+            (0x20, 2) => {
+                self.addr_abs = self.read(bus, self.pc) as u16;
+                self.pc = self.pc.wrapping_add(1);
+                false
+            }
+            (0x20, 3) => {
+                self.read(bus, 0x0100 | self.stkp as u16);
+                false
+            }
+            (0x20, 4) => {
+                self.push(bus, (self.pc >> 8) as u8);
+                false
+            }
+            (0x20, 5) => {
+                self.push(bus, self.pc as u8);
+                false
+            }
+            (0x20, _) => {
+                self.addr_abs |= (self.read(bus, self.pc) as u16) << 8;
+                op(self);
+                true
+            }
+            // RTI and RTS
+            (0x40, 2) | (0x60, 2) => {
+                self.read(bus, self.pc);
+                false
+            }
+            (0x40, 3) | (0x60, 3) => {
+                self.read(bus, 0x0100 | self.stkp as u16);
+                false
+            }
+            (0x40, 4) => {
+                self.fetched = self.pull(bus);
+                op(self);
+                false
+            }
+            (0x40, 5) | (0x60, 4) => {
+                self.pc = (self.pc & 0xFF00) | self.pull(bus) as u16;
+                false
+            }
+            (0x40, _) => {
+                self.pc = (self.pc & 0x00FF) | (self.pull(bus) as u16) << 8;
+                true
+            }
+            (0x60, 5) => {
+                self.pc = (self.pc & 0x00FF) | (self.pull(bus) as u16) << 8;
+                false
+            }
+            (0x60, _) => {
+                self.read(bus, self.pc);
+                op(self);
+                true
+            }
+            // PHP and PHA
+            (0x08, 2) | (0x48, 2) => {
+                self.read(bus, self.pc);
+                op(self);
+                false
+            }
+            (0x08, _) | (0x48, _) => {
+                self.push(bus, self.fetched);
+                true
+            }
+            // PLP and PLA
+            (0x28, 2) | (0x68, 2) => {
+                self.read(bus, self.pc);
+                false
+            }
+            (0x28, 3) | (0x68, 3) => {
+                self.read(bus, 0x0100 | self.stkp as u16);
+                false
+            }
+            (0x28, _) | (0x68, _) => {
+                self.fetched = self.pull(bus);
+                op(self);
+                true
+            }
+            // JMP absolute
+            (0x4C, 2) => {
+                self.addr_abs = self.read(bus, self.pc) as u16;
+                self.pc = self.pc.wrapping_add(1);
+                false
+            }
+            (0x4C, _) => {
+                self.addr_abs |= (self.read(bus, self.pc) as u16) << 8;
+                op(self);
+                true
+            }
+            // JMP indirect, whose pointer does not carry into its high byte
+            (0x6C, 2) => {
+                self.base = self.read(bus, self.pc) as u16;
+                self.pc = self.pc.wrapping_add(1);
+                false
+            }
+            (0x6C, 3) => {
+                self.base |= (self.read(bus, self.pc) as u16) << 8;
+                self.pc = self.pc.wrapping_add(1);
+                false
+            }
+            (0x6C, 4) => {
+                self.addr_abs = self.read(bus, self.base) as u16;
+                false
+            }
+            (0x6C, _) => {
+                let ptr = (self.base & 0xFF00) | (self.base.wrapping_add(1) & 0x00FF);
+                self.addr_abs |= (self.read(bus, ptr) as u16) << 8;
+                op(self);
+                true
+            }
+            // Branches take one more cycle when taken and another when crossing a page
+            (_, 2) => {
+                self.fetched = self.read(bus, self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                op(self);
+                !self.branch_taken
+            }
+            (_, 3) => {
+                // A taken branch that stays in the page ignores an IRQ arriving on its
+                // second cycle, so one more instruction runs before it
+                if self.irq_pending && !self.irq_polled {
+                    self.irq_pending = false;
+                }
 
-        if ptr_lo == 0x00FF {
-            // simulate page boundary hardware bug
-            self.addr_abs = ((self.read(bus, ppu, cart, ptr & 0xFF00) as u16).wrapping_shl(8))
-                | self.read(bus, ppu, cart, ptr) as u16;
-        } else {
-            // behave normally
-            self.addr_abs = ((self.read(bus, ppu, cart, ptr.wrapping_add(1)) as u16)
-                .wrapping_shl(8))
-                | self.read(bus, ppu, cart, ptr) as u16;
+                self.read(bus, self.pc);
+                self.addr_abs = self.pc.wrapping_add(self.fetched as i8 as u16);
+                let same_page = (self.addr_abs & 0xFF00) == (self.pc & 0xFF00);
+                self.pc = (self.pc & 0xFF00) | (self.addr_abs & 0x00FF);
+                same_page
+            }
+            (_, _) => {
+                self.read(bus, self.pc);
+                self.pc = self.addr_abs;
+                true
+            }
         }
-
-        0
     }
 
-    fn izx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let t: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        let lo: u16 = self.read(bus, ppu, cart, t.wrapping_add(self.x as u16) & 0x00FF) as u16;
-        let hi: u16 = self.read(
-            bus,
-            ppu,
-            cart,
-            t.wrapping_add(self.x as u16).wrapping_add(1) & 0x00FF,
-        ) as u16;
-
-        self.addr_abs = (hi.wrapping_shl(8)) | lo;
+    // Reset, NMI and IRQ. The first two cycles read the next opcode without using it.
+    fn interrupt_sequence(&mut self, bus: &mut impl CpuBus, t: u8, interrupt: Interrupt) -> bool {
+        let vector = match interrupt {
+            Interrupt::Reset => 0xFFFC,
+            Interrupt::Nmi => 0xFFFA,
+            Interrupt::Irq => 0xFFFE,
+        };
 
-        0
+        match t {
+            1 | 2 => {
+                self.read(bus, self.pc);
+                self.fetched = (self.status & !(Flags::B as u8)) | Flags::U as u8;
+                false
+            }
+            // Reset goes through the pushes with the bus held in read mode
+            3..=5 if interrupt == Interrupt::Reset => {
+                self.read(bus, 0x0100 | self.stkp as u16);
+                self.stkp = self.stkp.wrapping_sub(1);
+                self.addr_abs = vector;
+                false
+            }
+            _ => self.interrupt_cycle(bus, t, vector),
+        }
     }
 
-    fn izy(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        let t: u16 = self.read(bus, ppu, cart, self.pc) as u16;
-        self.pc = self.pc.wrapping_add(1);
-
-        let lo: u16 = self.read(bus, ppu, cart, t & 0x00FF) as u16;
-        let hi: u16 = self.read(bus, ppu, cart, (t.wrapping_add(1)) & 0x00FF) as u16;
-
-        self.addr_abs = (hi.wrapping_shl(8)) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.y as u16);
-
-        if (self.addr_abs & 0x00FF) != (hi.wrapping_shl(8)) {
-            1
-        } else {
-            0
+    // Cycles 3 to 7 of BRK and the interrupts, pushing the return address and the status in
+    // `fetched`, then loading the vector. An NMI detected before the status is pushed hijacks
+    // the sequence, which then uses the NMI vector.
+    fn interrupt_cycle(&mut self, bus: &mut impl CpuBus, t: u8, vector: u16) -> bool {
+        match t {
+            3 => {
+                self.push(bus, (self.pc >> 8) as u8);
+                false
+            }
+            4 => {
+                self.push(bus, self.pc as u8);
+                false
+            }
+            5 => {
+                self.addr_abs = if self.nmi_pending {
+                    self.nmi_pending = false;
+                    0xFFFA
+                } else {
+                    vector
+                };
+                self.push(bus, self.fetched);
+                false
+            }
+            6 => {
+                self.pc = (self.pc & 0xFF00) | self.read(bus, self.addr_abs) as u16;
+                self.set_flag(Flags::I, true);
+                false
+            }
+            _ => {
+                let hi = self.read(bus, self.addr_abs.wrapping_add(1)) as u16;
+                self.pc = (self.pc & 0x00FF) | hi << 8;
+                true
+            }
         }
     }
 
     // Opcodes
-    fn adc(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-
-        let temp: u16 = (self.a as u16)
-            .wrapping_add(self.fetched as u16)
-            .wrapping_add(self.get_flag(Flags::C) as u16);
-        self.set_flag(Flags::C, temp > 255);
-        self.set_flag(Flags::Z, (temp & 0x00FF) == 0);
-        self.set_flag(Flags::N, (temp & 0x0080) > 0);
-        self.set_flag(
-            Flags::V,
-            (!(self.a as u16 ^ self.fetched as u16) & (self.a as u16 ^ temp) & 0x0080) > 0,
-        );
-        self.a = (temp & 0x00FF) as u8;
-
-        1
+    fn adc(&mut self) {
+        if self.decimal_mode() {
+            self.add_decimal(self.fetched);
+        } else {
+            self.add_with_carry(self.fetched);
+        }
     }
 
-    fn and(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn and(&mut self) {
         self.a &= self.fetched;
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        1
     }
 
-    fn asl(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn asl(&mut self) {
         let temp = (self.fetched as u16).wrapping_shl(1);
         self.set_flag(Flags::C, (temp & 0xFF00) > 0);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x00);
         self.set_flag(Flags::N, (temp & 0x80) > 0);
-
-        if self.lookup[self.opcode as usize].addrmode as usize == Cpu::imp as usize {
-            self.a = (temp & 0x00FF) as u8;
-        } else {
-            self.write(bus, ppu, cart, self.addr_abs, (temp & 0x00FF) as u8);
-        }
-
-        0
+        self.fetched = (temp & 0x00FF) as u8;
     }
 
-    fn bcc(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::C) == 0 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bcc(&mut self) {
+        self.branch_taken = self.get_flag(Flags::C) == 0;
     }
 
-    fn bcs(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::C) == 1 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bcs(&mut self) {
+        self.branch_taken = self.get_flag(Flags::C) == 1;
     }
 
-    fn beq(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::Z) == 1 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn beq(&mut self) {
+        self.branch_taken = self.get_flag(Flags::Z) == 1;
     }
 
-    fn bit(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn bit(&mut self) {
         let temp = self.a & self.fetched;
 
         self.set_flag(Flags::Z, (temp as u16 & 0x00FF) == 0x00);
         self.set_flag(Flags::N, self.fetched & (1 << 7) > 0);
         self.set_flag(Flags::V, self.fetched & (1 << 6) > 0);
-
-        0
     }
 
-    fn bmi(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::N) == 1 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bmi(&mut self) {
+        self.branch_taken = self.get_flag(Flags::N) == 1;
     }
 
-    fn bne(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::Z) == 0 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bne(&mut self) {
+        self.branch_taken = self.get_flag(Flags::Z) == 0;
     }
 
-    fn bpl(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::N) == 0 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bpl(&mut self) {
+        self.branch_taken = self.get_flag(Flags::N) == 0;
     }
 
-    fn brk(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.pc = self.pc.wrapping_add(1);
-
-        self.set_flag(Flags::I, true);
-
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100_u16.wrapping_add(self.stkp as u16),
-            (self.pc.wrapping_shr(8) & 0x00FF) as u8,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100_u16.wrapping_add(self.stkp as u16),
-            (self.pc & 0x00FF) as u8,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-
-        self.set_flag(Flags::B, true);
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100_u16.wrapping_add(self.stkp as u16),
-            self.status,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-        self.set_flag(Flags::B, false);
-
-        self.pc = self.read(bus, ppu, cart, 0xFFFE) as u16
-            | ((self.read(bus, ppu, cart, 0xFFFF) as u16).wrapping_shl(8));
-
-        0
+    // Status as pushed by BRK
+    fn brk(&mut self) {
+        self.fetched = self.status | Flags::B as u8 | Flags::U as u8;
     }
 
-    fn bvc(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::V) == 0 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bvc(&mut self) {
+        self.branch_taken = self.get_flag(Flags::V) == 0;
     }
 
-    fn bvs(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        if self.get_flag(Flags::V) == 1 {
-            self.cycles = self.cycles.wrapping_add(1);
-            self.addr_abs = self.pc.wrapping_add(self.addr_rel);
-
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles.wrapping_add(1);
-            }
-
-            self.pc = self.addr_abs;
-        }
-
-        0
+    fn bvs(&mut self) {
+        self.branch_taken = self.get_flag(Flags::V) == 1;
     }
 
-    fn clc(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn clc(&mut self) {
         self.set_flag(Flags::C, false);
-        0
     }
 
-    fn cld(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn cld(&mut self) {
         self.set_flag(Flags::D, false);
-        0
     }
 
-    fn cli(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn cli(&mut self) {
         self.set_flag(Flags::I, false);
-        0
     }
 
-    fn clv(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn clv(&mut self) {
         self.set_flag(Flags::V, false);
-        0
     }
 
-    fn cmp(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn cmp(&mut self) {
         let temp = (self.a as u16).wrapping_sub(self.fetched as u16);
         self.set_flag(Flags::C, self.a >= self.fetched);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        1
     }
 
-    fn cpx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn cpx(&mut self) {
         let temp = (self.x as u16).wrapping_sub(self.fetched as u16);
         self.set_flag(Flags::C, self.x >= self.fetched);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        0
     }
 
-    fn cpy(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn cpy(&mut self) {
         let temp = (self.y as u16).wrapping_sub(self.fetched as u16);
         self.set_flag(Flags::C, self.y >= self.fetched);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        0
     }
 
-    fn dec(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-        let temp = self.fetched.wrapping_sub(1);
-        self.write(bus, ppu, cart, self.addr_abs, temp & 0x00FF);
-        self.set_flag(Flags::Z, temp == 0x0000);
-        self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        0
+    fn dec(&mut self) {
+        self.fetched = self.fetched.wrapping_sub(1);
+        self.set_flag(Flags::Z, self.fetched == 0x00);
+        self.set_flag(Flags::N, (self.fetched & 0x80) > 0);
     }
 
-    fn dex(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn dex(&mut self) {
         self.x = self.x.wrapping_sub(1);
         self.set_flag(Flags::Z, self.x == 0x00);
         self.set_flag(Flags::N, (self.x & 0x80) > 0);
-
-        0
     }
 
-    fn dey(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn dey(&mut self) {
         self.y = self.y.wrapping_sub(1);
         self.set_flag(Flags::Z, self.y == 0x00);
         self.set_flag(Flags::N, (self.y & 0x80) > 0);
-
-        0
     }
 
-    fn eor(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn eor(&mut self) {
         self.a ^= self.fetched;
 
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        1
     }
 
-    fn inc(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-        let temp = self.fetched.wrapping_add(1);
-        self.write(bus, ppu, cart, self.addr_abs, temp);
-
-        self.set_flag(Flags::Z, temp == 0x0000);
-        self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        0
+    fn inc(&mut self) {
+        self.fetched = self.fetched.wrapping_add(1);
+        self.set_flag(Flags::Z, self.fetched == 0x00);
+        self.set_flag(Flags::N, (self.fetched & 0x80) > 0);
     }
 
-    fn inx(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn inx(&mut self) {
         self.x = self.x.wrapping_add(1);
         self.set_flag(Flags::Z, self.x == 0x00);
         self.set_flag(Flags::N, (self.x & 0x80) > 0);
-
-        0
     }
 
-    fn iny(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn iny(&mut self) {
         self.y = self.y.wrapping_add(1);
         self.set_flag(Flags::Z, self.y == 0x00);
         self.set_flag(Flags::N, (self.y & 0x80) > 0);
-
-        0
     }
 
-    fn jmp(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn jmp(&mut self) {
         self.pc = self.addr_abs;
-
-        0
     }
 
-    fn jsr(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.pc = self.pc.wrapping_sub(1);
-
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            ((self.pc.wrapping_shr(8)) & 0x00FF) as u8,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            (self.pc & 0x00FF) as u8,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-
+    fn jsr(&mut self) {
         self.pc = self.addr_abs;
-
-        0
     }
 
-    fn lda(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn lda(&mut self) {
         self.a = self.fetched;
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        1
     }
 
-    fn ldx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn ldx(&mut self) {
         self.x = self.fetched;
         self.set_flag(Flags::Z, self.x == 0x00);
         self.set_flag(Flags::N, (self.x & 0x80) > 0);
-
-        1
     }
 
-    fn ldy(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn ldy(&mut self) {
         self.y = self.fetched;
         self.set_flag(Flags::Z, self.y == 0x00);
         self.set_flag(Flags::N, (self.y & 0x80) > 0);
-
-        1
     }
 
-    fn lsr(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn lsr(&mut self) {
         self.set_flag(Flags::C, (self.fetched & 0x0001) > 0);
         let temp = self.fetched.wrapping_shr(1);
         self.set_flag(Flags::Z, (temp as u16 & 0x00FF) == 0x0000);
         self.set_flag(Flags::N, (temp as u16 & 0x0080) > 0);
-
-        if self.lookup[self.opcode as usize].addrmode as usize == Cpu::imp as usize {
-            self.a = temp;
-        } else {
-            self.write(
-                bus,
-                ppu,
-                cart,
-                self.addr_abs,
-                (temp as u16 & 0x00FF) as u8,
-            );
-        }
-
-        0
+        self.fetched = temp;
     }
 
-    fn nop(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        match self.opcode {
-            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => 1,
-            _ => 0,
-        }
-    }
+    fn nop(&mut self) {}
 
-    fn ora(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
+    fn ora(&mut self) {
         self.a |= self.fetched;
 
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        1
     }
 
-    fn pha(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            self.a,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-
-        0
+    fn pha(&mut self) {
+        self.fetched = self.a;
     }
 
-    fn php(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            self.status | Flags::B as u8 | Flags::U as u8,
-        );
-        self.set_flag(Flags::B, false);
-        self.set_flag(Flags::U, false);
-        self.stkp = self.stkp.wrapping_sub(1);
-
-        0
+    fn php(&mut self) {
+        self.fetched = self.status | Flags::B as u8 | Flags::U as u8;
     }
 
-    fn pla(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.stkp = self.stkp.wrapping_add(1);
-        self.a = self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        );
+    fn pla(&mut self) {
+        self.a = self.fetched;
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        0
     }
 
-    fn plp(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.stkp = self.stkp.wrapping_add(1);
-        self.status = self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        );
+    fn plp(&mut self) {
+        self.status = self.fetched;
+        // B only exists in the copy pushed to the stack
+        self.set_flag(Flags::B, false);
         self.set_flag(Flags::U, true);
-
-        0
     }
 
-    fn rol(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-
+    fn rol(&mut self) {
         let temp = (self.fetched as u16).wrapping_shl(1) | self.get_flag(Flags::C) as u16;
 
         self.set_flag(Flags::C, (temp & 0xFF00) > 0);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x0000);
         self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        if self.lookup[self.opcode as usize].addrmode as usize == Cpu::imp as usize {
-            self.a = (temp & 0x00FF) as u8;
-        } else {
-            self.write(bus, ppu, cart, self.addr_abs, (temp & 0x00FF) as u8);
-        }
-
-        0
+        self.fetched = (temp & 0x00FF) as u8;
     }
 
-    fn ror(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-        let temp = self.get_flag(Flags::C).wrapping_shl(7) as u16
-            | self.fetched.wrapping_shr(1) as u16;
+    fn ror(&mut self) {
+        let temp =
+            self.get_flag(Flags::C).wrapping_shl(7) as u16 | self.fetched.wrapping_shr(1) as u16;
         self.set_flag(Flags::C, (self.fetched & 0x01) > 0);
         self.set_flag(Flags::Z, (temp & 0x00FF) == 0x00);
         self.set_flag(Flags::N, (temp & 0x0080) > 0);
-
-        if self.lookup[self.opcode as usize].addrmode as usize == Cpu::imp as usize {
-            self.a = (temp & 0x00FF) as u8;
-        } else {
-            self.write(bus, ppu, cart, self.addr_abs, (temp & 0x00FF) as u8);
-        }
-
-        0
+        self.fetched = (temp & 0x00FF) as u8;
     }
 
-    fn rti(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.stkp = self.stkp.wrapping_add(1);
-        self.status = self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        );
-        self.status &= !(Flags::B as u8);
-        self.status &= !(Flags::U as u8);
-
-        self.stkp = self.stkp.wrapping_add(1);
-        self.pc = self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        ) as u16;
-        self.stkp = self.stkp.wrapping_add(1);
-        self.pc |= (self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        ) as u16)
-            .wrapping_shl(8);
-
-        0
+    // Restores the status, the return address is pulled by the sequencer
+    fn rti(&mut self) {
+        self.plp();
     }
 
-    fn rts(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.stkp = self.stkp.wrapping_add(1);
-        self.pc = self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        ) as u16;
-        self.stkp = self.stkp.wrapping_add(1);
-        self.pc |= (self.read(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-        ) as u16)
-            .wrapping_shl(8);
-
+    // The pulled address points at the last byte of the JSR
+    fn rts(&mut self) {
         self.pc = self.pc.wrapping_add(1);
-        0
     }
 
-    fn sbc(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.fetch(bus, ppu, cart);
-        let value: u16 = (self.fetched as u16) ^ 0x00FF;
+    fn sbc(&mut self) {
+        let (a, carry) = (self.a, self.get_flag(Flags::C));
 
-        let temp: u16 = (self.a as u16)
-            .wrapping_add(value)
-            .wrapping_add(self.get_flag(Flags::C) as u16);
-        self.set_flag(Flags::C, (temp & 0xFF00) > 0);
-        self.set_flag(Flags::Z, (temp & 0x00FF) == 0);
-        self.set_flag(Flags::N, (temp & 0x0080) > 0);
-        self.set_flag(
-            Flags::V,
-            ((temp ^ (self.a as u16)) & (temp ^ value)) & 0x0080 > 0,
-        );
-        self.a = (temp & 0x00FF) as u8;
+        // Subtraction is addition of the inverted operand
+        self.add_with_carry(self.fetched ^ 0xFF);
 
-        1
+        // Decimal mode only changes the result, the flags are the binary ones
+        if self.decimal_mode() {
+            self.a = Cpu::subtract_decimal(a, self.fetched, carry);
+        }
     }
 
-    fn sec(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn sec(&mut self) {
         self.set_flag(Flags::C, true);
-        0
     }
 
-    fn sed(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn sed(&mut self) {
         self.set_flag(Flags::D, true);
-        0
     }
 
-    fn sei(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn sei(&mut self) {
         self.set_flag(Flags::I, true);
-        0
     }
 
-    fn sta(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.write(bus, ppu, cart, self.addr_abs, self.a);
-        0
+    fn sta(&mut self) {
+        self.fetched = self.a;
     }
 
-    fn stx(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.write(bus, ppu, cart, self.addr_abs, self.x);
-        0
+    fn stx(&mut self) {
+        self.fetched = self.x;
     }
 
-    fn sty(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        self.write(bus, ppu, cart, self.addr_abs, self.y);
-        0
+    fn sty(&mut self) {
+        self.fetched = self.y;
     }
 
-    fn tax(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn tax(&mut self) {
         self.x = self.a;
         self.set_flag(Flags::Z, self.x == 0x00);
         self.set_flag(Flags::N, (self.x & 0x80) > 0);
-
-        0
     }
 
-    fn tay(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn tay(&mut self) {
         self.y = self.a;
         self.set_flag(Flags::Z, self.y == 0x00);
         self.set_flag(Flags::N, (self.y & 0x80) > 0);
-
-        0
     }
 
-    fn tsx(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn tsx(&mut self) {
         self.x = self.stkp;
         self.set_flag(Flags::Z, self.x == 0x00);
         self.set_flag(Flags::N, (self.x & 0x80) > 0);
-
-        0
     }
 
-    fn txa(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn txa(&mut self) {
         self.a = self.x;
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
-
-        0
     }
 
-    fn txs(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn txs(&mut self) {
         self.stkp = self.x;
-
-        0
     }
 
-    fn tya(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
+    fn tya(&mut self) {
         self.a = self.y;
 
         self.set_flag(Flags::Z, self.a == 0x00);
         self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
+
+    // Unofficial opcodes
+    fn alr(&mut self) {
+        self.a &= self.fetched;
+        self.set_flag(Flags::C, (self.a & 0x01) > 0);
+        self.a >>= 1;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
 
-        0
+    fn anc(&mut self) {
+        self.a &= self.fetched;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+        self.set_flag(Flags::C, (self.a & 0x80) > 0);
     }
 
-    fn xxx(&mut self, _bus: &mut Bus, _ppu: &mut Ppu, _cart: &mut Cartridge) -> u8 {
-        0
+    fn arr(&mut self) {
+        self.a = (self.a & self.fetched) >> 1 | self.get_flag(Flags::C) << 7;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+        self.set_flag(Flags::C, (self.a & 0x40) > 0);
+        self.set_flag(Flags::V, ((self.a >> 6) ^ (self.a >> 5)) & 0x01 > 0);
     }
 
-    pub fn clock(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
-        if self.cycles == 0 {
-            self.opcode = self.read(bus, ppu, cart, self.pc);
-            self.set_flag(Flags::U, true);
-            self.pc = self.pc.wrapping_add(1);
+    // Also known as SBX
+    fn axs(&mut self) {
+        let value = self.a & self.x;
+        self.x = value.wrapping_sub(self.fetched);
+        self.set_flag(Flags::C, value >= self.fetched);
+        self.set_flag(Flags::Z, self.x == 0x00);
+        self.set_flag(Flags::N, (self.x & 0x80) > 0);
+    }
+
+    fn dcp(&mut self) {
+        self.fetched = self.fetched.wrapping_sub(1);
+        self.cmp();
+    }
+
+    fn isb(&mut self) {
+        self.fetched = self.fetched.wrapping_add(1);
+        self.sbc();
+    }
+
+    // Locks up the processor until the next reset
+    fn jam(&mut self) {
+        self.pc = self.pc.wrapping_sub(1);
+    }
+
+    fn las(&mut self) {
+        self.stkp &= self.fetched;
+        self.a = self.stkp;
+        self.x = self.stkp;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
+
+    fn lax(&mut self) {
+        self.a = self.fetched;
+        self.x = self.fetched;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
+
+    // Unstable, the bits of A that survive depend on the chip. $EE is the common value.
+    fn lxa(&mut self) {
+        self.a = (self.a | 0xEE) & self.fetched;
+        self.x = self.a;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
+
+    fn rla(&mut self) {
+        self.rol();
+        self.and();
+    }
+
+    fn rra(&mut self) {
+        self.ror();
+        self.adc();
+    }
+
+    fn sax(&mut self) {
+        self.fetched = self.a & self.x;
+    }
+
+    fn sha(&mut self) {
+        self.unstable_store(self.a & self.x);
+    }
+
+    fn shx(&mut self) {
+        self.unstable_store(self.x);
+    }
+
+    fn shy(&mut self) {
+        self.unstable_store(self.y);
+    }
+
+    fn slo(&mut self) {
+        self.asl();
+        self.ora();
+    }
+
+    fn sre(&mut self) {
+        self.lsr();
+        self.eor();
+    }
+
+    fn tas(&mut self) {
+        self.stkp = self.a & self.x;
+        self.unstable_store(self.stkp);
+    }
+
+    // Unstable, uses the same magic constant as LXA
+    fn xaa(&mut self) {
+        self.a = (self.a | 0xEE) & self.x & self.fetched;
+        self.set_flag(Flags::Z, self.a == 0x00);
+        self.set_flag(Flags::N, (self.a & 0x80) > 0);
+    }
+
+    // SHA, SHX, SHY and TAS store the value ANDed with the high byte of the base address plus
+    // one. When indexing crosses a page that same value replaces the high byte of the address.
+    fn unstable_store(&mut self, value: u8) {
+        self.fetched = value & ((self.base >> 8) as u8).wrapping_add(1);
+
+        if self.page_crossed() {
+            self.addr_abs = (self.fetched as u16) << 8 | (self.addr_abs & 0x00FF);
+        }
+    }
+
+    pub fn clock(&mut self, bus: &mut impl CpuBus) {
+        self.step += 1;
+
+        let done = if let Some(interrupt) = self.interrupt {
+            self.interrupt_sequence(bus, self.step, interrupt)
+        } else if self.step == 1 {
+            if self.trace.is_some() {
+                let line = self.trace_line(bus);
 
-            self.cycles = self.lookup[self.opcode as usize].cycles;
-            let additional_cycle1 =
-                (self.lookup[self.opcode as usize].addrmode)(self, bus, ppu, cart);
-            let additional_cycle2 =
-                (self.lookup[self.opcode as usize].operate)(self, bus, ppu, cart);
+                if let Some(sink) = &mut self.trace {
+                    if writeln!(sink, "{}", line).is_err() {
+                        self.trace = None;
+                    }
+                }
+            }
+
+            self.opcode = self.read(bus, self.pc);
+            self.pc = self.pc.wrapping_add(1);
+            false
+        } else {
+            self.execute(bus, self.step)
+        };
 
-            self.cycles = self
-                .cycles
-                .wrapping_add(additional_cycle1 & additional_cycle2);
+        self.poll_interrupts();
 
-            self.set_flag(Flags::U, true)
+        if done {
+            self.step = 0;
+            self.interrupt = match self.interrupt {
+                Some(_) => None,
+                None => self.polled_interrupt(),
+            };
         }
 
         self.clock_count = self.clock_count.wrapping_add(1);
-        self.cycles = self.cycles.wrapping_sub(1);
     }
 
-    pub fn reset(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
-        self.addr_abs = 0xFFFC;
-        let lo: u16 = self.read(bus, ppu, cart, self.addr_abs) as u16;
-        let hi: u16 = self.read(bus, ppu, cart, self.addr_abs.wrapping_add(1)) as u16;
-        self.pc = hi.wrapping_shl(8) | lo;
-
+    // Starts the 7 cycle reset sequence, which loads pc from the reset vector
+    pub fn reset(&mut self) {
         self.a = 0;
         self.x = 0;
         self.y = 0;
-        self.stkp = 0xFD;
-        self.status = 0x00 | Flags::U as u8;
+        // Decremented three times by the sequence, ending up at $FD
+        self.stkp = 0x00;
+        self.status = Flags::U as u8 | Flags::I as u8;
 
         self.addr_abs = 0x0000;
-        self.addr_rel = 0x0000;
+        self.base = 0x0000;
         self.fetched = 0x00;
 
-        self.cycles = 8;
+        self.step = 0;
+        self.interrupt = Some(Interrupt::Reset);
+        self.nmi_previous = self.nmi_line;
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        self.nmi_polled = false;
+        self.irq_polled = false;
     }
 
-    pub fn irq(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
-        if self.get_flag(Flags::I) == 0 {
-            self.write(
-                bus,
-                ppu,
-                cart,
-                0x0100u16.wrapping_add(self.stkp as u16),
-                ((self.pc.wrapping_shr(8)) & 0x00FF) as u8,
-            );
-            self.stkp = self.stkp.wrapping_sub(1);
-            self.write(
-                bus,
-                ppu,
-                cart,
-                0x0100u16.wrapping_add(self.stkp as u16),
-                (self.pc & 0x00FF) as u8,
-            );
-            self.stkp = self.stkp.wrapping_sub(1);
-
-            self.set_flag(Flags::B, false);
-            self.set_flag(Flags::U, true);
-            self.set_flag(Flags::I, true);
-            self.write(
-                bus,
-                ppu,
-                cart,
-                0x0100u16.wrapping_add(self.stkp as u16),
-                self.status,
-            );
-            self.stkp = self.stkp.wrapping_sub(1);
+    // NMI is edge triggered, taken once each time the line goes high
+    pub fn set_nmi(&mut self, level: bool) {
+        self.nmi_line = level;
+    }
 
-            self.addr_abs = 0xFFFE;
-            let lo = self.read(bus, ppu, cart, self.addr_abs) as u16;
-            let hi = self.read(bus, ppu, cart, self.addr_abs.wrapping_add(1)) as u16;
-            self.pc = hi.wrapping_shl(8) | lo;
+    // IRQ is level triggered, taken for as long as the line is high and I is clear
+    pub fn set_irq(&mut self, level: bool) {
+        self.irq_line = level;
+    }
 
-            self.cycles = 7;
+    // Runs at the end of every cycle. Whether an instruction is followed by an interrupt
+    // depends on the lines at the end of its second-to-last cycle, which is why CLI, SEI and
+    // PLP only take effect after the next instruction.
+    fn poll_interrupts(&mut self) {
+        self.nmi_polled = self.nmi_pending;
+        if self.nmi_line && !self.nmi_previous {
+            self.nmi_pending = true;
         }
+        self.nmi_previous = self.nmi_line;
+
+        self.irq_polled = self.irq_pending;
+        self.irq_pending = self.irq_line && self.get_flag(Flags::I) == 0;
     }
 
-    pub fn nmi(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            ((self.pc.wrapping_shr(8)) & 0x00FF) as u8,
-        );
-        self.stkp = self.stkp.wrapping_sub(1);
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            (self.pc & 0x00FF) as u8,
+    // Interrupt to run after the instruction that just finished, if any
+    fn polled_interrupt(&mut self) -> Option<Interrupt> {
+        // An NMI that hijacked BRK still lets the first instruction of the handler run
+        if self.opcode == 0x00 {
+            self.nmi_polled = false;
+        }
+
+        if self.nmi_polled {
+            Some(Interrupt::Nmi)
+        } else if self.irq_polled {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    // Enters the subroutine at `addr` as if a JSR just before `return_addr` had called it.
+    // Lets hosts like the NSF player run code without going through the reset vector; the
+    // final RTS lands on `return_addr`.
+    pub fn call(&mut self, bus: &mut impl CpuBus, addr: u16, return_addr: u16) {
+        let return_addr = return_addr.wrapping_sub(1);
+
+        self.push(bus, (return_addr >> 8) as u8);
+        self.push(bus, return_addr as u8);
+        self.pc = addr;
+
+        self.step = 0;
+        self.interrupt = None;
+    }
+
+    #[cfg(test)]
+    pub fn set_model(&mut self, model: CpuModel) {
+        self.model = model;
+    }
+
+    fn decimal_mode(&self) -> bool {
+        self.model == CpuModel::Nmos6502 && self.get_flag(Flags::D) == 1
+    }
+
+    // Decimal addition as the NMOS 6502 does it. N and V come from the result before the high
+    // digit is adjusted and Z from the binary sum.
+    fn add_decimal(&mut self, value: u8) {
+        let carry = self.get_flag(Flags::C) as u16;
+        let binary = (self.a as u16 + value as u16 + carry) & 0x00FF;
+
+        let mut lo = (self.a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (self.a & 0xF0) as u16 + (value & 0xF0) as u16 + lo;
+
+        self.set_flag(Flags::Z, binary == 0);
+        self.set_flag(Flags::N, (sum & 0x0080) > 0);
+        self.set_flag(
+            Flags::V,
+            (!(self.a as u16 ^ value as u16) & (self.a as u16 ^ sum) & 0x0080) > 0,
         );
-        self.stkp = self.stkp.wrapping_sub(1);
 
-        self.set_flag(Flags::B, false);
-        self.set_flag(Flags::U, true);
-        self.set_flag(Flags::I, true);
-        self.write(
-            bus,
-            ppu,
-            cart,
-            0x0100u16.wrapping_add(self.stkp as u16),
-            self.status,
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.set_flag(Flags::C, sum >= 0x100);
+        self.a = (sum & 0x00FF) as u8;
+    }
+
+    fn subtract_decimal(a: u8, value: u8, carry: u8) -> u8 {
+        let mut lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 + carry as i16 - 1;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+
+        let mut result = (a & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+        if result < 0 {
+            result -= 0x60;
+        }
+
+        result as u8
+    }
+
+    // Shared by ADC, SBC, RRA and ISB
+    fn add_with_carry(&mut self, value: u8) {
+        let temp: u16 = (self.a as u16)
+            .wrapping_add(value as u16)
+            .wrapping_add(self.get_flag(Flags::C) as u16);
+        self.set_flag(Flags::C, temp > 255);
+        self.set_flag(Flags::Z, (temp & 0x00FF) == 0);
+        self.set_flag(Flags::N, (temp & 0x0080) > 0);
+        self.set_flag(
+            Flags::V,
+            (!(self.a as u16 ^ value as u16) & (self.a as u16 ^ temp) & 0x0080) > 0,
         );
-        self.stkp = self.stkp.wrapping_sub(1);
+        self.a = (temp & 0x00FF) as u8;
+    }
 
-        self.addr_abs = 0xFFFA;
-        let lo = self.read(bus, ppu, cart, self.addr_abs) as u16;
-        let hi = self.read(bus, ppu, cart, self.addr_abs.wrapping_add(1)) as u16;
-        self.pc = hi.wrapping_shl(8) | lo;
+    // Logs every instruction in the format of nestest.log, or stops logging when given None
+    pub fn set_trace(&mut self, sink: Option<Box<dyn Write>>) {
+        self.trace = sink;
+    }
 
-        self.cycles = 8;
+    fn unofficial(&self, opcode: u8) -> bool {
+        match self.lookup[opcode as usize].name.as_str() {
+            "NOP" => opcode != 0xEA,
+            "SBC" => opcode == 0xEB,
+            "ALR" | "ANC" | "ARR" | "AXS" | "DCP" | "ISB" | "JAM" | "LAS" | "LAX" | "LXA"
+            | "RLA" | "RRA" | "SAX" | "SHA" | "SHX" | "SHY" | "SLO" | "SRE" | "TAS" | "XAA" => true,
+            _ => false,
+        }
     }
 
-    fn fetch(&mut self, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) -> u8 {
-        if !(self.lookup[self.opcode as usize].addrmode as usize == Cpu::imp as usize) {
-            self.fetched = self.read(bus, ppu, cart, self.addr_abs);
+    // State before the instruction at pc executes, e.g.
+    // C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    fn trace_line(&self, bus: &mut impl CpuBus) -> String {
+        let decoded = self.decode(bus, self.pc);
+        let operand = self.trace_operand(&decoded, bus);
+
+        let bytes = decoded
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // The PPU has already run the first dot of this CPU cycle, which is the one logged
+        let ppu = match bus.ppu_position() {
+            Some((scanline, dot)) => format!(" PPU:{:>3},{:>3}", scanline, dot),
+            None => String::new(),
+        };
+
+        format!(
+            "{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}{} CYC:{}",
+            decoded.address,
+            bytes,
+            if decoded.unofficial { '*' } else { ' ' },
+            format!("{} {}", decoded.mnemonic, operand).trim_end(),
+            self.a,
+            self.x,
+            self.y,
+            self.status,
+            self.stkp,
+            ppu,
+            self.clock_count
+        )
+    }
+
+    // Operand in nestest syntax, including the effective address and the value found there
+    fn trace_operand(&self, decoded: &DecodedInstruction, bus: &mut impl CpuBus) -> String {
+        let operand = decoded.operand;
+        let addr = decoded.effective_address.unwrap_or_default();
+        let value = decoded.effective_address.map_or(0, |addr| bus.peek(addr));
+        let register = match decoded.mode {
+            AddrMode::Zpy | AddrMode::Aby => 'Y',
+            _ => 'X',
+        };
+
+        match decoded.mode {
+            AddrMode::Imp => String::new(),
+            AddrMode::Acc => String::from("A"),
+            AddrMode::Imm => format!("#${:02X}", operand),
+            AddrMode::Zp0 => format!("${:02X} = {:02X}", operand, value),
+            AddrMode::Zpx | AddrMode::Zpy => format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                operand, register, addr, value
+            ),
+            AddrMode::Rel => format!("${:04X}", decoded.branch_target.unwrap_or_default()),
+            AddrMode::Abs => match decoded.branch_target {
+                Some(target) => format!("${:04X}", target),
+                None => format!("${:04X} = {:02X}", operand, value),
+            },
+            AddrMode::Abx | AddrMode::Aby => format!(
+                "${:04X},{} @ {:04X} = {:02X}",
+                operand, register, addr, value
+            ),
+            AddrMode::Ind => format!(
+                "(${:04X}) = {:04X}",
+                operand,
+                decoded.branch_target.unwrap_or_default()
+            ),
+            AddrMode::Izx => format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                operand,
+                (operand as u8).wrapping_add(self.x),
+                addr,
+                value
+            ),
+            AddrMode::Izy => format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                operand,
+                addr.wrapping_sub(self.y as u16),
+                addr,
+                value
+            ),
         }
+    }
 
-        self.fetched
+    // Decodes the instruction at `addr` using only `peek`. Effective addresses are worked out
+    // from the registers as they are now, so they only hold for the instruction at pc.
+    pub fn decode(&self, bus: &mut impl CpuBus, addr: u16) -> DecodedInstruction {
+        let opcode = bus.peek(addr);
+        let instruction = &self.lookup[opcode as usize];
+        let mode = instruction.addrmode;
+
+        let bytes: Vec<u8> = (0..mode.length())
+            .map(|i| bus.peek(addr.wrapping_add(i)))
+            .collect();
+        let operand = match bytes.len() {
+            2 => bytes[1] as u16,
+            3 => u16::from_le_bytes([bytes[1], bytes[2]]),
+            _ => 0,
+        };
+
+        let mut peek_word = |lo: u16, hi: u16| (bus.peek(hi) as u16) << 8 | bus.peek(lo) as u16;
+        // JMP and JSR, the only instructions with their own sequence using absolute addressing
+        let jump = matches!(instruction.operate, Operation::Control(_));
+
+        let effective_address = match mode {
+            AddrMode::Imp | AddrMode::Acc | AddrMode::Imm | AddrMode::Rel | AddrMode::Ind => None,
+            AddrMode::Abs if jump => None,
+            AddrMode::Zp0 | AddrMode::Abs => Some(operand),
+            AddrMode::Zpx => Some((operand + self.x as u16) & 0x00FF),
+            AddrMode::Zpy => Some((operand + self.y as u16) & 0x00FF),
+            AddrMode::Abx => Some(operand.wrapping_add(self.x as u16)),
+            AddrMode::Aby => Some(operand.wrapping_add(self.y as u16)),
+            AddrMode::Izx => {
+                let ptr = (operand as u8).wrapping_add(self.x);
+                Some(peek_word(ptr as u16, ptr.wrapping_add(1) as u16))
+            }
+            AddrMode::Izy => {
+                let ptr = operand as u8;
+                Some(peek_word(ptr as u16, ptr.wrapping_add(1) as u16).wrapping_add(self.y as u16))
+            }
+        };
+
+        let branch_target = match mode {
+            AddrMode::Rel => Some(addr.wrapping_add(2).wrapping_add(operand as i8 as u16)),
+            AddrMode::Abs if jump => Some(operand),
+            // Same page wrap as the real JMP ($xxFF)
+            AddrMode::Ind => Some(peek_word(
+                operand,
+                (operand & 0xFF00) | (operand.wrapping_add(1) & 0x00FF),
+            )),
+            _ => None,
+        };
+
+        DecodedInstruction {
+            address: addr,
+            bytes,
+            mnemonic: instruction.name.clone(),
+            mode,
+            unofficial: self.unofficial(opcode),
+            operand,
+            effective_address,
+            branch_target,
+        }
     }
 
     pub fn disassemble(
         &self,
         n_start: u16,
         n_stop: u16,
-        bus: &mut Bus,
-        ppu: &mut Ppu,
-        cart: &mut Cartridge,
-    ) -> BTreeMap<u16, String> {
+        bus: &mut impl CpuBus,
+    ) -> BTreeMap<u16, DecodedInstruction> {
         let mut addr: u32 = n_start as u32;
-        let mut value: u8;
-        let mut lo: u8;
-        let mut hi: u8;
         let mut map = BTreeMap::new();
-        let mut line_addr: u16;
 
         while addr <= n_stop as u32 {
-            line_addr = addr as u16;
-
-            let mut s_inst: String = String::from("$") + &format!("{:04X}", addr)[..] + ": ";
-
-            let opcode: u8 = bus.cpu_read(ppu, cart, addr as u16, true);
-            addr += 1;
-            s_inst.push_str(&self.lookup[opcode as usize].name[..]);
-            s_inst.push(' ');
-
-            if self.lookup[opcode as usize].addrmode as usize == Cpu::imp as usize {
-                s_inst.push_str(" {IMP}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::imm as usize {
-                value = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push_str("#$");
-                s_inst.push_str(&format!("{:02X}", value)[..]);
-                s_inst.push_str(" {IMM}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::zp0 as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:02X}", lo)[..]);
-                s_inst.push_str(" {ZP0}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::zpx as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:02X}", lo)[..]);
-                s_inst.push_str(", X {ZPX}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::zpy as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:02X}", lo)[..]);
-                s_inst.push_str(", Y {ZPY}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::izx as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push_str("($");
-                s_inst.push_str(&format!("{:02X}", lo)[..]);
-                s_inst.push_str(", X) {IZX}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::izy as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push_str("($");
-                s_inst.push_str(&format!("{:02X}", lo)[..]);
-                s_inst.push_str("), Y {IZY}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::abs as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                hi = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:04X}", (hi as u16).wrapping_shl(8) | lo as u16)[..]);
-                s_inst.push_str(" {ABS}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::abx as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                hi = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:04X}", (hi as u16).wrapping_shl(8) | lo as u16)[..]);
-                s_inst.push_str(", X {ABX}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::aby as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                hi = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:04X}", (hi as u16).wrapping_shl(8) | lo as u16)[..]);
-                s_inst.push_str(", Y {ABY}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::ind as usize {
-                lo = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                hi = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push_str("($");
-                s_inst.push_str(&format!("{:04X}", (hi as u16).wrapping_shl(8) | lo as u16)[..]);
-                s_inst.push_str(") {IND}");
-            } else if self.lookup[opcode as usize].addrmode as usize == Cpu::rel as usize {
-                value = bus.cpu_read(ppu, cart, addr as u16, true);
-                addr += 1;
-                s_inst.push('$');
-                s_inst.push_str(&format!("{:02X}", value)[..]);
-                s_inst.push_str(" [$");
-                s_inst.push_str(&format!("{:04X}", addr as i32 + (value as i8) as i32)[..]);
-                s_inst.push_str("] {REL}");
-            }
-
-            map.insert(line_addr, s_inst);
+            let decoded = self.decode(bus, addr as u16);
+            addr += decoded.bytes.len() as u32;
+            map.insert(decoded.address, decoded);
         }
 
         map
     }
 
     pub fn complete(&self) -> bool {
-        self.cycles == 0
+        self.step == 0 && self.interrupt.is_none()
     }
 
-    pub fn draw_cpu(&self, mut x: i32, y: i32) {
-        draw_text("STATUS:", x as f32, y as f32, 25.0, WHITE);
+    pub fn stack_pointer(&self) -> u8 {
+        self.stkp
+    }
 
-        x += 15;
+    pub fn set_stack_pointer(&mut self, stkp: u8) {
+        self.stkp = stkp;
+    }
 
-        if (self.status & Flags::N as u8) == Flags::N as u8 {
-            draw_text("N", (x + 64) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("N", (x + 64) as f32, y as f32, 25.0, RED);
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        self.status = status;
+    }
+
+    // Interrupt sequence in progress, if any
+    pub fn interrupt(&self) -> Option<Interrupt> {
+        self.interrupt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge, Bus, Ppu};
+    use serde_json::Value;
+    use std::{cell::RefCell, env, fs, io, path::Path, rc::Rc};
+
+    // Trace sink the test can read back after handing it to the CPU
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        if (self.status & Flags::V as u8) == Flags::V as u8 {
-            draw_text("V", (x + 80) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("V", (x + 80) as f32, y as f32, 25.0, RED);
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
+    }
 
-        if (self.status & Flags::U as u8) == Flags::U as u8 {
-            draw_text("-", (x + 96) as f32, y as f32, 25.0, GREEN);
+    // APU registers aren't emulated, so they don't read back as the $FF shown in the log
+    fn mask_apu_value(line: &str) -> String {
+        if line[16..].starts_with("STA $40") {
+            format!("{}{}", &line[..28], &line[30..])
         } else {
-            draw_text("-", (x + 96) as f32, y as f32, 25.0, RED);
+            line.to_string()
         }
+    }
 
-        if (self.status & Flags::B as u8) == Flags::B as u8 {
-            draw_text("B", (x + 112) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("B", (x + 112) as f32, y as f32, 25.0, RED);
+    // Runs nestest's automated mode from $C000 and compares every line with the golden log
+    #[test]
+    fn nestest() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let golden = fs::read_to_string(dir.join("nestest.log")).unwrap();
+        let expected: Vec<&str> = golden.lines().collect();
+        let last_cycle: u32 = expected[expected.len() - 1]
+            .rsplit("CYC:")
+            .next()
+            .and_then(|cyc| cyc.trim().parse().ok())
+            .unwrap();
+
+        let mut cart = cartridge::tests::nestest();
+        let mut bus = Bus::new();
+        let mut ppu = Ppu::new();
+        let mut cpu = Cpu::new();
+        let buffer = SharedBuffer::default();
+
+        bus.reset(&mut cpu, &mut ppu, &mut cart);
+        while !cpu.complete() {
+            bus.clock(&mut cpu, &mut ppu, &mut cart);
         }
+        cpu.pc = 0xC000;
+        cpu.set_trace(Some(Box::new(buffer.clone())));
 
-        if (self.status & Flags::D as u8) == Flags::D as u8 {
-            draw_text("D", (x + 128) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("D", (x + 128) as f32, y as f32, 25.0, RED);
+        while cpu.clock_count <= last_cycle {
+            bus.clock(&mut cpu, &mut ppu, &mut cart);
         }
 
-        if (self.status & Flags::I as u8) == Flags::I as u8 {
-            draw_text("I", (x + 144) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("I", (x + 144) as f32, y as f32, 25.0, RED);
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+
+        assert!(lines.len() >= expected.len(), "trace ended early");
+
+        for (i, (got, want)) in lines.iter().zip(&expected).enumerate() {
+            assert_eq!(
+                mask_apu_value(got),
+                mask_apu_value(want),
+                "nestest.log line {}",
+                i + 1
+            );
         }
+    }
 
-        if (self.status & Flags::Z as u8) == Flags::Z as u8 {
-            draw_text("Z", (x + 160) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("Z", (x + 160) as f32, y as f32, 25.0, RED);
+    // 64KB of RAM that records every access, for the ProcessorTests below
+    struct RamBus {
+        ram: Vec<u8>,
+        cycles: Vec<(u16, u8, &'static str)>,
+    }
+
+    impl CpuBus for RamBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            let data = self.ram[addr as usize];
+            self.cycles.push((addr, data, "read"));
+            data
         }
 
-        if (self.status & Flags::C as u8) == Flags::C as u8 {
-            draw_text("C", (x + 178) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("C", (x + 178) as f32, y as f32, 25.0, RED);
+        fn write(&mut self, addr: u16, data: u8) {
+            self.ram[addr as usize] = data;
+            self.cycles.push((addr, data, "write"));
         }
 
-        if (self.status & Flags::N as u8) == Flags::N as u8 {
-            draw_text("N", (x + 64) as f32, y as f32, 25.0, GREEN);
-        } else {
-            draw_text("N", (x + 64) as f32, y as f32, 25.0, RED);
+        fn peek(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+    }
+
+    fn field(state: &Value, name: &str) -> u64 {
+        state[name].as_u64().unwrap()
+    }
+
+    fn load_state(cpu: &mut Cpu, bus: &mut RamBus, state: &Value) {
+        cpu.pc = field(state, "pc") as u16;
+        cpu.stkp = field(state, "s") as u8;
+        cpu.a = field(state, "a") as u8;
+        cpu.x = field(state, "x") as u8;
+        cpu.y = field(state, "y") as u8;
+        cpu.status = field(state, "p") as u8;
+        cpu.step = 0;
+        cpu.interrupt = None;
+
+        for entry in state["ram"].as_array().unwrap() {
+            bus.ram[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
         }
+    }
 
-        let mut temp = String::from("PC: $");
-        temp.push_str(&format!("{:04X}", self.pc)[..]);
-        draw_text(&temp[..], x as f32, (y + 15) as f32, 25.0, WHITE);
-
-        temp = String::from("A: $");
-        temp.push_str(&format!("{:02X}", self.a)[..]);
-        temp.push_str("  [");
-        temp.push_str(self.a.to_string().as_str());
-        temp.push_str("]");
-        draw_text(&temp[..], x as f32, (y + 30) as f32, 25.0, WHITE);
-
-        temp = String::from("X: $");
-        temp.push_str(&format!("{:02X}", self.x)[..]);
-        temp.push_str("  [");
-        temp.push_str(self.x.to_string().as_str());
-        temp.push_str("]");
-        draw_text(&temp[..], x as f32, (y + 45) as f32, 25.0, WHITE);
-
-        temp = String::from("Y: $");
-        temp.push_str(&format!("{:02X}", self.y)[..]);
-        temp.push_str("  [");
-        temp.push_str(self.y.to_string().as_str());
-        temp.push_str("]");
-        draw_text(&temp[..], x as f32, (y + 60) as f32, 25.0, WHITE);
-
-        temp = String::from("Stack P: $");
-        temp.push_str(&format!("{:04X}", self.stkp)[..]);
-        draw_text(&temp[..], x as f32, (y + 75) as f32, 25.0, WHITE);
-    }
-
-    pub fn draw_code(
-        &self,
-        pc: &u16,
-        x: i64,
-        y: i64,
-        n_lines: i64,
-        map_asm: &BTreeMap<u16, String>,
-    ) {
-        let mut n_line_y: i64 = ((n_lines.wrapping_shr(1)) * 10) + y;
-        let mut it_a = map_asm.range(..);
-
-        if let Some(instruction) = it_a.find(|(k, _v)| k == &pc) {
-            draw_text(instruction.1, x as f32, n_line_y as f32, 25.0, CYAN);
-            while n_line_y < ((n_lines * 10) + y) {
-                n_line_y += 17;
-                if let Some(instruction) = it_a.next() {
-                    draw_text(instruction.1, x as f32, n_line_y as f32, 25.0, WHITE);
-                }
+    // Differences between the CPU and the expected state and bus activity of one test
+    fn check_state(cpu: &Cpu, bus: &RamBus, test: &Value) -> Vec<String> {
+        let state = &test["final"];
+        let mut errors = Vec::new();
+
+        let registers = [
+            ("pc", cpu.pc as u64),
+            ("s", cpu.stkp as u64),
+            ("a", cpu.a as u64),
+            ("x", cpu.x as u64),
+            ("y", cpu.y as u64),
+            ("p", cpu.status as u64),
+        ];
+        for (name, value) in registers.iter() {
+            if *value != field(state, name) {
+                errors.push(format!(
+                    "{} is {:02X}, expected {:02X}",
+                    name,
+                    value,
+                    field(state, name)
+                ));
+            }
+        }
+
+        for entry in state["ram"].as_array().unwrap() {
+            let addr = entry[0].as_u64().unwrap() as usize;
+            let value = entry[1].as_u64().unwrap() as u8;
+            if bus.ram[addr] != value {
+                errors.push(format!(
+                    "${:04X} is {:02X}, expected {:02X}",
+                    addr, bus.ram[addr], value
+                ));
             }
         }
 
-        let mut n_line_y: i64 = ((n_lines.wrapping_shr(1)) * 10) + y;
-        let mut it_a = map_asm.range(..).rev();
-        if let Some(_) = it_a.find(|(k, _v)| k == &pc) {
-            while n_line_y > y {
-                n_line_y -= 17;
-                if let Some(instruction) = it_a.next() {
-                    draw_text(instruction.1, x as f32, n_line_y as f32, 25.0, WHITE);
+        let expected: Vec<(u16, u8, &str)> = test["cycles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| {
+                (
+                    c[0].as_u64().unwrap() as u16,
+                    c[1].as_u64().unwrap() as u8,
+                    c[2].as_str().unwrap(),
+                )
+            })
+            .collect();
+        if bus.cycles != expected {
+            errors.push(format!(
+                "bus activity {:X?}, expected {:X?}",
+                bus.cycles, expected
+            ));
+        }
+
+        errors
+    }
+
+    // Tom Harte's SingleStepTests for the 2A03 (nes6502/v1), one JSON file per opcode named
+    // like 6d.json, in the directory given by PROCESSOR_TESTS:
+    //   PROCESSOR_TESTS=path/to/nes6502/v1 cargo test processor_tests -- --ignored
+    #[test]
+    #[ignore]
+    fn processor_tests() {
+        let dir = env::var("PROCESSOR_TESTS").expect("PROCESSOR_TESTS is not set");
+
+        let mut cpu = Cpu::new();
+        let mut bus = RamBus {
+            ram: vec![0; 0x10000],
+            cycles: Vec::new(),
+        };
+        let mut failures = Vec::new();
+
+        for opcode in 0..=0xFF_u8 {
+            // JAM halts the CPU, which isn't modelled
+            if cpu.lookup[opcode as usize].name == "JAM" {
+                continue;
+            }
+
+            let path = Path::new(&dir).join(format!("{:02x}.json", opcode));
+            let json = match fs::read_to_string(&path) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            let tests: Value = serde_json::from_str(&json).unwrap();
+
+            for test in tests.as_array().unwrap() {
+                // Every address a test touches is in its initial state, so what earlier
+                // tests left in RAM doesn't matter
+                bus.cycles.clear();
+                load_state(&mut cpu, &mut bus, &test["initial"]);
+
+                cpu.clock(&mut bus);
+                while !cpu.complete() {
+                    cpu.clock(&mut bus);
+                }
+
+                for error in check_state(&cpu, &bus, test) {
+                    failures.push(format!("{}: {}", test["name"], error));
                 }
             }
         }
+
+        assert!(
+            failures.is_empty(),
+            "{} failures, starting with\n{}",
+            failures.len(),
+            failures[..failures.len().min(20)].join("\n")
+        );
+    }
+
+    fn load_image(path: &str, origin: usize) -> RamBus {
+        let image = fs::read(path).unwrap();
+        let mut ram = vec![0; 0x10000];
+        ram[origin..origin + image.len()].copy_from_slice(&image);
+
+        RamBus {
+            ram,
+            cycles: Vec::new(),
+        }
+    }
+
+    fn step(cpu: &mut Cpu, bus: &mut RamBus) {
+        cpu.clock(bus);
+        while !cpu.complete() {
+            cpu.clock(bus);
+        }
+        bus.cycles.clear();
+    }
+
+    // ADC or SBC #value with D set, returning A and the N, V, Z and C flags
+    fn decimal(model: CpuModel, opcode: u8, a: u8, value: u8, carry: bool) -> (u8, [u8; 4]) {
+        let mut bus = RamBus {
+            ram: vec![0; 0x10000],
+            cycles: Vec::new(),
+        };
+        bus.ram[0x0200..0x0202].copy_from_slice(&[opcode, value]);
+
+        let mut cpu = Cpu::new();
+        cpu.set_model(model);
+        cpu.pc = 0x0200;
+        cpu.a = a;
+        cpu.status = Flags::U as u8 | Flags::D as u8 | if carry { Flags::C as u8 } else { 0 };
+        step(&mut cpu, &mut bus);
+
+        let flags = [Flags::N, Flags::V, Flags::Z, Flags::C].map(|flag| cpu.get_flag(flag));
+        (cpu.a, flags)
+    }
+
+    #[test]
+    fn decimal_mode() {
+        let adc = |a, value, carry| decimal(CpuModel::Nmos6502, 0x69, a, value, carry);
+        let sbc = |a, value, carry| decimal(CpuModel::Nmos6502, 0xE9, a, value, carry);
+
+        // N and V come from the sum before the high digit is adjusted
+        assert_eq!(adc(0x58, 0x46, false), (0x04, [1, 1, 0, 1]));
+        assert_eq!(adc(0x79, 0x00, true), (0x80, [1, 1, 0, 0]));
+        assert_eq!(adc(0x12, 0x34, false), (0x46, [0, 0, 0, 0]));
+        // and Z from the binary sum, so a zero result can leave it clear and $60 can set it
+        assert_eq!(adc(0x99, 0x01, false), (0x00, [1, 0, 0, 1]));
+        assert_eq!(adc(0x50, 0xB0, false), (0x60, [0, 0, 1, 1]));
+
+        // SBC adjusts the result but keeps the binary flags
+        assert_eq!(sbc(0x46, 0x12, true), (0x34, [0, 0, 0, 1]));
+        assert_eq!(sbc(0x32, 0x02, false), (0x29, [0, 0, 0, 1]));
+        assert_eq!(sbc(0x12, 0x21, true), (0x91, [1, 0, 0, 0]));
+        assert_eq!(sbc(0x00, 0x00, true), (0x00, [0, 0, 1, 1]));
+        assert_eq!(sbc(0x00, 0x01, true), (0x99, [1, 0, 0, 0]));
+
+        // The 2A03 ignores D
+        assert_eq!(
+            decimal(CpuModel::Ricoh2A03, 0x69, 0x58, 0x46, false),
+            (0x9E, [1, 1, 0, 0])
+        );
+        assert_eq!(
+            decimal(CpuModel::Ricoh2A03, 0xE9, 0x12, 0x21, true),
+            (0xF1, [1, 0, 0, 0])
+        );
+    }
+
+    // Klaus Dormann's 6502_functional_test.bin in FUNCTIONAL_TEST, which traps in a jump to
+    // itself at $3469 on success or wherever a check failed:
+    //   FUNCTIONAL_TEST=path/to/6502_functional_test.bin cargo test functional_test -- --ignored
+    #[test]
+    #[ignore]
+    fn functional_test() {
+        let path = env::var("FUNCTIONAL_TEST").expect("FUNCTIONAL_TEST is not set");
+
+        let mut bus = load_image(&path, 0x0000);
+        let mut cpu = Cpu::new();
+        cpu.set_model(CpuModel::Nmos6502);
+        cpu.pc = 0x0400;
+
+        loop {
+            let pc = cpu.pc;
+            step(&mut cpu, &mut bus);
+            if cpu.pc == pc {
+                break;
+            }
+        }
+
+        assert_eq!(cpu.pc, 0x3469, "trapped at ${:04X}", cpu.pc);
+    }
+
+    // Bruce Clark's 6502_decimal_test.bin in DECIMAL_TEST, assembled for the 6502 at $0200. It
+    // ends on the $DB placed at DONE, with ERROR at $000B cleared if every result matched:
+    //   DECIMAL_TEST=path/to/6502_decimal_test.bin cargo test decimal_test -- --ignored
+    #[test]
+    #[ignore]
+    fn decimal_test() {
+        let path = env::var("DECIMAL_TEST").expect("DECIMAL_TEST is not set");
+
+        let mut bus = load_image(&path, 0x0200);
+        let mut cpu = Cpu::new();
+        cpu.set_model(CpuModel::Nmos6502);
+        cpu.pc = 0x0200;
+
+        while bus.peek(cpu.pc) != 0xDB {
+            step(&mut cpu, &mut bus);
+        }
+
+        assert_eq!(bus.ram[0x000B], 0, "decimal mode results differ");
+    }
+
+    // NOPs everywhere apart from `program` at $0200, with IRQs handled at $0300 and NMIs at
+    // $0400
+    fn interrupt_bus(program: &[u8]) -> (Cpu, RamBus) {
+        let mut bus = RamBus {
+            ram: vec![0xEA; 0x10000],
+            cycles: Vec::new(),
+        };
+        bus.ram[0x0200..0x0200 + program.len()].copy_from_slice(program);
+        bus.ram[0xFFFA..0xFFFC].copy_from_slice(&[0x00, 0x04]);
+        bus.ram[0xFFFE..].copy_from_slice(&[0x00, 0x03]);
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0200;
+        cpu.stkp = 0xFD;
+        cpu.status = Flags::U as u8;
+
+        (cpu, bus)
+    }
+
+    // Runs one instruction or interrupt sequence, leaving any interrupt it polled queued
+    fn instruction(cpu: &mut Cpu, bus: &mut RamBus) {
+        cpu.clock(bus);
+        while cpu.step != 0 {
+            cpu.clock(bus);
+        }
+    }
+
+    #[test]
+    fn interrupt_polling() {
+        // An IRQ seen by the second-to-last cycle is taken after the instruction
+        let (mut cpu, mut bus) = interrupt_bus(&[]);
+        cpu.set_irq(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0201, Some(Interrupt::Irq)));
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0300);
+        assert_eq!(cpu.get_flag(Flags::I), 1);
+
+        // Raised during the last cycle, it waits for the next instruction
+        let (mut cpu, mut bus) = interrupt_bus(&[]);
+        cpu.clock(&mut bus);
+        cpu.set_irq(true);
+        cpu.clock(&mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0201, None));
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0202, Some(Interrupt::Irq)));
+
+        // CLI only lets it in after the instruction that follows
+        let (mut cpu, mut bus) = interrupt_bus(&[0x58]);
+        cpu.status |= Flags::I as u8;
+        cpu.set_irq(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0201, None));
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0202, Some(Interrupt::Irq)));
+
+        // NMI is taken once per rising edge, however long the line stays high
+        let (mut cpu, mut bus) = interrupt_bus(&[]);
+        cpu.set_nmi(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.interrupt(), Some(Interrupt::Nmi));
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0401, None));
+    }
+
+    #[test]
+    fn interrupt_hijack() {
+        // An NMI arriving before BRK pushes the status sends it to the NMI vector, with B
+        // still set in the pushed status and the handler's first instruction run before any
+        // further interrupt
+        let (mut cpu, mut bus) = interrupt_bus(&[0x00]);
+        for _ in 0..3 {
+            cpu.clock(&mut bus);
+        }
+        cpu.set_nmi(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(&bus.ram[0x01FB..0x01FE], &[0x30, 0x02, 0x02]);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0401, None));
+
+        // One cycle later BRK goes through its own vector and the NMI follows the handler's
+        // first instruction
+        let (mut cpu, mut bus) = interrupt_bus(&[0x00]);
+        for _ in 0..4 {
+            cpu.clock(&mut bus);
+        }
+        cpu.set_nmi(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0300);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!((cpu.pc, cpu.interrupt()), (0x0301, Some(Interrupt::Nmi)));
+
+        // An IRQ sequence is hijacked the same way, pushing B clear
+        let (mut cpu, mut bus) = interrupt_bus(&[]);
+        cpu.set_irq(true);
+        instruction(&mut cpu, &mut bus);
+        cpu.clock(&mut bus);
+        cpu.set_nmi(true);
+        instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(bus.ram[0x01FB] & Flags::B as u8, 0);
     }
 }
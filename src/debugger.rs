@@ -0,0 +1,711 @@
+use std::fmt;
+
+use crate::{
+    bus::SystemBus,
+    cpu::{CpuBus, Interrupt},
+    Bus, Cartridge, Cpu, Ppu,
+};
+
+// Breakpoints, watchpoints and stepping. Nothing in here knows about the GUI, so anything
+// driving the emulator (the window, a script, a remote debugger) goes through the same core
+// by calling Debugger::clock in place of Bus::clock.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Space {
+    Cpu,
+    Ppu,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: u16,
+    pub data: u8,
+}
+
+// Bus accesses made during one clock, only kept while watchpoints need them
+#[derive(Default)]
+pub struct AccessLog {
+    pub enabled: bool,
+    pub accesses: Vec<Access>,
+}
+
+impl AccessLog {
+    pub fn record(&mut self, kind: AccessKind, addr: u16, data: u8) {
+        if self.enabled {
+            self.accesses.push(Access { kind, addr, data });
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+    Scanline,
+    Dot,
+    // Data and address of the access that hit a watchpoint
+    Value,
+    Address,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+// Breakpoint condition such as "A == #$10 && [$0300] > 4", where [addr] reads CPU memory
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Number(i32),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i32),
+    Name(String),
+    Op(&'static str),
+}
+
+// Longest first, so "<=" isn't read as "<"
+const OPERATORS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "&", "|", "(", ")", "[", "]",
+];
+
+// Binary operators from lowest to highest precedence
+const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    if !text.is_ascii() {
+        return Err("unexpected character".to_string());
+    }
+
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+
+        if c.is_ascii_whitespace() || c == '#' {
+            // '#' marks an immediate value as in assembly, and means nothing more here
+            i += 1;
+        } else if c == '$' || c == '%' || c.is_ascii_digit() {
+            let (radix, start) = match c {
+                '$' => (16, i + 1),
+                '%' => (2, i + 1),
+                _ => (10, i),
+            };
+            let mut end = start;
+            while end < bytes.len() && (bytes[end] as char).is_digit(radix) {
+                end += 1;
+            }
+            let value = i32::from_str_radix(&text[start..end], radix)
+                .map_err(|_| format!("bad number at {}", i))?;
+            tokens.push(Token::Number(value));
+            i = end;
+        } else if c.is_ascii_alphabetic() {
+            let start = i;
+            while i < bytes.len() && (bytes[i] as char).is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push(Token::Name(text[start..i].to_ascii_uppercase()));
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| text[i..].starts_with(*op))
+                .ok_or_else(|| format!("unexpected '{}' at {}", c, i))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(format!("expected '{}'", op)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Op(found)) = self.tokens.get(self.pos) {
+            let op = match LEVELS[level].iter().find(|(name, _)| name == found) {
+                Some((_, op)) => *op,
+                None => break,
+            };
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Name(name)) => Ok(Expr::Register(match name.as_str() {
+                "A" => Register::A,
+                "X" => Register::X,
+                "Y" => Register::Y,
+                "S" | "SP" => Register::Sp,
+                "P" => Register::P,
+                "PC" => Register::Pc,
+                "SCANLINE" => Register::Scanline,
+                "DOT" | "CYCLE" => Register::Dot,
+                "VALUE" => Register::Value,
+                "ADDR" | "ADDRESS" => Register::Address,
+                _ => return Err(format!("unknown name {}", name)),
+            })),
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op("-")) => Ok(Expr::Binary(
+                Box::new(Expr::Number(0)),
+                BinaryOp::Sub,
+                Box::new(self.unary()?),
+            )),
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Op("[")) => {
+                let expr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+
+        let expr = parser.binary(0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("unexpected {:?}", parser.tokens[parser.pos]));
+        }
+
+        Ok(expr)
+    }
+
+    // Comparisons and logic give 1 or 0, anything non-zero counts as true
+    pub fn eval(&self, cpu: &Cpu, access: Option<Access>, bus: &mut impl CpuBus) -> i32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => match register {
+                Register::A => cpu.a as i32,
+                Register::X => cpu.x as i32,
+                Register::Y => cpu.y as i32,
+                Register::Sp => cpu.stack_pointer() as i32,
+                Register::P => cpu.status() as i32,
+                Register::Pc => cpu.pc as i32,
+                Register::Scanline => bus.ppu_position().map_or(0, |(line, _)| line as i32),
+                Register::Dot => bus.ppu_position().map_or(0, |(_, dot)| dot as i32),
+                Register::Value => access.map_or(0, |access| access.data as i32),
+                Register::Address => access.map_or(0, |access| access.addr as i32),
+            },
+            Expr::Memory(addr) => {
+                let addr = addr.eval(cpu, access, bus);
+                bus.peek(addr as u16) as i32
+            }
+            Expr::Not(expr) => (expr.eval(cpu, access, bus) == 0) as i32,
+            Expr::Binary(left, op, right) => {
+                let left = left.eval(cpu, access, bus);
+                let right = right.eval(cpu, access, bus);
+                match op {
+                    BinaryOp::Or => (left != 0 || right != 0) as i32,
+                    BinaryOp::And => (left != 0 && right != 0) as i32,
+                    BinaryOp::Eq => (left == right) as i32,
+                    BinaryOp::Ne => (left != right) as i32,
+                    BinaryOp::Lt => (left < right) as i32,
+                    BinaryOp::Le => (left <= right) as i32,
+                    BinaryOp::Gt => (left > right) as i32,
+                    BinaryOp::Ge => (left >= right) as i32,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BreakKind {
+    Execute,
+    Read(Space),
+    Write(Space),
+}
+
+pub struct Breakpoint {
+    pub kind: BreakKind,
+    pub start: u16,
+    pub end: u16,
+    pub condition: Option<Expr>,
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    // "C000" or "8000-80FF" break on execution, "r:", "w:", "pr:" and "pw:" prefixes watch
+    // CPU or PPU reads and writes instead, and "... if CONDITION" adds a condition
+    pub fn parse(spec: &str) -> Result<Breakpoint, String> {
+        let (target, condition) = match spec.find(" if ") {
+            Some(i) => (&spec[..i], Some(Expr::parse(&spec[i + 4..])?)),
+            None => (spec, None),
+        };
+
+        let (kind, range) = match target.trim().split_once(':') {
+            Some(("r", range)) => (BreakKind::Read(Space::Cpu), range),
+            Some(("w", range)) => (BreakKind::Write(Space::Cpu), range),
+            Some(("pr", range)) => (BreakKind::Read(Space::Ppu), range),
+            Some(("pw", range)) => (BreakKind::Write(Space::Ppu), range),
+            Some((prefix, _)) => return Err(format!("unknown breakpoint type {}", prefix)),
+            None => (BreakKind::Execute, target.trim()),
+        };
+
+        let address = |text: &str| {
+            u16::from_str_radix(text.trim().trim_start_matches('$'), 16)
+                .map_err(|_| format!("bad address {}", text))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (address(start)?, address(end)?),
+            None => (address(range)?, address(range)?),
+        };
+
+        Ok(Breakpoint {
+            kind,
+            start,
+            end,
+            condition,
+            enabled: true,
+        })
+    }
+
+    fn hit(
+        &self,
+        kind: BreakKind,
+        addr: u16,
+        cpu: &Cpu,
+        access: Option<Access>,
+        bus: &mut impl CpuBus,
+    ) -> bool {
+        self.enabled
+            && self.kind == kind
+            && addr >= self.start
+            && addr <= self.end
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.eval(cpu, access, bus) != 0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BreakReason {
+    Breakpoint(usize),
+    Nmi,
+    Irq,
+    Brk,
+    Step,
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(i) => write!(f, "breakpoint {}", i),
+            BreakReason::Nmi => write!(f, "NMI"),
+            BreakReason::Irq => write!(f, "IRQ"),
+            BreakReason::Brk => write!(f, "BRK"),
+            BreakReason::Step => write!(f, "step"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum StepTarget {
+    Instruction,
+    // Return address of a JSR, reached with the stack back where it was
+    Over { addr: u16, stkp: u8 },
+    // An RTS or RTI that pops the stack above where it was
+    Out { stkp: u8 },
+    Scanline(i16),
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub break_on_nmi: bool,
+    pub break_on_irq: bool,
+    pub break_on_brk: bool,
+    target: Option<StepTarget>,
+    // Stop at the next instruction boundary, so the CPU state shown is always consistent
+    pending: Option<BreakReason>,
+    reason: Option<BreakReason>,
+    // Opcode of the instruction started at the last boundary
+    opcode: u8,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            break_on_nmi: false,
+            break_on_irq: false,
+            break_on_brk: false,
+            target: None,
+            pending: None,
+            reason: None,
+            opcode: 0x00,
+        }
+    }
+
+    // Adds a breakpoint in Breakpoint::parse syntax, or "nmi", "irq" or "brk"
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        match spec.trim().to_ascii_lowercase().as_str() {
+            "nmi" => self.break_on_nmi = true,
+            "irq" => self.break_on_irq = true,
+            "brk" => self.break_on_brk = true,
+            _ => self.breakpoints.push(Breakpoint::parse(spec)?),
+        }
+
+        Ok(())
+    }
+
+    // Why execution last stopped, until it is resumed
+    pub fn reason(&self) -> Option<BreakReason> {
+        self.reason
+    }
+
+    pub fn resume(&mut self) {
+        self.target = None;
+        self.pending = None;
+        self.reason = None;
+    }
+
+    pub fn step_instruction(&mut self) {
+        self.resume();
+        self.target = Some(StepTarget::Instruction);
+    }
+
+    // Runs a JSR and the subroutine it calls as if they were one instruction
+    pub fn step_over(&mut self, cpu: &Cpu, bus: &mut impl CpuBus) {
+        self.resume();
+        self.target = Some(if bus.peek(cpu.pc) == 0x20 {
+            StepTarget::Over {
+                addr: cpu.pc.wrapping_add(3),
+                stkp: cpu.stack_pointer(),
+            }
+        } else {
+            StepTarget::Instruction
+        });
+    }
+
+    // Runs until the current subroutine or interrupt handler returns
+    pub fn step_out(&mut self, cpu: &Cpu) {
+        self.resume();
+        self.target = Some(StepTarget::Out {
+            stkp: cpu.stack_pointer(),
+        });
+    }
+
+    pub fn run_to_scanline(&mut self, scanline: i16) {
+        self.resume();
+        self.target = Some(StepTarget::Scanline(scanline));
+    }
+
+    // Stands in for Bus::clock, returning true when execution should stop
+    pub fn clock(
+        &mut self,
+        cpu: &mut Cpu,
+        bus: &mut Bus,
+        ppu: &mut Ppu,
+        cart: &mut Cartridge,
+    ) -> bool {
+        let watching = self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.enabled && breakpoint.kind != BreakKind::Execute);
+        bus.access_log.enabled = watching;
+        ppu.access_log.get_mut().enabled = watching;
+
+        let was_complete = cpu.complete();
+        let was_interrupted = cpu.interrupt().is_some();
+
+        bus.clock(cpu, ppu, cart);
+
+        // Viewers read through the PPU between clocks, which shouldn't trigger anything
+        bus.access_log.enabled = false;
+        ppu.access_log.get_mut().enabled = false;
+        let cpu_accesses = std::mem::take(&mut bus.access_log.accesses);
+        let ppu_accesses = std::mem::take(&mut ppu.access_log.get_mut().accesses);
+
+        if !was_interrupted {
+            match cpu.interrupt() {
+                Some(Interrupt::Nmi) if self.break_on_nmi => {
+                    self.pending.get_or_insert(BreakReason::Nmi);
+                }
+                Some(Interrupt::Irq) if self.break_on_irq => {
+                    self.pending.get_or_insert(BreakReason::Irq);
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(StepTarget::Scanline(scanline)) = self.target {
+            if ppu.scanline == scanline && ppu.cycle == 0 {
+                self.pending.get_or_insert(BreakReason::Step);
+            }
+        }
+
+        let mut system = SystemBus { bus, ppu, cart };
+
+        for (space, accesses) in [(Space::Cpu, &cpu_accesses), (Space::Ppu, &ppu_accesses)].iter() {
+            for access in accesses.iter() {
+                let kind = match access.kind {
+                    AccessKind::Read => BreakKind::Read(*space),
+                    AccessKind::Write => BreakKind::Write(*space),
+                };
+                if let Some(i) = self.breakpoints.iter().position(|breakpoint| {
+                    breakpoint.hit(kind, access.addr, cpu, Some(*access), &mut system)
+                }) {
+                    self.pending.get_or_insert(BreakReason::Breakpoint(i));
+                }
+            }
+        }
+
+        if !was_complete && cpu.complete() {
+            self.boundary(cpu, &mut system)
+        } else {
+            false
+        }
+    }
+
+    fn boundary(&mut self, cpu: &Cpu, bus: &mut impl CpuBus) -> bool {
+        let finished = self.opcode;
+        self.opcode = bus.peek(cpu.pc);
+
+        let reason = match (self.pending.take(), self.target) {
+            (Some(reason), _) => Some(reason),
+            (None, Some(StepTarget::Instruction)) => Some(BreakReason::Step),
+            (None, Some(StepTarget::Over { addr, stkp }))
+                if cpu.pc == addr && cpu.stack_pointer() >= stkp =>
+            {
+                Some(BreakReason::Step)
+            }
+            (None, Some(StepTarget::Out { stkp }))
+                if (finished == 0x40 || finished == 0x60) && cpu.stack_pointer() > stkp =>
+            {
+                Some(BreakReason::Step)
+            }
+            _ if self.break_on_brk && self.opcode == 0x00 => Some(BreakReason::Brk),
+            _ => self
+                .breakpoints
+                .iter()
+                .position(|breakpoint| breakpoint.hit(BreakKind::Execute, cpu.pc, cpu, None, bus))
+                .map(BreakReason::Breakpoint),
+        };
+
+        match reason {
+            Some(reason) => {
+                self.reason = Some(reason);
+                self.target = None;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::nestest;
+
+    struct RamBus {
+        ram: Vec<u8>,
+    }
+
+    impl CpuBus for RamBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.ram[addr as usize] = data;
+        }
+
+        fn peek(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let mut cpu = Cpu::new();
+        let mut bus = RamBus {
+            ram: vec![0; 0x10000],
+        };
+        cpu.a = 0x10;
+        bus.ram[0x0300] = 5;
+
+        let eval = |text: &str, cpu: &Cpu, bus: &mut RamBus| {
+            Expr::parse(text).unwrap().eval(cpu, None, bus)
+        };
+        assert_eq!(eval("A == #$10 && [$0300] > 4", &cpu, &mut bus), 1);
+        assert_eq!(eval("A == #$10 && [$0300] > 5", &cpu, &mut bus), 0);
+        assert_eq!(eval("[$02FF + 1] - %101 || !(a != 16)", &cpu, &mut bus), 1);
+        assert_eq!(eval("1 + 2 & 3 == 3", &cpu, &mut bus), 1);
+
+        assert!(Expr::parse("A ==").is_err());
+        assert!(Expr::parse("[$0300").is_err());
+        assert!(Expr::parse("Q > 1").is_err());
+    }
+
+    #[test]
+    fn breakpoint_specs() {
+        let breakpoint = Breakpoint::parse("pw:2000-23FF if VALUE != 0").unwrap();
+        assert_eq!(breakpoint.kind, BreakKind::Write(Space::Ppu));
+        assert_eq!((breakpoint.start, breakpoint.end), (0x2000, 0x23FF));
+        assert!(breakpoint.condition.is_some());
+
+        let breakpoint = Breakpoint::parse("$C000").unwrap();
+        assert_eq!(breakpoint.kind, BreakKind::Execute);
+        assert_eq!((breakpoint.start, breakpoint.end), (0xC000, 0xC000));
+
+        assert!(Breakpoint::parse("x:C000").is_err());
+        assert!(Breakpoint::parse("r:zz").is_err());
+    }
+
+    fn run(
+        debugger: &mut Debugger,
+        cpu: &mut Cpu,
+        bus: &mut Bus,
+        ppu: &mut Ppu,
+        cart: &mut Cartridge,
+    ) -> (Option<BreakReason>, u16, u8) {
+        while !debugger.clock(cpu, bus, ppu, cart) {}
+        (debugger.reason(), cpu.pc, cpu.stack_pointer())
+    }
+
+    #[test]
+    fn stepping() {
+        let mut cart = nestest();
+        let mut bus = Bus::new();
+        let mut ppu = Ppu::new();
+        let mut cpu = Cpu::new();
+        let mut debugger = Debugger::new();
+
+        bus.reset(&mut cpu, &mut ppu, &mut cart);
+        while !cpu.complete() {
+            bus.clock(&mut cpu, &mut ppu, &mut cart);
+        }
+        cpu.pc = 0xC000;
+
+        debugger.add("C5FD").unwrap();
+        assert_eq!(
+            run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart),
+            (Some(BreakReason::Breakpoint(0)), 0xC5FD, 0xFD)
+        );
+
+        // JSR $C72D
+        debugger.step_over(
+            &cpu,
+            &mut SystemBus {
+                bus: &mut bus,
+                ppu: &mut ppu,
+                cart: &mut cart,
+            },
+        );
+        assert_eq!(
+            run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart),
+            (Some(BreakReason::Step), 0xC600, 0xFD)
+        );
+
+        // JSR $C7DB, then back out of it
+        debugger.step_instruction();
+        assert_eq!(
+            run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart),
+            (Some(BreakReason::Step), 0xC7DB, 0xFB)
+        );
+        debugger.step_out(&cpu);
+        assert_eq!(
+            run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart),
+            (Some(BreakReason::Step), 0xC603, 0xFD)
+        );
+
+        debugger.add("w:0000-07FF if VALUE == $FF").unwrap();
+        debugger.resume();
+        let (reason, _, _) = run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart);
+        assert_eq!(reason, Some(BreakReason::Breakpoint(1)));
+
+        debugger.breakpoints[1].enabled = false;
+        debugger.run_to_scanline(241);
+        run(&mut debugger, &mut cpu, &mut bus, &mut ppu, &mut cart);
+        assert_eq!(ppu.scanline, 241);
+    }
+}
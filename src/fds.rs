@@ -0,0 +1,254 @@
+// Famicom Disk System images. Both .fds (with or without the 16 byte fwNES header) and .qd
+// store each side as a bare sequence of blocks. The drive on the other hand sees gaps, a start
+// mark and a CRC around every block, so sides are expanded into that raw form when loaded and
+// packed back into the file layout when saved.
+
+const FDS_SIDE_SIZE: usize = 65500;
+const QD_SIDE_SIZE: usize = 65536;
+
+// 28300 bits of gap before the first block and 976 bits after every other one
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_START: u8 = 0x80;
+
+// Room for files written beyond the end of the original data
+const RAW_SIDE_SIZE: usize = LEAD_IN + FDS_SIDE_SIZE + 64 * BLOCK_GAP;
+
+pub fn is_disk_image(data: &[u8]) -> bool {
+    data.starts_with(b"FDS\x1A") || data.get(1..15) == Some(b"*NINTENDO-HVC*")
+}
+
+// CRC-16 as computed by the RAM adapter, over the start mark, the block and two zero bytes.
+// Running it over a block followed by its CRC leaves zero.
+pub fn crc_update(crc: u16, data: u8) -> u16 {
+    let mut crc = crc;
+
+    for bit in 0..8 {
+        let carry = (crc & 0x0001) > 0;
+        crc >>= 1;
+
+        if carry {
+            crc ^= 0x8408;
+        }
+
+        if (data >> bit) & 0x01 > 0 {
+            crc ^= 0x8000;
+        }
+    }
+
+    crc
+}
+
+fn block_length(block_type: u8, file_size: usize) -> Option<usize> {
+    match block_type {
+        // Disk info
+        1 => Some(56),
+        // File amount
+        2 => Some(2),
+        // File header
+        3 => Some(16),
+        // File data
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+pub struct DiskImage {
+    header: Vec<u8>,
+    quick_disk: bool,
+    pub sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    pub fn parse(data: &[u8]) -> DiskImage {
+        let (header, body) = if data.starts_with(b"FDS\x1A") {
+            data.split_at(16.min(data.len()))
+        } else {
+            data.split_at(0)
+        };
+
+        let quick_disk =
+            header.is_empty() && body.len() % QD_SIDE_SIZE == 0 && body.len() % FDS_SIDE_SIZE != 0;
+        let side_size = if quick_disk {
+            QD_SIDE_SIZE
+        } else {
+            FDS_SIDE_SIZE
+        };
+
+        let sides = body
+            .chunks(side_size)
+            .map(|side| DiskImage::expand_side(side, quick_disk))
+            .collect();
+
+        DiskImage {
+            header: header.to_vec(),
+            quick_disk,
+            sides,
+        }
+    }
+
+    // Back to the layout the image was loaded from
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.header.clone();
+
+        for side in &self.sides {
+            data.extend(self.pack_side(side));
+        }
+
+        data
+    }
+
+    fn expand_side(side: &[u8], quick_disk: bool) -> Vec<u8> {
+        let mut raw = vec![0_u8; LEAD_IN];
+        let mut pos = 0;
+        let mut file_size = 0;
+
+        while let Some(&block_type) = side.get(pos) {
+            let block = match block_length(block_type, file_size)
+                .and_then(|length| side.get(pos..pos + length))
+            {
+                Some(block) => block,
+                None => break,
+            };
+
+            if block_type == 3 {
+                file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+
+            let crc = [BLOCK_START]
+                .iter()
+                .chain(block)
+                .chain(&[0, 0])
+                .fold(0, |crc, &b| crc_update(crc, b));
+
+            raw.push(BLOCK_START);
+            raw.extend_from_slice(block);
+            raw.extend_from_slice(&crc.to_le_bytes());
+            raw.resize(raw.len() + BLOCK_GAP, 0);
+
+            // .qd images keep the CRC after each block
+            pos += block.len() + if quick_disk { 2 } else { 0 };
+        }
+
+        if raw.len() < RAW_SIDE_SIZE {
+            raw.resize(RAW_SIDE_SIZE, 0);
+        }
+
+        raw
+    }
+
+    fn pack_side(&self, raw: &[u8]) -> Vec<u8> {
+        let mut side = Vec::new();
+        let mut pos = 0;
+        let mut file_size = 0;
+
+        loop {
+            while raw.get(pos) == Some(&0) {
+                pos += 1;
+            }
+
+            if raw.get(pos) != Some(&BLOCK_START) {
+                break;
+            }
+            pos += 1;
+
+            let block = match raw
+                .get(pos)
+                .and_then(|&block_type| block_length(block_type, file_size))
+                .and_then(|length| raw.get(pos..pos + length + 2))
+            {
+                Some(block) => block,
+                None => break,
+            };
+
+            if block[0] == 3 {
+                file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+
+            let length = block.len() - 2;
+            side.extend_from_slice(if self.quick_disk {
+                block
+            } else {
+                &block[..length]
+            });
+            pos += block.len();
+        }
+
+        side.resize(
+            if self.quick_disk {
+                QD_SIDE_SIZE
+            } else {
+                FDS_SIDE_SIZE
+            },
+            0,
+        );
+
+        side
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // One side holding a single four byte file, in .fds layout with its fwNES header
+    pub fn image() -> Vec<u8> {
+        let mut side = vec![1];
+        side.extend_from_slice(b"*NINTENDO-HVC*");
+        side.resize(56, 0);
+        side.extend_from_slice(&[2, 1]);
+        let mut header = [0_u8; 16];
+        header[0] = 3;
+        header[3..11].copy_from_slice(b"FILENAME");
+        header[13] = 4;
+        side.extend_from_slice(&header);
+        side.extend_from_slice(&[4, 0xDE, 0xAD, 0xBE, 0xEF]);
+        side.resize(FDS_SIDE_SIZE, 0);
+
+        let mut image = b"FDS\x1A\x01".to_vec();
+        image.resize(16, 0);
+        image.extend(side);
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image();
+        let disk = DiskImage::parse(&image);
+        assert_eq!(disk.sides.len(), 1);
+        assert_eq!(disk.to_bytes(), image);
+
+        // Each block sits behind its start mark, with gaps between
+        let raw = &disk.sides[0];
+        assert!(raw[..LEAD_IN].iter().all(|&b| b == 0));
+        assert_eq!(&raw[LEAD_IN..LEAD_IN + 2], &[BLOCK_START, 1]);
+        let data = LEAD_IN + 3 * (3 + BLOCK_GAP) + 56 + 2 + 16 + 1;
+        assert_eq!(&raw[data..data + 5], &[4, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+        // The CRC stored after a block brings it back to zero
+        let block = &raw[LEAD_IN..LEAD_IN + 1 + 56 + 2];
+        assert_eq!(block.iter().fold(0, |crc, &b| crc_update(crc, b)), 0);
+        assert_ne!(block[57..], [0, 0]);
+
+        // A .qd image keeps those CRCs in the file
+        let mut qd = Vec::new();
+        let mut pos = 16;
+        for &length in [56, 2, 16, 5].iter() {
+            let block = &image[pos..pos + length];
+            let crc = [BLOCK_START]
+                .iter()
+                .chain(block)
+                .chain(&[0, 0])
+                .fold(0, |crc, &b| crc_update(crc, b));
+            qd.extend_from_slice(block);
+            qd.extend_from_slice(&crc.to_le_bytes());
+            pos += length;
+        }
+        qd.resize(QD_SIDE_SIZE, 0);
+
+        let disk = DiskImage::parse(&qd);
+        assert!(disk.quick_disk);
+        assert_eq!(&disk.sides[0][data..data + 5], &[4, 0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(disk.to_bytes(), qd);
+    }
+}
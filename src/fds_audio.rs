@@ -0,0 +1,398 @@
+// Famicom Disk System wavetable channel ($4040-$4097), used by both the FDS RAM adapter and
+// NSF playback. It is clocked once per CPU cycle: a 64 step wave RAM played at a pitch the
+// modulator bends through its own table, scaled by the volume envelope and master volume.
+
+const WAVE_VOLUME: [u32; 4] = [36, 24, 17, 14];
+const MOD_ADJUST: [i32; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+// The adapter's output goes through a low-pass at about 2kHz, 1 - e^(-2pi * 2000 / CPU rate)
+const LOW_PASS: f32 = 0.007;
+
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+    frequency: u16,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+            frequency: 0,
+        }
+    }
+
+    fn write_control(&mut self, data: u8, master_speed: u8) {
+        self.speed = data & 0x3F;
+        self.increase = (data & 0x40) > 0;
+        self.disabled = (data & 0x80) > 0;
+        self.reset_timer(master_speed);
+
+        if self.disabled {
+            self.gain = self.speed;
+        }
+    }
+
+    fn write_frequency_lo(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x0F00) | data as u16;
+    }
+
+    fn write_frequency_hi(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x0F) << 8);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn tick(&mut self, master_speed: u8) -> bool {
+        if !self.disabled && master_speed > 0 {
+            self.timer = self.timer.saturating_sub(1);
+
+            if self.timer == 0 {
+                self.reset_timer(master_speed);
+
+                if self.increase && self.gain < 32 {
+                    self.gain += 1;
+                } else if !self.increase && self.gain > 0 {
+                    self.gain -= 1;
+                }
+
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+pub struct FdsAudio {
+    wave_table: [u8; 64],
+    wave_write: bool,
+    wave_position: u8,
+    wave_accumulator: u16,
+    halt_wave: bool,
+    disable_envelopes: bool,
+    master_volume: u8,
+    master_speed: u8,
+    volume: Envelope,
+    modulator: Envelope,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    mod_counter: i32,
+    mod_accumulator: u16,
+    mod_disabled: bool,
+    mod_output: i32,
+    output: u8,
+    filtered: f32,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave_table: [0; 64],
+            wave_write: false,
+            wave_position: 0,
+            wave_accumulator: 0,
+            halt_wave: false,
+            disable_envelopes: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            volume: Envelope::new(),
+            modulator: Envelope::new(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_accumulator: 0,
+            mod_disabled: true,
+            mod_output: 0,
+            output: 0,
+            filtered: 0.0,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave_table[(addr & 0x3F) as usize] = data & 0x3F;
+            }
+            0x4080 => self.volume.write_control(data, self.master_speed),
+            0x4082 => self.volume.write_frequency_lo(data),
+            0x4083 => {
+                self.disable_envelopes = (data & 0x40) > 0;
+                self.halt_wave = (data & 0x80) > 0;
+
+                if self.disable_envelopes {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulator.reset_timer(self.master_speed);
+                }
+
+                self.volume.write_frequency_hi(data);
+            }
+            0x4084 => self.modulator.write_control(data, self.master_speed),
+            0x4085 => self.set_mod_counter((data & 0x7F) as i32),
+            0x4086 => self.modulator.write_frequency_lo(data),
+            0x4087 => {
+                self.modulator.write_frequency_hi(data);
+                self.mod_disabled = (data & 0x80) > 0;
+
+                if self.mod_disabled {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // The table is only writable while the modulator is halted, and each write fills
+            // two consecutive entries
+            0x4088 if self.mod_disabled => {
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position as usize] = data & 0x07;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.master_volume = data & 0x03;
+                self.wave_write = (data & 0x80) > 0;
+            }
+            0x408A => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    pub fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407F => self.wave_table[(addr & 0x3F) as usize],
+            0x4090 => self.volume.gain,
+            0x4092 => self.modulator.gain,
+            0x4096 => self.wave_table[self.wave_position as usize],
+            0x4097 => (self.mod_counter & 0x7F) as u8,
+            _ => 0x00,
+        }
+    }
+
+    pub fn clock(&mut self) {
+        let pitch = self.volume.frequency;
+
+        if !self.halt_wave && !self.disable_envelopes {
+            self.volume.tick(self.master_speed);
+
+            if self.modulator.tick(self.master_speed) {
+                self.update_mod_output(pitch);
+            }
+        }
+
+        if self.tick_modulator() {
+            self.update_mod_output(pitch);
+        }
+
+        if self.halt_wave {
+            self.wave_position = 0;
+            self.update_output();
+        } else {
+            self.update_output();
+
+            let step = pitch as i32 + self.mod_output;
+            if step > 0 && !self.wave_write {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(step as u16);
+                self.wave_accumulator = accumulator;
+
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+
+        self.filtered += (self.output as f32 / 63.0 - self.filtered) * LOW_PASS;
+    }
+
+    // Current 6 bit output level
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+
+    // Output level after the adapter's filter, 0.0..=1.0
+    pub fn sample(&self) -> f32 {
+        self.filtered
+    }
+
+    fn set_mod_counter(&mut self, value: i32) {
+        self.mod_counter = value;
+
+        if self.mod_counter >= 64 {
+            self.mod_counter -= 128;
+        } else if self.mod_counter < -64 {
+            self.mod_counter += 128;
+        }
+    }
+
+    fn tick_modulator(&mut self) -> bool {
+        if self.mod_disabled || self.modulator.frequency == 0 {
+            return false;
+        }
+
+        let (accumulator, overflow) = self
+            .mod_accumulator
+            .overflowing_add(self.modulator.frequency);
+        self.mod_accumulator = accumulator;
+
+        if overflow {
+            let entry = self.mod_table[self.mod_position as usize];
+            if entry == 4 {
+                self.set_mod_counter(0);
+            } else {
+                self.set_mod_counter(self.mod_counter + MOD_ADJUST[entry as usize]);
+            }
+
+            self.mod_position = (self.mod_position + 1) & 0x3F;
+        }
+
+        overflow
+    }
+
+    // Pitch bend from the modulator, as described on the NESdev wiki
+    fn update_mod_output(&mut self, pitch: u16) {
+        let mut temp = self.mod_counter * self.modulator.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+
+        if remainder > 0 && (temp & 0x80) == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= pitch as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        self.mod_output = temp;
+    }
+
+    fn update_output(&mut self) {
+        if self.wave_write {
+            return;
+        }
+
+        let level = (self.volume.gain.min(32) as u32) * WAVE_VOLUME[self.master_volume as usize];
+        self.output = ((self.wave_table[self.wave_position as usize] as u32 * level) / 1152) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(audio: &mut FdsAudio, cycles: usize) {
+        for _ in 0..cycles {
+            audio.clock();
+        }
+    }
+
+    // Wave RAM holding 0..63, full gain and master volume, playing one step per 32 cycles
+    fn ramp() -> FdsAudio {
+        let mut audio = FdsAudio::new();
+        audio.cpu_write(0x4089, 0x80);
+        for i in 0..64 {
+            audio.cpu_write(0x4040 + i, i as u8);
+        }
+        audio.cpu_write(0x4089, 0x00);
+        audio.cpu_write(0x4080, 0x80 | 32);
+        audio.cpu_write(0x4082, 0x00);
+        audio.cpu_write(0x4083, 0x08);
+        audio
+    }
+
+    #[test]
+    fn wave() {
+        let mut audio = ramp();
+        assert_eq!(audio.cpu_read(0x4045), 5);
+        // Wave RAM only takes writes while $4089 bit 7 holds the channel
+        audio.cpu_write(0x4045, 0x3F);
+        assert_eq!(audio.cpu_read(0x4045), 5);
+
+        clock(&mut audio, 32 * 5 + 1);
+        assert_eq!(audio.cpu_read(0x4096), 5);
+        assert_eq!(audio.output(), 5);
+
+        // Master volume 2/3 of the top of the ramp
+        clock(&mut audio, 32 * 58);
+        assert_eq!(audio.output(), 63);
+        audio.cpu_write(0x4089, 0x01);
+        audio.clock();
+        assert_eq!(audio.output(), 42);
+        assert!(audio.sample() > 0.0 && audio.sample() < 1.0);
+
+        // Halting the wave resets it to the first step
+        audio.cpu_write(0x4083, 0x80);
+        audio.clock();
+        assert_eq!(audio.cpu_read(0x4096), 0);
+        assert_eq!(audio.output(), 0);
+    }
+
+    #[test]
+    fn volume_envelope() {
+        let mut audio = ramp();
+        audio.cpu_write(0x408A, 0x01);
+        audio.cpu_write(0x4080, 0x80 | 10);
+        assert_eq!(audio.cpu_read(0x4090), 10);
+
+        // Decreasing at speed 0, one step every 8 * master speed cycles
+        audio.cpu_write(0x4080, 0x00);
+        clock(&mut audio, 8 * 3);
+        assert_eq!(audio.cpu_read(0x4090), 7);
+
+        // Increasing stops at 32
+        audio.cpu_write(0x4080, 0x40);
+        clock(&mut audio, 8 * 40);
+        assert_eq!(audio.cpu_read(0x4090), 32);
+
+        // $4083 bit 6 stops both envelopes
+        audio.cpu_write(0x4080, 0x00);
+        audio.cpu_write(0x4083, 0x48);
+        clock(&mut audio, 8 * 4);
+        assert_eq!(audio.cpu_read(0x4090), 32);
+    }
+
+    #[test]
+    fn modulator() {
+        let mut audio = ramp();
+        // The table only takes writes while the modulator is halted, each filling two entries
+        audio.cpu_write(0x4087, 0x80);
+        for _ in 0..16 {
+            audio.cpu_write(0x4088, 0x01);
+        }
+        audio.cpu_write(0x4088, 0x04);
+        for _ in 0..15 {
+            audio.cpu_write(0x4088, 0x07);
+        }
+        audio.cpu_write(0x4086, 0xFF);
+        audio.cpu_write(0x4087, 0x0F);
+        audio.cpu_write(0x4088, 0x02);
+
+        // $FFF steps the table every 16 cycles and a bit: +1 for 32 entries, two resets, then -1
+        clock(&mut audio, 16 * 32 + 1);
+        assert_eq!(audio.cpu_read(0x4097), 32);
+        clock(&mut audio, 16);
+        assert_eq!(audio.cpu_read(0x4097), 0);
+        clock(&mut audio, 16 * 3);
+        assert_eq!(audio.cpu_read(0x4097), 0x7E);
+
+        // The counter bends the pitch by the modulator's gain
+        audio.cpu_write(0x4084, 0x80 | 0x20);
+        audio.cpu_write(0x4085, 0x10);
+        clock(&mut audio, 16);
+        assert_ne!(audio.mod_output, 0);
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    bus::SystemBus,
+    cpu::CpuBus,
+    debugger::{BreakKind, Breakpoint, Debugger, Space},
+    Bus, Cartridge, Cpu, Ppu,
+};
+
+// GDB remote serial protocol stub, so gdb or any other RSP client can drive the emulator
+// over a local socket. Execution goes through the Debugger, so breakpoints set by the client
+// and on the command line behave the same.
+
+// Registers in the order 'g' and 'p' use them, all 8 bits except PC
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.6502.core">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8"/>
+    <reg name="y" bitsize="8"/>
+    <reg name="p" bitsize="8"/>
+    <reg name="sp" bitsize="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub<'a> {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    cpu: &'a mut Cpu,
+    bus: &'a mut Bus,
+    ppu: &'a mut Ppu,
+    cart: &'a mut Cartridge,
+    debugger: &'a mut Debugger,
+    // Last packet sent, resent when the client asks for it again
+    last: Vec<u8>,
+}
+
+// Waits for one client on localhost and serves it until it detaches or disconnects
+pub fn serve(
+    port: u16,
+    cpu: &mut Cpu,
+    bus: &mut Bus,
+    ppu: &mut Ppu,
+    cart: &mut Cartridge,
+    debugger: &mut Debugger,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for a debugger on port {}", port);
+
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(stream, cpu, bus, ppu, cart, debugger)?.run()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(
+        stream: TcpStream,
+        cpu: &'a mut Cpu,
+        bus: &'a mut Bus,
+        ppu: &'a mut Ppu,
+        cart: &'a mut Cartridge,
+        debugger: &'a mut Debugger,
+    ) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(GdbStub {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            cpu,
+            bus,
+            ppu,
+            cart,
+            debugger,
+            last: Vec::new(),
+        })
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            if !self.handle(&packet)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Next packet from the client, None once it has gone
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0_u8; 1];
+
+        loop {
+            // Acks, and interrupts that arrive while already stopped, are dropped here
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'$' => break,
+                    b'-' => self.writer.write_all(&self.last)?,
+                    _ => (),
+                }
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let mut sum = [0_u8; 2];
+            self.reader.read_exact(&mut sum)?;
+            let sum = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+            if sum == Some(checksum(&data)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        self.last = format!("${}#{:02x}", reply, checksum(reply.as_bytes())).into_bytes();
+        self.writer.write_all(&self.last)
+    }
+
+    // Handles one packet, returning false when the session is over
+    fn handle(&mut self, packet: &str) -> io::Result<bool> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self
+                .registers()
+                .iter()
+                .map(|value| value.1.clone())
+                .collect(),
+            "G" => self.write_registers(args),
+            "p" => match hex(args).and_then(|n| self.registers().get(n as usize).cloned()) {
+                Some((_, value)) => value,
+                None => "E01".to_string(),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "c" | "s" => {
+                if let Some(addr) = hex(args) {
+                    self.cpu.pc = addr;
+                }
+                if command == "s" {
+                    self.debugger.step_instruction();
+                } else {
+                    self.debugger.resume();
+                }
+                format!("S{:02x}", self.resume()?)
+            }
+            "k" => return Ok(false),
+            "D" => {
+                self.send("OK")?;
+                return Ok(false);
+            }
+            "H" => "OK".to_string(),
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+
+        self.send(&reply)?;
+        Ok(true)
+    }
+
+    // Hex encoded registers as sent over the wire, with their widths in bytes
+    fn registers(&self) -> Vec<(usize, String)> {
+        let pc = self.cpu.pc.to_le_bytes();
+
+        vec![
+            (1, format!("{:02x}", self.cpu.a)),
+            (1, format!("{:02x}", self.cpu.x)),
+            (1, format!("{:02x}", self.cpu.y)),
+            (1, format!("{:02x}", self.cpu.status())),
+            (1, format!("{:02x}", self.cpu.stack_pointer())),
+            (2, format!("{:02x}{:02x}", pc[0], pc[1])),
+        ]
+    }
+
+    fn set_register(&mut self, n: usize, bytes: &[u8]) {
+        match n {
+            0 => self.cpu.a = bytes[0],
+            1 => self.cpu.x = bytes[0],
+            2 => self.cpu.y = bytes[0],
+            3 => self.cpu.set_status(bytes[0]),
+            4 => self.cpu.set_stack_pointer(bytes[0]),
+            _ => self.cpu.pc = u16::from_le_bytes([bytes[0], bytes[1]]),
+        }
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let widths: Vec<usize> = self.registers().iter().map(|value| value.0).collect();
+        match hex_bytes(args) {
+            Some(bytes) if bytes.len() == widths.iter().sum::<usize>() => {
+                let mut offset = 0;
+                for (n, width) in widths.iter().enumerate() {
+                    self.set_register(n, &bytes[offset..offset + width]);
+                    offset += width;
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let widths: Vec<usize> = self.registers().iter().map(|value| value.0).collect();
+        let register = args
+            .split_once('=')
+            .and_then(|(n, value)| Some((hex(n)? as usize, hex_bytes(value)?)));
+
+        match register {
+            Some((n, bytes)) if widths.get(n) == Some(&bytes.len()) => {
+                self.set_register(n, &bytes);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn system_bus(&mut self) -> SystemBus<'_> {
+        SystemBus {
+            bus: self.bus,
+            ppu: self.ppu,
+            cart: self.cart,
+        }
+    }
+
+    // Reads go through peek, so looking at registers like $2002 doesn't disturb them
+    fn read_memory(&mut self, args: &str) -> String {
+        let range = args
+            .split_once(',')
+            .and_then(|(addr, len)| Some((hex(addr)?, hex(len)?)));
+
+        match range {
+            Some((addr, len)) => {
+                let mut bus = self.system_bus();
+                (0..len)
+                    .map(|i| format!("{:02x}", bus.peek(addr.wrapping_add(i))))
+                    .collect()
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let write = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = range.split_once(',')?;
+            Some((hex(addr)?, hex(len)?, hex_bytes(data)?))
+        });
+
+        match write {
+            Some((addr, len, data)) if data.len() == len as usize => {
+                let mut bus = self.system_bus();
+                for (i, byte) in data.iter().enumerate() {
+                    bus.write(addr.wrapping_add(i as u16), *byte);
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // Z0 and Z1 are execution breakpoints, Z2 to Z4 watch writes, reads or both
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let parsed = match args.split(',').collect::<Vec<_>>()[..] {
+            [kind, addr, len] => hex(addr).zip(hex(len)).map(|(addr, len)| (kind, addr, len)),
+            _ => None,
+        };
+
+        let (kinds, start, end) = match parsed {
+            Some(("0", addr, _)) | Some(("1", addr, _)) => (vec![BreakKind::Execute], addr, addr),
+            Some((kind, addr, len)) => {
+                let kinds = match kind {
+                    "2" => vec![BreakKind::Write(Space::Cpu)],
+                    "3" => vec![BreakKind::Read(Space::Cpu)],
+                    "4" => vec![BreakKind::Read(Space::Cpu), BreakKind::Write(Space::Cpu)],
+                    _ => return String::new(),
+                };
+                (kinds, addr, addr.wrapping_add(len.max(1) - 1))
+            }
+            None => return "E01".to_string(),
+        };
+
+        for kind in kinds {
+            if insert {
+                self.debugger.breakpoints.push(Breakpoint {
+                    kind,
+                    start,
+                    end,
+                    condition: None,
+                    enabled: true,
+                });
+            } else {
+                self.debugger.breakpoints.retain(|breakpoint| {
+                    (breakpoint.kind, breakpoint.start, breakpoint.end) != (kind, start, end)
+                });
+            }
+        }
+
+        "OK".to_string()
+    }
+
+    fn query(&mut self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+        if args == "Attached" {
+            return "1".to_string();
+        }
+
+        match args.strip_prefix("Xfer:features:read:target.xml:") {
+            Some(range) => match range.split_once(',').and_then(|(offset, len)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(len, 16).ok()?,
+                ))
+            }) {
+                Some((offset, len)) => {
+                    let rest = TARGET_XML.get(offset..).unwrap_or("");
+                    if rest.len() > len {
+                        format!("m{}", &rest[..len])
+                    } else {
+                        format!("l{}", rest)
+                    }
+                }
+                None => "E01".to_string(),
+            },
+            None => String::new(),
+        }
+    }
+
+    // Runs until the debugger stops, returning the signal to report. A bare 0x03 from the
+    // client interrupts, checked once a frame so polling the socket doesn't slow things down.
+    fn resume(&mut self) -> io::Result<u8> {
+        let mut signal = SIGTRAP;
+
+        self.reader.get_ref().set_nonblocking(true)?;
+        while !self.debugger.clock(self.cpu, self.bus, self.ppu, self.cart) {
+            if !self.ppu.frame_complete || signal == SIGINT {
+                continue;
+            }
+            self.ppu.frame_complete = false;
+
+            let interrupted = match self.reader.fill_buf() {
+                // Gone, so stop and let the next read notice
+                Ok([]) => true,
+                Ok(bytes) => bytes[0] == 0x03,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+                Err(e) => return Err(e),
+            };
+            if interrupted {
+                if !self.reader.buffer().is_empty() {
+                    self.reader.consume(1);
+                }
+                // Finish the instruction first so the registers make sense
+                self.debugger.step_instruction();
+                signal = SIGINT;
+            }
+        }
+        self.reader.get_ref().set_nonblocking(false)?;
+
+        Ok(signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::nestest;
+    use std::thread;
+
+    fn request(reader: &mut BufReader<TcpStream>, packet: &str) {
+        let frame = format!("${}#{:02x}", packet, checksum(packet.as_bytes()));
+        reader.get_mut().write_all(frame.as_bytes()).unwrap();
+
+        let mut ack = [0_u8; 1];
+        reader.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+    }
+
+    fn exchange(reader: &mut BufReader<TcpStream>, packet: &str) -> String {
+        request(reader, packet);
+        reply(reader)
+    }
+
+    fn reply(reader: &mut BufReader<TcpStream>) -> String {
+        let mut reply = Vec::new();
+        reader.read_until(b'$', &mut reply).unwrap();
+        reply.clear();
+        reader.read_until(b'#', &mut reply).unwrap();
+        reply.pop();
+        let mut sum = [0_u8; 2];
+        reader.read_exact(&mut sum).unwrap();
+        reader.get_mut().write_all(b"+").unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn session() {
+        let mut cart = nestest();
+        let mut bus = Bus::new();
+        let mut ppu = Ppu::new();
+        let mut cpu = Cpu::new();
+        let mut debugger = Debugger::new();
+
+        bus.reset(&mut cpu, &mut ppu, &mut cart);
+        while !cpu.complete() {
+            bus.clock(&mut cpu, &mut ppu, &mut cart);
+        }
+        cpu.pc = 0xC000;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+
+                assert!(exchange(&mut client, "qSupported:xmlRegisters=i386").contains("qXfer"));
+                assert_eq!(exchange(&mut client, "?"), "S05");
+                assert_eq!(exchange(&mut client, "g"), "00000024fd00c0");
+                assert_eq!(exchange(&mut client, "mc000,3"), "4cf5c5");
+                assert_eq!(exchange(&mut client, "Z0,c5fd,1"), "OK");
+                assert_eq!(exchange(&mut client, "c"), "S05");
+                assert_eq!(exchange(&mut client, "p5"), "fdc5");
+                assert_eq!(exchange(&mut client, "z0,c5fd,1"), "OK");
+                assert_eq!(exchange(&mut client, "s"), "S05");
+                assert_eq!(exchange(&mut client, "p5"), "2dc7");
+                assert_eq!(exchange(&mut client, "p4"), "fb");
+                assert_eq!(exchange(&mut client, "M0300,2:abcd"), "OK");
+                assert_eq!(exchange(&mut client, "m0300,2"), "abcd");
+                assert_eq!(exchange(&mut client, "P0=42"), "OK");
+                assert_eq!(exchange(&mut client, "p0"), "42");
+
+                // Interrupted from the client while running
+                request(&mut client, "c");
+                client.get_mut().write_all(b"\x03").unwrap();
+                assert_eq!(reply(&mut client), "S02");
+                assert_eq!(exchange(&mut client, "D"), "OK");
+            });
+
+            let (stream, _) = listener.accept().unwrap();
+            GdbStub::new(
+                stream,
+                &mut cpu,
+                &mut bus,
+                &mut ppu,
+                &mut cart,
+                &mut debugger,
+            )
+            .unwrap()
+            .run()
+            .unwrap();
+        });
+    }
+}
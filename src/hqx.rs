@@ -0,0 +1,427 @@
+use crate::scaler::Image;
+
+// hq2x and hq4x, Maxim Stepin's pattern scalers. Each output block is picked from which of the
+// eight neighbours differ from the centre pixel in YUV. His tables are symmetric, so the rules
+// below are written for the top left quarter of the block, with the neighbourhood flipped so
+// that the quarter being filled is always top left.
+
+// Neighbourhood positions, row by row
+const TL: usize = 0;
+const T: usize = 1;
+const L: usize = 3;
+const C: usize = 4;
+const R: usize = 5;
+const B: usize = 7;
+
+// n is 2 or 4
+pub fn scale(image: &Image, n: usize) -> Image {
+    let mut out = Image::new(image.width * n, image.height * n);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let mut pixels = [0; 9];
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let (dx, dy) = ((i % 3) as isize - 1, (i / 3) as isize - 1);
+                *pixel = image.get(x as isize + dx, y as isize + dy);
+            }
+            let colours = pixels.map(yuv);
+
+            for &(flip_x, flip_y) in
+                [(false, false), (true, false), (false, true), (true, true)].iter()
+            {
+                let quarter = Quarter::new(&pixels, &colours, flip_x, flip_y);
+                let place = |out: &mut Image, row: usize, col: usize, pixel: u32| {
+                    let col = if flip_x { n - 1 - col } else { col };
+                    let row = if flip_y { n - 1 - row } else { row };
+                    out.set(x * n + col, y * n + row, pixel);
+                };
+
+                if n == 2 {
+                    place(&mut out, 0, 0, hq2x(&quarter));
+                } else {
+                    for (i, &pixel) in hq4x(&quarter).iter().enumerate() {
+                        place(&mut out, i / 2, i % 2, pixel);
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+type Yuv = (i32, i32, i32);
+
+fn yuv(pixel: u32) -> Yuv {
+    let [r, g, b, _] = pixel.to_le_bytes();
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    (
+        (0.299 * r + 0.587 * g + 0.114 * b) as i32,
+        (-0.169 * r - 0.331 * g + 0.5 * b + 128.0) as i32,
+        (0.5 * r - 0.419 * g - 0.081 * b + 128.0) as i32,
+    )
+}
+
+fn differ((y1, u1, v1): Yuv, (y2, u2, v2): Yuv) -> bool {
+    (y1 - y2).abs() > 48 || (u1 - u2).abs() > 7 || (v1 - v2).abs() > 6
+}
+
+// The neighbourhood as seen from one quarter of the output block
+struct Quarter {
+    w: [u32; 9],
+    yuv: [Yuv; 9],
+    // Bit per neighbour, in position order skipping the centre, set where it differs
+    pattern: u8,
+}
+
+impl Quarter {
+    fn new(pixels: &[u32; 9], colours: &[Yuv; 9], flip_x: bool, flip_y: bool) -> Quarter {
+        let mut quarter = Quarter {
+            w: [0; 9],
+            yuv: [(0, 0, 0); 9],
+            pattern: 0,
+        };
+        for i in 0..9 {
+            let (col, row) = (i % 3, i / 3);
+            let col = if flip_x { 2 - col } else { col };
+            let row = if flip_y { 2 - row } else { row };
+            quarter.w[i] = pixels[row * 3 + col];
+            quarter.yuv[i] = colours[row * 3 + col];
+        }
+
+        for (bit, i) in [0, 1, 2, 3, 5, 6, 7, 8].iter().enumerate() {
+            if differ(quarter.yuv[*i], quarter.yuv[C]) {
+                quarter.pattern |= 1 << bit;
+            }
+        }
+
+        quarter
+    }
+
+    // Whether the pattern, masked, matches any of the (mask, bits) pairs
+    fn any(&self, patterns: &[(u8, u8)]) -> bool {
+        patterns
+            .iter()
+            .any(|&(mask, bits)| self.pattern & mask == bits)
+    }
+
+    fn differ(&self, a: usize, b: usize) -> bool {
+        differ(self.yuv[a], self.yuv[b])
+    }
+}
+
+// Weighted sum of each channel, shifted down by the weights' total
+fn interp(parts: &[(u32, u32)], shift: u32) -> u32 {
+    let mut out = [0; 4];
+    for (channel, out) in out.iter_mut().enumerate() {
+        let sum: u32 = parts
+            .iter()
+            .map(|&(pixel, weight)| pixel.to_le_bytes()[channel] as u32 * weight)
+            .sum();
+        *out = (sum >> shift) as u8;
+    }
+
+    u32::from_le_bytes(out)
+}
+
+// The top left of the 2x2 block
+fn hq2x(q: &Quarter) -> u32 {
+    let (tl, t, l, c) = (q.w[TL], q.w[T], q.w[L], q.w[C]);
+
+    if q.any(&[(0xbf, 0x37), (0xdb, 0x13)]) && q.differ(T, R) {
+        interp(&[(c, 3), (l, 1)], 2)
+    } else if q.any(&[(0xdb, 0x49), (0xef, 0x6d)]) && q.differ(B, L) {
+        interp(&[(c, 3), (t, 1)], 2)
+    } else if q.any(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && q.differ(L, T) {
+        c
+    } else if q.any(&[
+        (0x6f, 0x2a),
+        (0x5b, 0x0a),
+        (0xbf, 0x3a),
+        (0xdf, 0x5a),
+        (0x9f, 0x8a),
+        (0xcf, 0x8a),
+        (0xef, 0x4e),
+        (0x3f, 0x0e),
+        (0xfb, 0x5a),
+        (0xbb, 0x8a),
+        (0x7f, 0x5a),
+        (0xaf, 0x8a),
+        (0xeb, 0x8a),
+    ]) && q.differ(L, T)
+    {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if q.any(&[(0x0b, 0x08)]) {
+        interp(&[(c, 2), (tl, 1), (t, 1)], 2)
+    } else if q.any(&[(0x0b, 0x02)]) {
+        interp(&[(c, 2), (tl, 1), (l, 1)], 2)
+    } else if q.any(&[(0x2f, 0x2f)]) {
+        interp(&[(c, 14), (l, 1), (t, 1)], 4)
+    } else if q.any(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+        interp(&[(c, 5), (t, 2), (l, 1)], 3)
+    } else if q.any(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+        interp(&[(c, 5), (l, 2), (t, 1)], 3)
+    } else if q.any(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        interp(&[(c, 3), (l, 1)], 2)
+    } else if q.any(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        interp(&[(c, 3), (t, 1)], 2)
+    } else if q.any(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        interp(&[(c, 2), (l, 3), (t, 3)], 3)
+    } else if q.any(&[
+        (0xfb, 0x6a),
+        (0x6f, 0x6e),
+        (0x3f, 0x3e),
+        (0xfb, 0xfa),
+        (0xdf, 0xde),
+        (0xdf, 0x1e),
+    ]) {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if q.any(&[
+        (0x0a, 0x00),
+        (0x4f, 0x4b),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0xee, 0x0a),
+        (0x7e, 0x0a),
+        (0xeb, 0x4b),
+        (0x3b, 0x1b),
+    ]) {
+        interp(&[(c, 2), (l, 1), (t, 1)], 2)
+    } else {
+        interp(&[(c, 6), (l, 1), (t, 1)], 3)
+    }
+}
+
+// The top left 2x2 of the 4x4 block, row by row
+fn hq4x(q: &Quarter) -> [u32; 4] {
+    let (tl, t, l, c) = (q.w[TL], q.w[T], q.w[L], q.w[C]);
+
+    let cond00 = q.any(&[(0xbf, 0x37), (0xdb, 0x13)]) && q.differ(T, R);
+    let cond01 = q.any(&[(0xdb, 0x49), (0xef, 0x6d)]) && q.differ(B, L);
+    let cond02 = q.any(&[
+        (0x6f, 0x2a),
+        (0x5b, 0x0a),
+        (0xbf, 0x3a),
+        (0xdf, 0x5a),
+        (0x9f, 0x8a),
+        (0xcf, 0x8a),
+        (0xef, 0x4e),
+        (0x3f, 0x0e),
+        (0xfb, 0x5a),
+        (0xbb, 0x8a),
+        (0x7f, 0x5a),
+        (0xaf, 0x8a),
+        (0xeb, 0x8a),
+    ]) && q.differ(L, T);
+    let cond03 = q.any(&[(0xdb, 0x49), (0xef, 0x6d)]);
+    let cond04 = q.any(&[(0xbf, 0x37), (0xdb, 0x13)]);
+    let cond05 = q.any(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]);
+    let cond06 = q.any(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]);
+    let cond07 = q.any(&[
+        (0x0b, 0x08),
+        (0xf9, 0x68),
+        (0xf3, 0x62),
+        (0x6d, 0x6c),
+        (0x67, 0x66),
+        (0x3d, 0x3c),
+        (0x37, 0x36),
+        (0xf9, 0xf8),
+        (0xdd, 0xdc),
+        (0xf3, 0xf2),
+        (0xd7, 0xd6),
+        (0xdd, 0x1c),
+        (0xd7, 0x16),
+        (0x0b, 0x02),
+    ]);
+    let cond08 = q.any(&[(0x0f, 0x0b), (0x2b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && q.differ(L, T);
+    let cond09 = q.any(&[(0x2f, 0x2f)]);
+    let cond10 = q.any(&[(0x0a, 0x00)]);
+    let cond11 = q.any(&[(0x0b, 0x09)]);
+    let cond12 = q.any(&[(0x7e, 0x2a), (0xef, 0xab)]);
+    let cond13 = q.any(&[(0xbf, 0x8f), (0x7e, 0x0e)]);
+    let cond14 = q.any(&[
+        (0x4f, 0x4b),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0xee, 0x0a),
+        (0x7e, 0x0a),
+        (0xeb, 0x4b),
+        (0x3b, 0x1b),
+    ]);
+    let cond15 = q.any(&[(0x0b, 0x03)]);
+
+    let top_left = if cond00 {
+        interp(&[(c, 5), (l, 3)], 3)
+    } else if cond01 {
+        interp(&[(c, 5), (t, 3)], 3)
+    } else if q.any(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && q.differ(L, T) {
+        c
+    } else if cond02 {
+        interp(&[(c, 5), (tl, 3)], 3)
+    } else if cond03 {
+        interp(&[(c, 3), (l, 1)], 2)
+    } else if cond04 {
+        interp(&[(c, 3), (t, 1)], 2)
+    } else if cond05 {
+        interp(&[(c, 5), (l, 3)], 3)
+    } else if cond06 {
+        interp(&[(c, 5), (t, 3)], 3)
+    } else if q.any(&[
+        (0x0f, 0x0b),
+        (0x5e, 0x0a),
+        (0x2b, 0x0b),
+        (0xbe, 0x0a),
+        (0x7a, 0x0a),
+        (0xee, 0x0a),
+    ]) {
+        interp(&[(t, 1), (l, 1)], 1)
+    } else if cond07 {
+        interp(&[(c, 5), (tl, 3)], 3)
+    } else {
+        interp(&[(c, 2), (t, 1), (l, 1)], 2)
+    };
+
+    let top_right = if cond00 {
+        interp(&[(c, 7), (l, 1)], 3)
+    } else if cond08 {
+        c
+    } else if cond02 {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if cond09 {
+        c
+    } else if cond10 {
+        interp(&[(c, 5), (t, 2), (l, 1)], 3)
+    } else if q.any(&[(0x0b, 0x08)]) {
+        interp(&[(c, 5), (t, 2), (tl, 1)], 3)
+    } else if cond11 {
+        interp(&[(c, 5), (t, 3)], 3)
+    } else if cond04 {
+        interp(&[(t, 3), (c, 1)], 2)
+    } else if cond12 {
+        interp(&[(t, 2), (c, 1), (l, 1)], 2)
+    } else if cond13 {
+        interp(&[(t, 5), (l, 3)], 3)
+    } else if cond05 {
+        interp(&[(c, 7), (l, 1)], 3)
+    } else if q.any(&[
+        (0xf3, 0x62),
+        (0x67, 0x66),
+        (0x37, 0x36),
+        (0xf3, 0xf2),
+        (0xd7, 0xd6),
+        (0xd7, 0x16),
+        (0x0b, 0x02),
+    ]) {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if cond14 {
+        interp(&[(t, 1), (c, 1)], 1)
+    } else {
+        interp(&[(c, 3), (t, 1)], 2)
+    };
+
+    let bottom_left = if cond01 {
+        interp(&[(c, 7), (t, 1)], 3)
+    } else if cond08 {
+        c
+    } else if cond02 {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if cond09 {
+        c
+    } else if cond10 {
+        interp(&[(c, 5), (l, 2), (t, 1)], 3)
+    } else if q.any(&[(0x0b, 0x02)]) {
+        interp(&[(c, 5), (l, 2), (tl, 1)], 3)
+    } else if cond15 {
+        interp(&[(c, 5), (l, 3)], 3)
+    } else if cond03 {
+        interp(&[(l, 3), (c, 1)], 2)
+    } else if cond13 {
+        interp(&[(l, 2), (c, 1), (t, 1)], 2)
+    } else if cond12 {
+        interp(&[(l, 5), (t, 3)], 3)
+    } else if cond06 {
+        interp(&[(c, 7), (t, 1)], 3)
+    } else if q.any(&[
+        (0x0b, 0x08),
+        (0xf9, 0x68),
+        (0x6d, 0x6c),
+        (0x3d, 0x3c),
+        (0xf9, 0xf8),
+        (0xdd, 0xdc),
+        (0xdd, 0x1c),
+    ]) {
+        interp(&[(c, 3), (tl, 1)], 2)
+    } else if cond14 {
+        interp(&[(l, 1), (c, 1)], 1)
+    } else {
+        interp(&[(c, 3), (l, 1)], 2)
+    };
+
+    let bottom_right =
+        if q.any(&[(0x7f, 0x2b), (0xef, 0xab), (0xbf, 0x8f), (0x7f, 0x0f)]) && q.differ(L, T) {
+            c
+        } else if cond02 {
+            interp(&[(c, 7), (tl, 1)], 3)
+        } else if cond15 {
+            interp(&[(c, 7), (l, 1)], 3)
+        } else if cond11 {
+            interp(&[(c, 7), (t, 1)], 3)
+        } else if q.any(&[
+            (0x0a, 0x00),
+            (0x7e, 0x2a),
+            (0xef, 0xab),
+            (0xbf, 0x8f),
+            (0x7e, 0x0e),
+        ]) {
+            interp(&[(c, 6), (l, 1), (t, 1)], 3)
+        } else if cond07 {
+            interp(&[(c, 7), (tl, 1)], 3)
+        } else {
+            c
+        };
+
+    [top_left, top_right, bottom_left, bottom_right]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric() {
+        // Scaling a transposed picture gives the transposed result, so the rules written for
+        // the top left agree with themselves mirrored about the diagonal
+        let colours = [
+            0xFF00_0000,
+            0xFFFF_FFFF,
+            0xFF20_A040,
+            0xFF28_A848,
+            0xFFC0_4010,
+        ];
+        let mut seed = 12345u32;
+        let mut image = Image::new(12, 12);
+        for pixel in image.pixels.iter_mut() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *pixel = colours[(seed >> 16) as usize % colours.len()];
+        }
+        let transpose = |image: &Image| {
+            let mut out = Image::new(image.height, image.width);
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    out.set(y, x, image.pixels[y * image.width + x]);
+                }
+            }
+            out
+        };
+
+        for &n in [2, 4].iter() {
+            let scaled = scale(&image, n);
+            assert_eq!(
+                transpose(&scaled).pixels,
+                scale(&transpose(&image), n).pixels
+            );
+            assert!(scaled.pixels.iter().any(|p| !colours.contains(p)));
+        }
+    }
+}
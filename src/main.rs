@@ -1,16 +1,46 @@
 use macroquad::prelude::*;
-use std::collections::BTreeMap;
+use std::{fs::File, io::BufWriter, path::Path};
+mod archive;
+mod audio;
 mod bus;
+mod fds;
+mod fds_audio;
 mod mapper;
 mod mapper_000;
 mod mapper_004;
-use bus::Bus;
+mod mapper_020;
+mod mapper_nsf;
+use bus::{Bus, SystemBus};
 mod cpu;
 use cpu::Cpu;
 mod ppu;
 use ppu::{Debug, Ppu};
 mod cartridge;
 use cartridge::Cartridge;
+mod config;
+use config::{Config, PaletteSource};
+use palette::NtscSettings;
+mod debugger;
+use debugger::Debugger;
+mod gdb;
+mod hqx;
+mod nsf;
+mod nsf_player;
+use nsf_player::NsfPlayer;
+mod ntsc;
+use ntsc::NtscFilter;
+mod palette;
+mod patch;
+mod region;
+use region::Region;
+mod scaler;
+mod script;
+use script::ScriptHost;
+mod sound;
+use sound::Sound;
+mod video;
+mod xbrz;
+use video::Recorder;
 
 fn window_conf() -> Conf {
     Conf {
@@ -21,64 +51,287 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    // Load Program (assembled at https://www.masswerk.at/6502/assembler.html)
-    /*
-        *=$8000
-        LDX #10
-        STX $0000
-        LDX #3
-        STX $0001
-        LDY $0000
-        LDA #0
-        CLC
-        loop
-        ADC $0001
-        DEY
-        BNE loop
-        STA $0002
-        NOP
-        NOP
-        NOP
-    */
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}\n{}", e, config::USAGE);
+            return;
+        }
+    };
+
+    if config.gdb.is_some() || config.script.is_some() {
+        debug_headless(&config);
+    } else {
+        macroquad::Window::from_config(window_conf(), emulate(config));
+    }
+}
+
+// Attribute grid, the screen as the capture line scrolls it, and the tile under the mouse
+fn draw_name_table_overlay(ppu: &mut Ppu, cart: &mut Cartridge) {
+    let width = ppu::NAME_TABLES_WIDTH as f32;
+    let height = ppu::NAME_TABLES_HEIGHT as f32;
+    let grid = Color::new(1.0, 1.0, 1.0, 0.15);
+    for x in (16..ppu::NAME_TABLES_WIDTH).step_by(16) {
+        draw_line(x as f32, 0.0, x as f32, height, 1.0, grid);
+    }
+    for y in (16..ppu::NAME_TABLES_HEIGHT).step_by(16) {
+        draw_line(0.0, y as f32, width, y as f32, 1.0, grid);
+    }
+    draw_line(width / 2.0, 0.0, width / 2.0, height, 1.0, GRAY);
+    draw_line(0.0, height / 2.0, width, height / 2.0, 1.0, GRAY);
+
+    // The screen wraps around the edges, so it can take up to four pieces
+    let (scroll_x, scroll_y) = ppu.scroll();
+    let (scroll_x, scroll_y) = (scroll_x as f32, scroll_y as f32);
+    let (screen_width, screen_height) = (ppu::SCREEN_WIDTH as f32, ppu::SCREEN_HEIGHT as f32);
+    for &(x, w) in &[
+        (scroll_x, screen_width.min(width - scroll_x)),
+        (0.0, scroll_x + screen_width - width),
+    ] {
+        for &(y, h) in &[
+            (scroll_y, screen_height.min(height - scroll_y)),
+            (0.0, scroll_y + screen_height - height),
+        ] {
+            if w > 0.0 && h > 0.0 {
+                draw_rectangle_lines(x, y, w, h, 2.0, YELLOW);
+            }
+        }
+    }
+
+    draw_text(
+        &format!(
+            "Scroll ({}, {}) at line {}    [ ] = Move line",
+            scroll_x, scroll_y, ppu.scroll_capture_line
+        ),
+        10.0,
+        555.0,
+        25.0,
+        WHITE,
+    );
+
+    let (mouse_x, mouse_y) = mouse_position();
+    if mouse_x >= 0.0 && mouse_x < width && mouse_y >= 0.0 && mouse_y < height {
+        let (x, y) = (mouse_x as usize, mouse_y as usize);
+        let tile = ppu.name_table_tile(cart, x, y);
+        draw_rectangle_lines((x & !7) as f32, (y & !7) as f32, 8.0, 8.0, 2.0, RED);
+        draw_text(
+            &format!(
+                "Tile ${:02X} at ${:04X}  Attribute ${:04X} Palette {}",
+                tile.id, tile.address, tile.attribute_address, tile.palette
+            ),
+            10.0,
+            580.0,
+            25.0,
+            WHITE,
+        );
+    }
+}
+
+fn colour([r, g, b, a]: [u8; 4]) -> Color {
+    Color::from_rgba(r, g, b, a)
+}
+
+// Status flags, set ones in green, then the registers
+fn draw_cpu(cpu: &Cpu, x: i32, y: i32) {
+    draw_text("STATUS:", x as f32, y as f32, 25.0, WHITE);
 
+    let status = cpu.status();
+    for (i, flag) in "NV-BDIZC".chars().enumerate() {
+        let set = status & (0x80 >> i) != 0;
+        let flag_x = (x + 79 + 16 * i as i32) as f32;
+        let colour = if set { GREEN } else { RED };
+        draw_text(&flag.to_string(), flag_x, y as f32, 25.0, colour);
+    }
+
+    let x = (x + 15) as f32;
+    let registers = [
+        format!("PC: ${:04X}", cpu.pc),
+        format!("A: ${:02X}  [{}]", cpu.a, cpu.a),
+        format!("X: ${:02X}  [{}]", cpu.x, cpu.x),
+        format!("Y: ${:02X}  [{}]", cpu.y, cpu.y),
+        format!("Stack P: ${:04X}", cpu.stack_pointer()),
+    ];
+    for (i, register) in registers.iter().enumerate() {
+        draw_text(register, x, (y + 15 * (i as i32 + 1)) as f32, 25.0, WHITE);
+    }
+}
+
+// First free name like screenshot_0001.png, so a new file never replaces an earlier one
+fn numbered_path(stem: &str, extension: &str) -> String {
+    (1..)
+        .map(|n| format!("{}_{:04}.{}", stem, n, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn load_debugger(config: &Config) -> Debugger {
+    let mut debugger = Debugger::new();
+    for spec in &config.breakpoints {
+        if let Err(e) = debugger.add(spec) {
+            println!("Ignoring breakpoint {}: {}", spec, e);
+        }
+    }
+
+    debugger
+}
+
+// The command line wins over what the image says, and NTSC covers everything else
+fn set_region(config: &Config, bus: &mut Bus, ppu: &mut Ppu, cart: &Cartridge) -> Region {
+    let region = config
+        .region
+        .or_else(|| cart.region())
+        .unwrap_or(Region::Ntsc);
+    bus.set_region(region);
+    ppu.set_region(region);
+
+    region
+}
+
+// Runs without a window, driven by a script or a remote debugger
+fn debug_headless(config: &Config) {
+    let mut cart = match Cartridge::open(&config.rom, config.entry.as_deref(), &config.patches) {
+        Ok(cart) => cart,
+        Err(e) => panic!("error loading cartridge image: {}", e),
+    };
     let mut ppu = Ppu::new();
     let mut bus = Bus::new();
     let mut cpu = Cpu::new();
-    let mut cart = Cartridge::new("nestest.nes");
-    let map_asm: BTreeMap<u16, String>;
+    let mut debugger = load_debugger(config);
+
+    set_region(config, &mut bus, &mut ppu, &cart);
+    bus.reset(&mut cpu, &mut ppu, &mut cart);
+    while !cpu.complete() {
+        bus.clock(&mut cpu, &mut ppu, &mut cart);
+    }
+
+    if let Some(path) = &config.script {
+        let result = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path, e))
+            .and_then(|script| {
+                let out = std::io::stdout();
+                ScriptHost::new(
+                    out.lock(),
+                    &mut cpu,
+                    &mut bus,
+                    &mut ppu,
+                    &mut cart,
+                    &mut debugger,
+                )
+                .run(&script)
+            });
+        if let Err(e) = result {
+            println!("Script stopped: {}", e);
+        }
+    } else if let Some(port) = config.gdb {
+        if let Err(e) = gdb::serve(port, &mut cpu, &mut bus, &mut ppu, &mut cart, &mut debugger) {
+            println!("Debugger connection failed: {}", e);
+        }
+    }
+}
+
+async fn emulate(config: Config) {
+    let mut ppu = Ppu::new();
+    let mut bus = Bus::new();
+    let mut cpu = Cpu::new();
+    let mut cart = match Cartridge::open(&config.rom, config.entry.as_deref(), &config.patches) {
+        Ok(cart) => cart,
+        Err(e) => panic!("error loading cartridge image: {}", e),
+    };
     let mut emulation_run: bool = false;
     let mut selected_pallete: u8 = 0x00;
 
-    // let program = Vec::from(hex!(
-    //     "A2 0A 8E 00 00 A2 03 8E 01 00 AC 00 00 A9 00 18 6D 01 00 88 D0 FA 8D 02 00 EA EA EA"
-    // ));
-    // cpu.load_program(&mut bus, 0x8000, program, 0x00, 0x80);
+    let region = set_region(&config, &mut bus, &mut ppu, &cart);
+    ppu.sprite_limit = config.sprite_limit;
+    match &config.palette {
+        PaletteSource::Builtin => {}
+        PaletteSource::File(path) => match palette::load(path) {
+            Ok(colours) => ppu.set_palette(colours),
+            Err(e) => println!("Keeping the built-in palette: {}", e),
+        },
+        PaletteSource::Ntsc(settings) => ppu.set_palette(palette::generate(*settings)),
+    }
+    bus.reset(&mut cpu, &mut ppu, &mut cart);
 
-    if !cart.image_valid {
-        panic!("error loading cartridge image");
+    if let Some(path) = &config.trace {
+        match File::create(path) {
+            Ok(file) => cpu.set_trace(Some(Box::new(BufWriter::new(file)))),
+            Err(e) => println!("Failed to create {}: {}", path, e),
+        }
     }
 
-    // map_asm = cpu.disassemble(0x0000, 0xFFFF, &mut bus, &mut ppu, &mut cart);
+    let mut debugger = load_debugger(&config);
 
-    bus.reset(&mut cpu, &mut ppu, &mut cart);
+    let mut nsf_player = cart.nsf().cloned().map(|nsf| NsfPlayer::new(nsf, region));
+    if let Some(player) = &mut nsf_player {
+        player.start(&mut cpu, &mut bus, &mut ppu, &mut cart);
+        emulation_run = true;
+    }
 
     let mut fps_timer = 0_f32;
     let mut fps: i32 = 0;
     let mut show_name_tbl: bool = false;
 
-    let target_fps = 60;
+    let sound = Sound::open();
+    let target_fps = region.frame_rate();
+    let mut recorder: Option<Recorder<BufWriter<File>>> = None;
     let mut last_frame_time = get_time();
 
-    let main_texture: Texture2D = Texture2D::from_image(&Image::gen_image_color(256, 240, BLACK));
-    let image_0_texture: Texture2D = Texture2D::from_image(&Image::gen_image_color(128, 128, BLACK));
-    let image_1_texture: Texture2D = Texture2D::from_image(&Image::gen_image_color(128, 128, BLACK));
+    prevent_quit();
+
+    // The PPU draws palette pixels. The filter or palette turns them into RGBA, which the
+    // display pipeline crops and scales for the texture.
+    let mut ntsc_filter = config.ntsc.map(|preset| {
+        let settings = match &config.palette {
+            PaletteSource::Ntsc(settings) => *settings,
+            _ => NtscSettings::default(),
+        };
+        NtscFilter::new(preset, settings)
+    });
+    let main_width = match ntsc_filter {
+        Some(_) => ntsc::OUTPUT_WIDTH,
+        None => ppu::SCREEN_WIDTH,
+    };
+    let mut main_rgba = vec![0; main_width * ppu::SCREEN_HEIGHT * 4];
+    let frame = config
+        .display
+        .process(&main_rgba, main_width, ppu::SCREEN_HEIGHT);
+    let mut main_image = Image::gen_image_color(frame.width as u16, frame.height as u16, BLACK);
+    // Fits the picture to the space left of the debug panels. Aspect correction already gives
+    // square pixels; otherwise the NTSC filter's 602 columns still span the PPU's 256.
+    let pixel_aspect = if config.display.aspect {
+        (1, 1)
+    } else {
+        (ppu::SCREEN_WIDTH, main_width)
+    };
+    let frame_width = (frame.width * pixel_aspect.0) as f32 / pixel_aspect.1 as f32;
+    let main_scale = (540.0 / frame_width).min(480.0 / frame.height as f32);
+    let main_size = vec2(frame_width * main_scale, frame.height as f32 * main_scale);
+    let mut image_0 = Image::gen_image_color(128, 128, BLACK);
+    let mut image_1 = Image::gen_image_color(128, 128, BLACK);
+    let mut name_table_image = Image::gen_image_color(
+        ppu::NAME_TABLES_WIDTH as u16,
+        ppu::NAME_TABLES_HEIGHT as u16,
+        BLACK,
+    );
+    let main_texture: Texture2D = Texture2D::from_image(&main_image);
+    let image_0_texture: Texture2D = Texture2D::from_image(&image_0);
+    let image_1_texture: Texture2D = Texture2D::from_image(&image_1);
+    let name_table_texture: Texture2D = Texture2D::from_image(&name_table_image);
 
     loop {
+        if is_quit_requested() {
+            if let Err(e) = cart.save() {
+                println!("Failed to write {}: {}", cart.save_path().display(), e);
+            }
+            // Flushes the trace file
+            cpu.set_trace(None);
+            break;
+        }
+
         let current_time = get_time();
         let delta_time = current_time - last_frame_time;
-        let target_delta_time = 1.0 / target_fps as f64;
+        let target_delta_time = 1.0 / target_fps;
 
         if delta_time < target_delta_time {
             continue;
@@ -126,8 +379,7 @@ async fn main() {
 
         // cpu.draw_ram(&mut bus, &mut ppu, &mut cartridge, 2, 12, 0x0000, 16, 16);
         // cpu.draw_ram(&mut bus, &mut ppu, &mut cartridge, 2, 272, 0x8000, 16, 16);
-        // cpu.draw_cpu(550, 12);
-        // cpu.draw_code(550, 122, 26, &map_asm);
+        // draw_cpu(&cpu, 550, 12);
 
         // draw_text(
         //     "SPACE = Step Instruction    R = RESET    I = IRQ    N = NMI",
@@ -159,21 +411,50 @@ async fn main() {
             0x00
         };
 
+        let mut new_frame = false;
         if emulation_run {
             while !ppu.frame_complete {
-                bus.clock(&mut cpu, &mut ppu, &mut cart);
+                match &mut nsf_player {
+                    Some(player) => player.clock(&mut cpu, &mut bus, &mut ppu, &mut cart),
+                    None => {
+                        if debugger.clock(&mut cpu, &mut bus, &mut ppu, &mut cart) {
+                            emulation_run = false;
+                            break;
+                        }
+                    }
+                }
             }
 
+            new_frame = ppu.frame_complete;
             ppu.frame_complete = false;
+            sound.queue(bus.audio.drain());
         } else {
+            // Steps run until the debugger stops them, a frame at a time
             if is_key_pressed(KeyCode::C) {
-                while cpu.complete() {
-                    bus.clock(&mut cpu, &mut ppu, &mut cart);
-                }
+                debugger.step_instruction();
+                emulation_run = true;
+            }
 
-                while !cpu.complete() {
-                    bus.clock(&mut cpu, &mut ppu, &mut cart);
-                }
+            if is_key_pressed(KeyCode::O) {
+                debugger.step_over(
+                    &cpu,
+                    &mut SystemBus {
+                        bus: &mut bus,
+                        ppu: &mut ppu,
+                        cart: &mut cart,
+                    },
+                );
+                emulation_run = true;
+            }
+
+            if is_key_pressed(KeyCode::U) {
+                debugger.step_out(&cpu);
+                emulation_run = true;
+            }
+
+            if is_key_pressed(KeyCode::L) {
+                debugger.run_to_scanline(region.vblank_line());
+                emulation_run = true;
             }
 
             if is_key_pressed(KeyCode::F) {
@@ -190,24 +471,91 @@ async fn main() {
         }
 
         if is_key_pressed(KeyCode::R) {
-            bus.reset(&mut cpu, &mut ppu, &mut cart)
+            match &mut nsf_player {
+                Some(player) => player.start(&mut cpu, &mut bus, &mut ppu, &mut cart),
+                None => bus.reset(&mut cpu, &mut ppu, &mut cart),
+            }
+        }
+
+        if let Some(player) = &mut nsf_player {
+            if is_key_pressed(KeyCode::Right) {
+                player.next(&mut cpu, &mut bus, &mut ppu, &mut cart);
+            }
+
+            if is_key_pressed(KeyCode::Left) {
+                player.previous(&mut cpu, &mut bus, &mut ppu, &mut cart);
+            }
         }
 
         if is_key_pressed(KeyCode::Space) {
             emulation_run = !emulation_run;
+            debugger.resume();
+        }
+
+        if is_key_pressed(KeyCode::M) {
+            ppu.sprite_limit = !ppu.sprite_limit;
         }
 
         if is_key_pressed(KeyCode::P) {
             selected_pallete = selected_pallete.wrapping_add(1) & 0x07;
         }
 
+        if is_key_pressed(KeyCode::N) {
+            show_name_tbl = !show_name_tbl;
+        }
+
+        // Moves the line the viewer takes its scroll from, by a tile with shift held
+        let step = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+            8
+        } else {
+            1
+        };
+        if is_key_pressed(KeyCode::LeftBracket) {
+            ppu.scroll_capture_line = (ppu.scroll_capture_line - step).max(0);
+        }
+        if is_key_pressed(KeyCode::RightBracket) {
+            ppu.scroll_capture_line = (ppu.scroll_capture_line + step).min(239);
+        }
+
+        if is_key_pressed(KeyCode::D) {
+            cart.switch_disk_side();
+        }
+
+        if is_key_pressed(KeyCode::E) {
+            cart.eject_disk();
+        }
+
+        if let Some((side, sides)) = cart.disk() {
+            let status = match side {
+                Some(side) => format!(
+                    "Disk {}/{} Side {}",
+                    side / 2 + 1,
+                    sides.div_ceil(2),
+                    if side % 2 == 0 { 'A' } else { 'B' }
+                ),
+                None => "No disk".to_string(),
+            };
+
+            draw_text(&status, 10.0, 505.0, 25.0, WHITE);
+            draw_text("D = Switch side    E = Eject", 10.0, 530.0, 25.0, WHITE);
+        }
+
+        if nsf_player.is_none() && !emulation_run {
+            let status = match debugger.reason() {
+                Some(reason) => format!("Stopped: {} at ${:04X}", reason, cpu.pc),
+                None => format!("Paused at ${:04X}", cpu.pc),
+            };
+
+            draw_text(&status, 550.0, 470.0, 25.0, WHITE);
+            draw_text("C Step  O Over  U Out  L Vblank", 550.0, 495.0, 25.0, WHITE);
+        }
+
         // cpu.draw_ram(&mut bus, &mut ppu, &mut cart, 2, 272, 0x8000, 16, 16);
-        cpu.draw_cpu(550, 12);
-        // cpu.draw_code(&cpu.pc, 550, 122, 26, &map_asm);
+        draw_cpu(&cpu, 550, 12);
         // cpu.draw_ram(&mut bus, &mut ppu, &mut cart, 550, 450, 0x0000, 16, 16);
 
         for i in 0_usize..24 {
-            let oam_reg = ppu.oam[i];
+            let oam_reg = ppu.oam_entry(i);
 
             let mut s = format!("{:2x}", i);
             s.push_str(": (");
@@ -222,32 +570,88 @@ async fn main() {
             draw_text(s.as_str(), 550.0, (110 + i * 14) as f32, 25.0, WHITE);
         }
 
-        let main_image = ppu.get_screen();
+        let colours = *ppu.palette();
+        match &mut ntsc_filter {
+            Some(filter) => filter.filter(ppu.get_screen(), ppu.frame_phase(), &mut main_rgba),
+            None => palette::to_rgba(&colours, ppu.get_screen(), &mut main_rgba),
+        }
+        let frame = config
+            .display
+            .process(&main_rgba, main_width, ppu::SCREEN_HEIGHT);
+
+        let control = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if control && is_key_pressed(KeyCode::F12) {
+            recorder = match recorder.take() {
+                Some(recorder) => {
+                    if let Err(e) = recorder.finish() {
+                        println!("Failed to finish the recording: {}", e);
+                    }
+                    None
+                }
+                None => {
+                    let path = numbered_path("recording", "y4m");
+                    let file = File::create(&path).map(BufWriter::new);
+                    let (width, height) = (frame.width, frame.height);
+                    let rate = region.frame_rate();
+                    match file.and_then(|f| Recorder::new(f, width, height, rate, pixel_aspect)) {
+                        Ok(recorder) => {
+                            println!("Recording to {}", path);
+                            Some(recorder)
+                        }
+                        Err(e) => {
+                            println!("Failed to create {}: {}", path, e);
+                            None
+                        }
+                    }
+                }
+            };
+        }
 
-        if is_key_pressed(KeyCode::PrintScreen)
-            && (is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl))
-        {
-            main_image.export_png("main_image.png");
+        if new_frame {
+            if let Some(output) = &mut recorder {
+                if let Err(e) = output.write(&frame) {
+                    println!("Recording stopped: {}", e);
+                    recorder = None;
+                }
+            }
         }
 
-        main_texture.update(main_image);
-        draw_texture_ex(
-            main_texture,
-            0.0,
-            0.0,
-            WHITE,
-            DrawTextureParams {
-                dest_size: Some(vec2(
-                    (main_image.width * 2) as f32,
-                    (main_image.height * 2) as f32,
-                )),
-                source: None,
-                rotation: 0.0,
-                flip_x: false,
-                flip_y: false,
-                pivot: None,
-            },
-        );
+        main_image.bytes = frame.rgba;
+
+        if control && is_key_pressed(KeyCode::PrintScreen) {
+            let path = numbered_path("screenshot", "png");
+            main_image.export_png(&path);
+            println!("Saved {}", path);
+        }
+
+        if show_name_tbl {
+            let pixels = ppu.get_name_tables(&mut cart);
+            palette::to_rgba(&colours, pixels, &mut name_table_image.bytes);
+            name_table_texture.update(&name_table_image);
+            draw_texture(name_table_texture, 0.0, 0.0, WHITE);
+            draw_name_table_overlay(&mut ppu, &mut cart);
+        } else {
+            main_texture.update(&main_image);
+            draw_texture_ex(
+                main_texture,
+                0.0,
+                0.0,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(main_size),
+                    source: None,
+                    rotation: 0.0,
+                    flip_x: false,
+                    flip_y: false,
+                    pivot: None,
+                },
+            );
+        }
+
+        // Tunes draw nothing, so the player takes over the screen area
+        if let Some(player) = &nsf_player {
+            player.draw(10.0, 30.0);
+        }
 
         let swatch_size = 6;
         for p in 0_u8..8 {
@@ -259,7 +663,7 @@ async fn main() {
                     440.0,
                     swatch_size as f32,
                     swatch_size as f32,
-                    ppu.get_colour_from_pallet_ram(&mut cart, p, s),
+                    colour(colours[ppu.get_colour_from_pallet_ram(&mut cart, p, s) as usize]),
                 )
             }
         }
@@ -273,8 +677,9 @@ async fn main() {
             YELLOW,
         );
 
-        let image_0 = ppu.get_pattern_table(0, &selected_pallete, &mut cart);
-        image_0_texture.update(image_0);
+        let pixels = ppu.get_pattern_table(0, &selected_pallete, &mut cart);
+        palette::to_rgba(&colours, pixels, &mut image_0.bytes);
+        image_0_texture.update(&image_0);
         draw_texture_ex(
             image_0_texture,
             550.0,
@@ -293,8 +698,9 @@ async fn main() {
             },
         );
 
-        let image_1 = ppu.get_pattern_table(1, &selected_pallete, &mut cart);
-        image_1_texture.update(image_1);
+        let pixels = ppu.get_pattern_table(1, &selected_pallete, &mut cart);
+        palette::to_rgba(&colours, pixels, &mut image_1.bytes);
+        image_1_texture.update(&image_1);
         draw_texture_ex(
             image_1_texture,
             820.0,
@@ -7,16 +7,29 @@ pub struct Mapper {
 
 pub trait RW {
     fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32, data: &mut u8) -> bool;
+    // Like `cpu_map_read` but without side effects, for debuggers and tracing
+    fn cpu_map_peek(&self, addr: u16, mapped_addr: &mut u32, data: &mut u8) -> bool {
+        self.cpu_map_read(addr, mapped_addr, data)
+    }
     fn cpu_map_write(&mut self, addr: u16, mapped_addr: &mut u32, data: &u8) -> bool;
     fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool;
     fn ppu_map_write(&self, cart: &Cartridge, addr: u16, mapped_addr: &mut u32) -> bool;
     fn reset(&mut self);
 
     fn irq_state(&self) -> bool;
-    fn irq_clear(&mut self);
 
     fn scanline(&mut self);
+    // Called once per CPU cycle, for boards with their own timers
+    fn cpu_clock(&mut self);
     fn mirror(&self) -> Mirror;
+
+    // Battery backed work RAM, if the board has any
+    fn prg_ram(&mut self) -> Option<&mut [u8]>;
+
+    // Expansion audio level in 0.0..=1.0, for boards with their own sound
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
 }
 
 impl Mapper {
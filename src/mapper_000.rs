@@ -72,11 +72,15 @@ impl RW for Mapper000 {
         false
     }
 
-    fn irq_clear(&mut self) {}
-
     fn scanline(&mut self) {}
 
+    fn cpu_clock(&mut self) {}
+
     fn mirror(&self) -> crate::cartridge::Mirror {
         crate::cartridge::Mirror::Hardware
     }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
 }
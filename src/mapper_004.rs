@@ -244,10 +244,6 @@ impl RW for Mapper004 {
         self.irq_active
     }
 
-    fn irq_clear(&mut self) {
-        self.irq_active = false;
-    }
-
     fn scanline(&mut self) {
         if self.irq_counter == 0 {
             self.irq_counter = self.irq_reload;
@@ -260,7 +256,13 @@ impl RW for Mapper004 {
         }
     }
 
+    fn cpu_clock(&mut self) {}
+
     fn mirror(&self) -> Mirror {
         self.mirror_mode.clone()
     }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram_static[..8 * 1024])
+    }
 }
@@ -0,0 +1,520 @@
+use std::cell::Cell;
+
+use crate::{
+    cartridge::{Cartridge, Mirror},
+    fds::{self, DiskImage},
+    fds_audio::FdsAudio,
+    mapper::RW,
+    patch::{self, PatchError},
+};
+
+// CPU cycles between bytes passing under the head, and before the first one once the motor
+// has spun up
+const BYTE_DELAY: u32 = 150;
+const SPIN_UP_DELAY: u32 = 50000;
+
+// How long a disk stays out of the drive when switching sides, so the BIOS notices
+const SWAP_DELAY: u32 = 1_789_773;
+
+// Famicom Disk System RAM adapter. PRG is 32KB of RAM at $6000-$DFFF followed by the 8KB BIOS
+// at $E000, CHR is 8KB of RAM.
+pub struct Mapper020 {
+    image: Vec<u8>,
+    disk: DiskImage,
+    modified: bool,
+
+    side: Option<usize>,
+    next_side: Option<usize>,
+    swap_delay: u32,
+
+    disk_io_enable: bool,
+    sound_io_enable: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enable: bool,
+    timer_irq: Cell<bool>,
+    disk_irq: Cell<bool>,
+
+    motor_on: bool,
+    transfer_reset: bool,
+    read_mode: bool,
+    mirror_mode: Mirror,
+    crc_control: bool,
+    crc_enable: bool,
+    disk_irq_enable: bool,
+    ext_port: u8,
+
+    write_data: u8,
+    read_data: u8,
+    transfer_complete: Cell<bool>,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    previous_crc_control: bool,
+    crc: u16,
+    delay: u32,
+    position: usize,
+
+    audio: FdsAudio,
+}
+
+impl Mapper020 {
+    pub fn new(image: Vec<u8>) -> Self {
+        let disk = DiskImage::parse(&image);
+        let side = if disk.sides.is_empty() { None } else { Some(0) };
+
+        let mut mapper = Mapper020 {
+            image,
+            disk,
+            modified: false,
+            side,
+            next_side: None,
+            swap_delay: 0,
+            disk_io_enable: false,
+            sound_io_enable: false,
+            irq_reload: 0x0000,
+            irq_counter: 0x0000,
+            irq_repeat: false,
+            irq_enable: false,
+            timer_irq: Cell::new(false),
+            disk_irq: Cell::new(false),
+            motor_on: false,
+            transfer_reset: false,
+            read_mode: true,
+            mirror_mode: Mirror::Horizontal,
+            crc_control: false,
+            crc_enable: false,
+            disk_irq_enable: false,
+            ext_port: 0x00,
+            write_data: 0x00,
+            read_data: 0x00,
+            transfer_complete: Cell::new(false),
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            previous_crc_control: false,
+            crc: 0x0000,
+            delay: 0,
+            position: 0,
+            audio: FdsAudio::new(),
+        };
+
+        mapper.reset();
+
+        mapper
+    }
+
+    pub fn sides(&self) -> usize {
+        self.disk.sides.len()
+    }
+
+    // Side currently in the drive, if any
+    pub fn side(&self) -> Option<usize> {
+        self.side
+    }
+
+    pub fn eject(&mut self) {
+        self.side = None;
+        self.next_side = None;
+    }
+
+    // Ejects the current disk and inserts `side` a moment later
+    pub fn insert(&mut self, side: usize) {
+        if side < self.sides() {
+            self.side = None;
+            self.next_side = Some(side);
+            self.swap_delay = SWAP_DELAY;
+        }
+    }
+
+    // Flips to the next side, or the next disk after side B
+    pub fn switch_side(&mut self) {
+        let next = match self.side.or(self.next_side) {
+            Some(side) => (side + 1) % self.sides().max(1),
+            None => 0,
+        };
+
+        self.insert(next);
+    }
+
+    // Restores disk writes saved by `changes`
+    pub fn load_changes(&mut self, diff: &[u8]) -> Result<(), PatchError> {
+        let data = patch::apply(diff, &self.image)?;
+        self.disk = DiskImage::parse(&data);
+        self.side = self.side.filter(|&side| side < self.sides());
+
+        Ok(())
+    }
+
+    // IPS patch from the loaded image to the current disk contents, if anything was written
+    pub fn changes(&self) -> Option<Vec<u8>> {
+        if self.modified {
+            Some(patch::create_ips(&self.image, &self.disk.to_bytes()))
+        } else {
+            None
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.irq_enable {
+            if self.irq_counter == 0 {
+                self.timer_irq.set(true);
+                self.irq_counter = self.irq_reload;
+
+                if !self.irq_repeat {
+                    self.irq_enable = false;
+                }
+            } else {
+                self.irq_counter -= 1;
+            }
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        if self.next_side.is_some() {
+            self.swap_delay = self.swap_delay.saturating_sub(1);
+
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+
+        if self.transfer_reset && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.delay = SPIN_UP_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut need_irq = self.disk_irq_enable;
+
+        if self.read_mode {
+            let data = self.disk.sides[side][self.position];
+
+            if !self.previous_crc_control {
+                self.crc = fds::crc_update(self.crc, data);
+            }
+
+            if !self.crc_enable {
+                self.gap_ended = false;
+                self.crc = 0x0000;
+            } else if data > 0 && !self.gap_ended {
+                // The start mark is latched but does not raise an IRQ
+                self.gap_ended = true;
+                need_irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete.set(true);
+                self.read_data = data;
+
+                if need_irq {
+                    self.disk_irq.set(true);
+                }
+            }
+        } else {
+            let mut data = 0x00;
+
+            if !self.crc_control {
+                self.transfer_complete.set(true);
+                data = self.write_data;
+
+                if need_irq {
+                    self.disk_irq.set(true);
+                }
+            }
+
+            if !self.crc_enable {
+                data = 0x00;
+            }
+
+            if !self.crc_control {
+                self.crc = fds::crc_update(self.crc, data);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = fds::crc_update(self.crc, 0x00);
+                    self.crc = fds::crc_update(self.crc, 0x00);
+                }
+
+                data = (self.crc & 0x00FF) as u8;
+                self.crc >>= 8;
+            }
+
+            // The adapter's shift register trails the head by two bytes
+            if let Some(pos) = self.position.checked_sub(2) {
+                if self.disk.sides[side][pos] != data {
+                    self.disk.sides[side][pos] = data;
+                    self.modified = true;
+                }
+            }
+
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.disk.sides[side].len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+
+    // $4030-$4033
+    fn disk_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030 => {
+                (self.timer_irq.get() as u8)
+                    | (self.transfer_complete.get() as u8) << 1
+                    | ((self.read_mode && self.crc_control && self.crc != 0) as u8) << 4
+                    | (self.end_of_head as u8) << 6
+                    | (self.disk_io_enable as u8) << 7
+            }
+            0x4031 => self.read_data,
+            0x4032 => {
+                // Disk missing, not ready and write protected
+                (self.side.is_none() as u8)
+                    | ((self.side.is_none() || !self.scanning) as u8) << 1
+                    | (self.side.is_none() as u8) << 2
+            }
+            _ => self.ext_port & 0x80,
+        }
+    }
+}
+
+impl RW for Mapper020 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32, data: &mut u8) -> bool {
+        if (0x4030..=0x4033).contains(&addr) && self.disk_io_enable {
+            *mapped_addr = 0xFFFFFFFF;
+            *data = self.disk_register(addr);
+
+            // Reading the status acknowledges both IRQs, reading data the disk one
+            match addr {
+                0x4030 => {
+                    self.transfer_complete.set(false);
+                    self.timer_irq.set(false);
+                    self.disk_irq.set(false);
+                }
+                0x4031 => {
+                    self.transfer_complete.set(false);
+                    self.disk_irq.set(false);
+                }
+                _ => {}
+            }
+            return true;
+        }
+
+        if (0x4040..=0x4097).contains(&addr) && self.sound_io_enable {
+            *mapped_addr = 0xFFFFFFFF;
+            *data = self.audio.cpu_read(addr);
+            return true;
+        }
+
+        if (0x6000..=0xDFFF).contains(&addr) {
+            *mapped_addr = (addr - 0x6000) as u32;
+            return true;
+        }
+
+        if addr >= 0xE000 {
+            *mapped_addr = 0x8000 + (addr & 0x1FFF) as u32;
+            return true;
+        }
+
+        false
+    }
+
+    fn cpu_map_peek(&self, addr: u16, mapped_addr: &mut u32, data: &mut u8) -> bool {
+        if (0x4030..=0x4033).contains(&addr) && self.disk_io_enable {
+            *mapped_addr = 0xFFFFFFFF;
+            *data = self.disk_register(addr);
+            return true;
+        }
+
+        self.cpu_map_read(addr, mapped_addr, data)
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, mapped_addr: &mut u32, data: &u8) -> bool {
+        if (0x4020..=0x4026).contains(&addr) {
+            *mapped_addr = 0xFFFFFFFF;
+
+            if !self.disk_io_enable && addr >= 0x4024 {
+                return true;
+            }
+
+            match addr {
+                0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | *data as u16,
+                0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (*data as u16) << 8,
+                0x4022 => {
+                    self.irq_repeat = (data & 0x01) > 0;
+                    self.irq_enable = (data & 0x02) > 0 && self.disk_io_enable;
+
+                    if self.irq_enable {
+                        self.irq_counter = self.irq_reload;
+                    } else {
+                        self.timer_irq.set(false);
+                    }
+                }
+                0x4023 => {
+                    self.disk_io_enable = (data & 0x01) > 0;
+                    self.sound_io_enable = (data & 0x02) > 0;
+
+                    if !self.disk_io_enable {
+                        self.irq_enable = false;
+                        self.timer_irq.set(false);
+                        self.disk_irq.set(false);
+                    }
+                }
+                0x4024 => {
+                    self.write_data = *data;
+                    self.transfer_complete.set(false);
+                    self.disk_irq.set(false);
+                }
+                0x4025 => {
+                    self.motor_on = (data & 0x01) > 0;
+                    self.transfer_reset = (data & 0x02) > 0;
+                    self.read_mode = (data & 0x04) > 0;
+                    self.mirror_mode = if (data & 0x08) > 0 {
+                        Mirror::Horizontal
+                    } else {
+                        Mirror::Vertical
+                    };
+                    self.crc_control = (data & 0x10) > 0;
+                    self.crc_enable = (data & 0x40) > 0;
+                    self.disk_irq_enable = (data & 0x80) > 0;
+                    self.disk_irq.set(false);
+                }
+                _ => self.ext_port = *data,
+            }
+            return true;
+        }
+
+        if (0x4040..=0x408A).contains(&addr) {
+            *mapped_addr = 0xFFFFFFFF;
+
+            if self.sound_io_enable {
+                self.audio.cpu_write(addr, *data);
+            }
+            return true;
+        }
+
+        if (0x6000..=0xDFFF).contains(&addr) {
+            *mapped_addr = (addr - 0x6000) as u32;
+            return true;
+        }
+
+        // BIOS ROM
+        if addr >= 0xE000 {
+            *mapped_addr = 0xFFFFFFFF;
+            return true;
+        }
+
+        false
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr <= 0x1FFF {
+            *mapped_addr = addr as u32;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ppu_map_write(&self, _cart: &Cartridge, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr <= 0x1FFF {
+            *mapped_addr = addr as u32;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.disk_io_enable = false;
+        self.sound_io_enable = false;
+        self.irq_enable = false;
+        self.timer_irq.set(false);
+        self.disk_irq.set(false);
+        self.motor_on = false;
+        self.transfer_complete.set(false);
+        self.end_of_head = true;
+        self.scanning = false;
+    }
+
+    fn irq_state(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    fn scanline(&mut self) {}
+
+    fn cpu_clock(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn mirror(&self) -> Mirror {
+        self.mirror_mode.clone()
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_changes() {
+        let image = fds::tests::image();
+        let mut drive = Mapper020::new(image.clone());
+        assert!(drive.changes().is_none());
+
+        // As if the BIOS had rewritten the file's data
+        let raw = &mut drive.disk.sides[0];
+        let data = raw
+            .windows(4)
+            .position(|w| w == [0xDE, 0xAD, 0xBE, 0xEF])
+            .unwrap();
+        raw[data..data + 4].copy_from_slice(b"SAVE");
+        drive.modified = true;
+
+        // The .sav is an IPS patch of the image, which a fresh drive loads back
+        let diff = drive.changes().unwrap();
+        assert!(diff.starts_with(b"PATCH") && diff.len() < 32);
+
+        let mut reloaded = Mapper020::new(image.clone());
+        reloaded.load_changes(&diff).unwrap();
+        assert_eq!(reloaded.disk.to_bytes(), drive.disk.to_bytes());
+        assert_ne!(reloaded.disk.to_bytes(), image);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    cartridge::{Cartridge, Mirror},
+    fds_audio::FdsAudio,
+    mapper::RW,
+    nsf::CHIP_FDS,
+};
+
+// Address of the player's idle loop, "JMP $4100". INIT and PLAY return here.
+pub const DRIVER_ADDR: u16 = 0x4100;
+const DRIVER: [u8; 3] = [0x4C, 0x00, 0x41];
+
+// Expansion chips from the header's flags that the board has sound for. The others are still
+// taken, but their registers are left to fall through and they stay silent.
+pub const AUDIO_CHIPS: u8 = CHIP_FDS;
+
+// Board synthesised for NSF playback: 4KB PRG banks at $8000-$FFFF selected through
+// $5FF8-$5FFF, 8KB of work RAM at $6000 and the expansion audio the tune asks for. FDS tunes
+// run from RAM instead, which is banked at $6000-$7FFF as well through $5FF6/$5FF7.
+pub struct MapperNsf {
+    // One bank per 4KB from $6000
+    banks: [u8; 10],
+    initial_banks: [u8; 10],
+    bank_count: usize,
+    ram: Vec<u8>,
+    // Expansion chip flags from the header
+    chips: u8,
+    fds_audio: Option<FdsAudio>,
+}
+
+impl MapperNsf {
+    pub fn new(banks: [u8; 10], bank_count: usize, chips: u8) -> Self {
+        let mut mapper = MapperNsf {
+            banks,
+            initial_banks: banks,
+            bank_count: bank_count.max(1),
+            ram: vec![0; 8 * 1024],
+            chips,
+            fds_audio: None,
+        };
+
+        mapper.reset();
+
+        mapper
+    }
+
+    fn bank_addr(&self, addr: u16) -> u32 {
+        let bank = self.banks[((addr - 0x6000) >> 12) as usize] as usize % self.bank_count;
+        (bank * 0x1000) as u32 + (addr & 0x0FFF) as u32
+    }
+
+    fn fds(&self) -> bool {
+        (self.chips & CHIP_FDS) > 0
+    }
+}
+
+impl RW for MapperNsf {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32, data: &mut u8) -> bool {
+        if (DRIVER_ADDR..DRIVER_ADDR + 3).contains(&addr) {
+            *mapped_addr = 0xFFFFFFFF;
+            *data = DRIVER[(addr - DRIVER_ADDR) as usize];
+            return true;
+        }
+
+        if let Some(audio) = &self.fds_audio {
+            if (0x4040..=0x4097).contains(&addr) {
+                *mapped_addr = 0xFFFFFFFF;
+                *data = audio.cpu_read(addr);
+                return true;
+            }
+        }
+
+        if (0x6000..=0x7FFF).contains(&addr) && !self.fds() {
+            *mapped_addr = 0xFFFFFFFF;
+            *data = self.ram[(addr & 0x1FFF) as usize];
+            return true;
+        }
+
+        if addr >= 0x6000 {
+            *mapped_addr = self.bank_addr(addr);
+            return true;
+        }
+
+        false
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, mapped_addr: &mut u32, data: &u8) -> bool {
+        if (0x5FF8..=0x5FFF).contains(&addr) || (self.fds() && (0x5FF6..=0x5FF7).contains(&addr)) {
+            *mapped_addr = 0xFFFFFFFF;
+            self.banks[(addr - 0x5FF6) as usize] = *data;
+            return true;
+        }
+
+        if let Some(audio) = &mut self.fds_audio {
+            if (0x4040..=0x408A).contains(&addr) {
+                *mapped_addr = 0xFFFFFFFF;
+                audio.cpu_write(addr, *data);
+                return true;
+            }
+        }
+
+        if self.fds() {
+            // FDS tunes run from RAM and may write to their program area
+            if (0x6000..=0xDFFF).contains(&addr) {
+                *mapped_addr = self.bank_addr(addr);
+                return true;
+            }
+        }
+
+        if (0x6000..=0x7FFF).contains(&addr) {
+            *mapped_addr = 0xFFFFFFFF;
+            self.ram[(addr & 0x1FFF) as usize] = *data;
+            return true;
+        }
+
+        if addr >= 0x8000 {
+            *mapped_addr = 0xFFFFFFFF;
+            return true;
+        }
+
+        false
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr <= 0x1FFF {
+            *mapped_addr = addr as u32;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ppu_map_write(&self, _cart: &Cartridge, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr <= 0x1FFF {
+            *mapped_addr = addr as u32;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.banks = self.initial_banks;
+        self.ram.iter_mut().for_each(|b| *b = 0);
+        self.fds_audio = if self.fds() {
+            Some(FdsAudio::new())
+        } else {
+            None
+        };
+    }
+
+    fn irq_state(&self) -> bool {
+        false
+    }
+
+    fn scanline(&mut self) {}
+
+    fn cpu_clock(&mut self) {
+        if let Some(audio) = &mut self.fds_audio {
+            audio.clock();
+        }
+    }
+
+    fn mirror(&self) -> Mirror {
+        Mirror::Hardware
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.fds_audio.as_ref().map_or(0.0, |audio| audio.sample())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::CHIP_VRC6;
+
+    fn write(mapper: &mut MapperNsf, addr: u16, data: u8) -> bool {
+        mapper.cpu_map_write(addr, &mut 0, &data)
+    }
+
+    fn read(mapper: &MapperNsf, addr: u16) -> Option<u8> {
+        let mut data = 0;
+        if mapper.cpu_map_read(addr, &mut 0, &mut data) {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn expansion_audio() {
+        // The FDS flag puts the wavetable channel behind $4040-$4097
+        let mut mapper = MapperNsf::new([0; 10], 8, CHIP_FDS | CHIP_VRC6);
+        write(&mut mapper, 0x4089, 0x80);
+        for addr in 0x4040..=0x407F {
+            assert!(write(&mut mapper, addr, 0x3F));
+        }
+        write(&mut mapper, 0x4089, 0x00);
+        assert_eq!(read(&mapper, 0x4050), Some(0x3F));
+
+        write(&mut mapper, 0x4080, 0x80 | 32);
+        write(&mut mapper, 0x4082, 0xFF);
+        write(&mut mapper, 0x4083, 0x0F);
+        for _ in 0..1000 {
+            mapper.cpu_clock();
+        }
+        assert!(mapper.audio_output() > 0.5);
+
+        // Resetting for the next track silences it
+        mapper.reset();
+        assert_eq!(read(&mapper, 0x4050), Some(0x00));
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        // Without it the registers are open bus and the board is silent
+        let mut mapper = MapperNsf::new([0; 10], 8, CHIP_VRC6);
+        assert!(!write(&mut mapper, 0x4080, 0x80 | 32));
+        assert_eq!(read(&mapper, 0x4090), None);
+        mapper.cpu_clock();
+        assert_eq!(mapper.audio_output(), 0.0);
+    }
+}
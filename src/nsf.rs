@@ -0,0 +1,450 @@
+// NSF and NSFe music rips. Both describe a 6502 program plus the INIT and PLAY routines a
+// player calls; NSFe (and NSF2 metadata) add per track titles, durations and fades.
+
+use crate::cartridge::CartridgeError;
+
+// Expansion audio flags
+pub const CHIP_VRC6: u8 = 0x01;
+pub const CHIP_VRC7: u8 = 0x02;
+pub const CHIP_FDS: u8 = 0x04;
+pub const CHIP_MMC5: u8 = 0x08;
+pub const CHIP_N163: u8 = 0x10;
+pub const CHIP_S5B: u8 = 0x20;
+
+const CHIP_NAMES: [(u8, &str); 6] = [
+    (CHIP_VRC6, "VRC6"),
+    (CHIP_VRC7, "VRC7"),
+    (CHIP_FDS, "FDS"),
+    (CHIP_MMC5, "MMC5"),
+    (CHIP_N163, "N163"),
+    (CHIP_S5B, "5B"),
+];
+
+#[derive(Clone, Default)]
+pub struct Track {
+    pub title: Option<String>,
+    // Milliseconds
+    pub duration: Option<u32>,
+    pub fade: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub start_song: u8,
+    // Play routine period in microseconds
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    pub chips: u8,
+    pub banks: [u8; 8],
+    pub data: Vec<u8>,
+    pub tracks: Vec<Track>,
+    // Order to play tracks in
+    pub playlist: Vec<u8>,
+}
+
+pub fn is_nsf(data: &[u8]) -> bool {
+    data.starts_with(b"NESM\x1A") || data.starts_with(b"NSFE")
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+// NUL padded or NUL separated strings
+fn strings(data: &[u8]) -> Vec<String> {
+    data.split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).trim().to_string())
+        .collect()
+}
+
+// Per track millisecond values, negative meaning "not given"
+fn times(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks_exact(4)
+        .map(|c| {
+            let ms = i32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+            if ms < 0 {
+                None
+            } else {
+                Some(ms as u32)
+            }
+        })
+        .collect()
+}
+
+impl Nsf {
+    pub fn parse(data: &[u8]) -> Result<Nsf, CartridgeError> {
+        if data.starts_with(b"NSFE") {
+            let mut nsf = Nsf::empty();
+            nsf.read_chunks(&data[4..], true)?;
+            nsf.finish()
+        } else {
+            Nsf::parse_nsf(data)
+        }
+    }
+
+    fn empty() -> Nsf {
+        Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            load_addr: 0x8000,
+            init_addr: 0x8000,
+            play_addr: 0x8000,
+            start_song: 0,
+            ntsc_speed: 16639,
+            pal_speed: 19997,
+            pal: false,
+            chips: 0,
+            banks: [0; 8],
+            data: Vec::new(),
+            tracks: Vec::new(),
+            playlist: Vec::new(),
+        }
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Nsf, CartridgeError> {
+        if data.len() < 0x80 {
+            return Err(CartridgeError::BadNsf("header is truncated"));
+        }
+
+        let mut nsf = Nsf::empty();
+        let songs = data[0x06];
+        nsf.start_song = data[0x07].saturating_sub(1);
+        nsf.load_addr = le16(data, 0x08);
+        nsf.init_addr = le16(data, 0x0A);
+        nsf.play_addr = le16(data, 0x0C);
+        nsf.title = strings(&data[0x0E..0x2E]).remove(0);
+        nsf.artist = strings(&data[0x2E..0x4E]).remove(0);
+        nsf.copyright = strings(&data[0x4E..0x6E]).remove(0);
+        nsf.ntsc_speed = le16(data, 0x6E);
+        nsf.banks.copy_from_slice(&data[0x70..0x78]);
+        nsf.pal_speed = le16(data, 0x78);
+        nsf.pal = (data[0x7A] & 0x03) == 0x01;
+        nsf.chips = data[0x7B];
+        nsf.tracks = vec![Track::default(); songs as usize];
+
+        // NSF2 gives the program length so that NSFe metadata chunks can follow it
+        let length = u32::from_le_bytes([data[0x7D], data[0x7E], data[0x7F], 0]) as usize;
+        if data[0x05] >= 2 && length > 0 && 0x80 + length <= data.len() {
+            nsf.data = data[0x80..0x80 + length].to_vec();
+            nsf.read_chunks(&data[0x80 + length..], false)?;
+        } else {
+            nsf.data = data[0x80..].to_vec();
+        }
+
+        nsf.finish()
+    }
+
+    fn read_chunks(&mut self, data: &[u8], full: bool) -> Result<(), CartridgeError> {
+        let mut pos = 0;
+        let mut info = !full;
+
+        while pos + 8 <= data.len() {
+            let length =
+                u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                    as usize;
+            let id = &data[pos + 4..pos + 8];
+            let chunk = data
+                .get(pos + 8..pos + 8 + length)
+                .ok_or(CartridgeError::BadNsf("chunk is truncated"))?;
+            pos += 8 + length;
+
+            match id {
+                b"INFO" if full => {
+                    if chunk.len() < 8 {
+                        return Err(CartridgeError::BadNsf("INFO chunk is truncated"));
+                    }
+
+                    self.load_addr = le16(chunk, 0);
+                    self.init_addr = le16(chunk, 2);
+                    self.play_addr = le16(chunk, 4);
+                    self.pal = (chunk[6] & 0x03) == 0x01;
+                    self.chips = chunk[7];
+                    let songs = chunk.get(8).copied().unwrap_or(1);
+                    self.start_song = chunk.get(9).copied().unwrap_or(0);
+                    self.tracks.resize(songs as usize, Track::default());
+                    info = true;
+                }
+                b"DATA" if full => self.data = chunk.to_vec(),
+                b"BANK" if full => {
+                    let len = chunk.len().min(8);
+                    self.banks[..len].copy_from_slice(&chunk[..len]);
+                }
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        self.ntsc_speed = le16(chunk, 0);
+                    }
+                    if chunk.len() >= 4 {
+                        self.pal_speed = le16(chunk, 2);
+                    }
+                }
+                b"auth" => {
+                    let mut fields = strings(chunk).into_iter();
+                    self.title = fields.next().unwrap_or_default();
+                    self.artist = fields.next().unwrap_or_default();
+                    self.copyright = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    for (track, title) in self.tracks.iter_mut().zip(strings(chunk)) {
+                        track.title = Some(title).filter(|t| !t.is_empty());
+                    }
+                }
+                b"time" => {
+                    for (track, time) in self.tracks.iter_mut().zip(times(chunk)) {
+                        track.duration = time;
+                    }
+                }
+                b"fade" => {
+                    for (track, time) in self.tracks.iter_mut().zip(times(chunk)) {
+                        track.fade = time;
+                    }
+                }
+                b"plst" => self.playlist = chunk.to_vec(),
+                b"NEND" => break,
+                _ => {}
+            }
+        }
+
+        if info {
+            Ok(())
+        } else {
+            Err(CartridgeError::BadNsf("missing INFO chunk"))
+        }
+    }
+
+    fn finish(mut self) -> Result<Nsf, CartridgeError> {
+        if self.data.is_empty() {
+            return Err(CartridgeError::BadNsf("no program data"));
+        }
+
+        if self.tracks.is_empty() {
+            return Err(CartridgeError::BadNsf("no tracks"));
+        }
+
+        let songs = self.tracks.len();
+        self.playlist.retain(|&track| (track as usize) < songs);
+        if self.playlist.is_empty() {
+            self.playlist = (0..songs as u8).collect();
+        }
+
+        Ok(self)
+    }
+
+    pub fn bankswitched(&self) -> bool {
+        self.banks.iter().any(|&bank| bank != 0)
+    }
+
+    // Program laid out in 4KB banks, along with the banks to map at $6000-$FFFF. Only FDS
+    // tunes bank $6000-$7FFF, through $5FF6/$5FF7 starting from the $5FFE/$5FFF values; the
+    // others have plain work RAM there.
+    pub fn prg_image(&self) -> (Vec<u8>, [u8; 10]) {
+        let fds = (self.chips & CHIP_FDS) > 0;
+        let mut banks = [0_u8; 10];
+
+        if self.bankswitched() {
+            let padding = (self.load_addr & 0x0FFF) as usize;
+            let mut memory = vec![0_u8; padding];
+            memory.extend_from_slice(&self.data);
+            memory.resize(memory.len().div_ceil(0x1000) * 0x1000, 0);
+
+            banks[2..].copy_from_slice(&self.banks);
+            if fds {
+                banks[..2].copy_from_slice(&self.banks[6..]);
+            }
+
+            (memory, banks)
+        } else {
+            // FDS tunes may load from $6000, the RAM adapter having RAM there
+            let base: usize = if fds { 0x6000 } else { 0x8000 };
+            let mut memory = vec![0_u8; 0x10000 - base];
+            let start = (self.load_addr as usize).max(base);
+            let skip = start - self.load_addr as usize;
+
+            if skip < self.data.len() {
+                let data = &self.data[skip..];
+                let len = data.len().min(0x10000 - start);
+                memory[start - base..start - base + len].copy_from_slice(&data[..len]);
+            }
+
+            let first = (base - 0x6000) / 0x1000;
+            for (slot, bank) in banks.iter_mut().enumerate().skip(first) {
+                *bank = (slot - first) as u8;
+            }
+
+            (memory, banks)
+        }
+    }
+
+    pub fn chip_names(&self) -> Vec<&'static str> {
+        chip_names(self.chips)
+    }
+}
+
+// Names of the expansion chips set in `chips`, in header bit order
+pub fn chip_names(chips: u8) -> Vec<&'static str> {
+    CHIP_NAMES
+        .iter()
+        .filter(|(flag, _)| (chips & flag) > 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn chunk(data: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(id);
+        data.extend_from_slice(body);
+    }
+
+    // Three songs starting at the second, PAL, FDS audio, with `program` loaded at $8000
+    pub fn image(program: &[u8]) -> Vec<u8> {
+        let mut data = b"NESM\x1A\x01\x03\x02".to_vec();
+        data.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x04, 0x80]);
+        for field in [&b"Title"[..], b"Artist", b"2024 Someone"].iter() {
+            let mut text = field.to_vec();
+            text.resize(32, 0);
+            data.extend(text);
+        }
+        data.extend_from_slice(&16639_u16.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&19997_u16.to_le_bytes());
+        data.extend_from_slice(&[0x01, CHIP_FDS, 0, 0, 0, 0]);
+        data.extend_from_slice(program);
+        data
+    }
+
+    #[test]
+    fn nsf_header() {
+        let nsf = Nsf::parse(&image(&[0xEA; 16])).unwrap();
+        assert_eq!(
+            (
+                nsf.title.as_str(),
+                nsf.artist.as_str(),
+                nsf.copyright.as_str()
+            ),
+            ("Title", "Artist", "2024 Someone")
+        );
+        assert_eq!(
+            (nsf.load_addr, nsf.init_addr, nsf.play_addr),
+            (0x8000, 0x8000, 0x8004)
+        );
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16639, 19997));
+        assert!(nsf.pal && !nsf.bankswitched());
+        assert_eq!(nsf.chip_names(), ["FDS"]);
+        assert_eq!((nsf.start_song, nsf.tracks.len()), (1, 3));
+        assert_eq!(nsf.playlist, [0, 1, 2]);
+        assert_eq!(nsf.data, [0xEA; 16]);
+
+        // NSF2 gives the program length, and NSFe chunks may follow it
+        let mut data = image(&[0xEA; 16]);
+        data[0x05] = 2;
+        data[0x7D] = 16;
+        chunk(&mut data, b"tlbl", b"One\0\0Three");
+        chunk(&mut data, b"plst", &[2, 0, 7]);
+        let nsf = Nsf::parse(&data).unwrap();
+        assert_eq!(nsf.data.len(), 16);
+        assert_eq!(nsf.tracks[0].title.as_deref(), Some("One"));
+        assert_eq!(nsf.tracks[1].title, None);
+        assert_eq!(nsf.playlist, [2, 0]);
+
+        assert!(matches!(
+            Nsf::parse(&data[..0x40]),
+            Err(CartridgeError::BadNsf("header is truncated"))
+        ));
+        data[0x06] = 0;
+        assert!(matches!(
+            Nsf::parse(&data),
+            Err(CartridgeError::BadNsf("no tracks"))
+        ));
+    }
+
+    #[test]
+    fn nsfe_chunks() {
+        let mut data = b"NSFE".to_vec();
+        chunk(
+            &mut data,
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x04, 0x80, 0x00, CHIP_FDS, 2, 1],
+        );
+        chunk(&mut data, b"BANK", &[0, 1, 2, 3, 4, 5, 6, 7]);
+        chunk(&mut data, b"DATA", &[0xEA; 0x2000]);
+        chunk(&mut data, b"RATE", &[0x10, 0x27]);
+        chunk(&mut data, b"auth", b"Game\0Composer\0Year\0Ripper");
+        chunk(&mut data, b"tlbl", b"Intro\0Boss");
+        let mut times = 90_000_i32.to_le_bytes().to_vec();
+        times.extend_from_slice(&(-1_i32).to_le_bytes());
+        chunk(&mut data, b"time", &times);
+        chunk(&mut data, b"fade", &5000_i32.to_le_bytes());
+        chunk(&mut data, b"NEND", &[]);
+        chunk(&mut data, b"DATA", &[0x00]);
+
+        let nsf = Nsf::parse(&data).unwrap();
+        assert_eq!(
+            (nsf.title.as_str(), nsf.artist.as_str()),
+            ("Game", "Composer")
+        );
+        assert_eq!(nsf.copyright, "Year");
+        assert_eq!((nsf.start_song, nsf.tracks.len()), (1, 2));
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (10000, 19997));
+        assert!(nsf.bankswitched() && !nsf.pal);
+        // Chunks after NEND are ignored
+        assert_eq!(nsf.data.len(), 0x2000);
+
+        let (intro, boss) = (&nsf.tracks[0], &nsf.tracks[1]);
+        assert_eq!(intro.title.as_deref(), Some("Intro"));
+        assert_eq!((intro.duration, intro.fade), (Some(90_000), Some(5000)));
+        assert_eq!(boss.title.as_deref(), Some("Boss"));
+        assert_eq!((boss.duration, boss.fade), (None, None));
+
+        // FDS tunes start $6000-$7FFF on the banks given for $E000-$FFFF
+        let (memory, banks) = nsf.prg_image();
+        assert_eq!(memory.len(), 0x2000);
+        assert_eq!(banks, [6, 7, 0, 1, 2, 3, 4, 5, 6, 7]);
+
+        assert!(matches!(
+            Nsf::parse(&data[..data.len() - 20]),
+            Err(CartridgeError::BadNsf("chunk is truncated"))
+        ));
+        let mut no_info = b"NSFE".to_vec();
+        chunk(&mut no_info, b"DATA", &[0xEA]);
+        assert!(matches!(
+            Nsf::parse(&no_info),
+            Err(CartridgeError::BadNsf("missing INFO chunk"))
+        ));
+    }
+
+    #[test]
+    fn prg_layout() {
+        // Without banking, FDS tunes may load below $8000
+        let mut nsf = Nsf::parse(&image(&[0xEA; 16])).unwrap();
+        nsf.load_addr = 0x7000;
+        let (memory, banks) = nsf.prg_image();
+        assert_eq!(memory.len(), 0xA000);
+        assert_eq!(&memory[0x1000..0x1010], &[0xEA; 16]);
+        assert_eq!(banks, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        nsf.chips = 0;
+        let (memory, banks) = nsf.prg_image();
+        assert_eq!(memory.len(), 0x8000);
+        assert!(memory.iter().all(|&b| b == 0));
+        assert_eq!(&banks[2..], &[0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // Banked tunes are padded to their load address within the first bank
+        nsf.load_addr = 0x8123;
+        nsf.banks = [0, 0, 0, 0, 0, 0, 0, 1];
+        let (memory, banks) = nsf.prg_image();
+        assert_eq!(memory.len(), 0x1000);
+        assert_eq!(&memory[0x123..0x133], &[0xEA; 16]);
+        assert_eq!(&banks[2..], &nsf.banks);
+    }
+}
@@ -0,0 +1,256 @@
+use macroquad::prelude::*;
+
+use crate::{
+    bus::SystemBus,
+    cpu::CpuBus,
+    mapper_nsf::{AUDIO_CHIPS, DRIVER_ADDR},
+    nsf::{self, Nsf},
+    region::Region,
+    Bus, Cartridge, Cpu, Ppu,
+};
+
+const TRACKS_SHOWN: usize = 10;
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Plays an NSF by calling its INIT routine once per track and then PLAY at the rate given in
+// the header, each time the previous call has returned to the driver loop. There is no APU
+// yet, so only the FDS expansion channel is heard; other tunes run silent.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    position: usize,
+    play_period: f64,
+    play_timer: f64,
+    dots: u64,
+    region: Region,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, region: Region) -> Self {
+        let speed = if region != Region::Ntsc {
+            nsf.pal_speed
+        } else {
+            nsf.ntsc_speed
+        };
+        let position = nsf
+            .playlist
+            .iter()
+            .position(|&track| track == nsf.start_song)
+            .unwrap_or(0);
+
+        NsfPlayer {
+            play_period: speed.max(1) as f64 * region.dot_rate() / 1_000_000.0,
+            nsf,
+            position,
+            play_timer: 0.0,
+            dots: 0,
+            region,
+        }
+    }
+
+    pub fn track(&self) -> usize {
+        self.nsf.playlist[self.position] as usize
+    }
+
+    // Seconds since the track started
+    pub fn elapsed(&self) -> f64 {
+        self.dots as f64 / self.region.dot_rate()
+    }
+
+    // Resets the machine and runs INIT for the current track, with RAM and the sound
+    // registers set up as the NSF spec asks
+    pub fn start(&mut self, cpu: &mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
+        bus.reset(cpu, ppu, cart);
+        while !cpu.complete() {
+            bus.clock(cpu, ppu, cart);
+        }
+        bus.cpu_ram.iter_mut().for_each(|b| *b = 0);
+
+        let mut system = SystemBus { bus, ppu, cart };
+        for addr in 0x4000..=0x4013 {
+            system.write(addr, 0x00);
+        }
+        system.write(0x4015, 0x0F);
+        // Frame counter in 4-step mode with its IRQ inhibited
+        system.write(0x4017, 0x40);
+
+        cpu.call(&mut system, self.nsf.init_addr, DRIVER_ADDR);
+        cpu.a = self.track() as u8;
+        cpu.x = (self.region != Region::Ntsc) as u8;
+
+        self.play_timer = 0.0;
+        self.dots = 0;
+    }
+
+    pub fn next(&mut self, cpu: &mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
+        self.position = (self.position + 1) % self.nsf.playlist.len();
+        self.start(cpu, bus, ppu, cart);
+    }
+
+    pub fn previous(&mut self, cpu: &mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
+        self.position = self
+            .position
+            .checked_sub(1)
+            .unwrap_or(self.nsf.playlist.len() - 1);
+        self.start(cpu, bus, ppu, cart);
+    }
+
+    // Track length including its fade out, when the file gives one
+    fn length(&self) -> Option<f64> {
+        let track = &self.nsf.tracks[self.track()];
+
+        track
+            .duration
+            .map(|duration| (duration + track.fade.unwrap_or(0)) as f64 / 1000.0)
+    }
+
+    // Replaces `Bus::clock` while playing
+    pub fn clock(&mut self, cpu: &mut Cpu, bus: &mut Bus, ppu: &mut Ppu, cart: &mut Cartridge) {
+        bus.clock(cpu, ppu, cart);
+
+        self.dots += 1;
+        self.play_timer += 1.0;
+
+        if self.play_timer >= self.play_period && cpu.complete() && cpu.pc == DRIVER_ADDR {
+            // A PLAY routine running long delays the next call rather than queueing more
+            self.play_timer = (self.play_timer - self.play_period).min(self.play_period);
+            cpu.call(
+                &mut SystemBus { bus, ppu, cart },
+                self.nsf.play_addr,
+                DRIVER_ADDR,
+            );
+        }
+
+        if self.length().is_some_and(|length| self.elapsed() >= length) {
+            self.next(cpu, bus, ppu, cart);
+        }
+    }
+
+    pub fn draw(&self, x: f32, y: f32) {
+        let nsf = &self.nsf;
+
+        draw_text(&nsf.title, x, y, 25.0, WHITE);
+        draw_text(&nsf.artist, x, y + 20.0, 20.0, WHITE);
+        draw_text(&nsf.copyright, x, y + 38.0, 20.0, WHITE);
+
+        let played = nsf::chip_names(nsf.chips & AUDIO_CHIPS);
+        let mut text = if played.is_empty() {
+            "Silent: the APU is not emulated yet".to_string()
+        } else {
+            format!(
+                "{} audio only: the APU is not emulated yet",
+                played.join("/")
+            )
+        };
+        let missing = nsf::chip_names(nsf.chips & !AUDIO_CHIPS);
+        if !missing.is_empty() {
+            text.push_str(&format!(", nor {} expansion audio", missing.join("/")));
+        }
+        draw_text(&text, x, y + 56.0, 20.0, YELLOW);
+
+        let mut status = format!(
+            "Track {}/{}  {}",
+            self.position + 1,
+            nsf.playlist.len(),
+            format_time(self.elapsed())
+        );
+        if let Some(length) = self.length() {
+            status.push_str(&format!(" / {}", format_time(length)));
+        }
+        draw_text(&status, x, y + 80.0, 25.0, WHITE);
+
+        let first = self
+            .position
+            .saturating_sub(TRACKS_SHOWN / 2)
+            .min(nsf.playlist.len().saturating_sub(TRACKS_SHOWN));
+
+        for (row, (i, &track)) in nsf
+            .playlist
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(TRACKS_SHOWN)
+            .enumerate()
+        {
+            let info = &nsf.tracks[track as usize];
+            let mut line = format!(
+                "{}{:2} {}",
+                if i == self.position { "> " } else { "  " },
+                i + 1,
+                info.title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {}", track + 1))
+            );
+
+            if let Some(duration) = info.duration {
+                line.push_str(&format!("  {}", format_time(duration as f64 / 1000.0)));
+            }
+            if let Some(fade) = info.fade {
+                line.push_str(&format!(" +{}", format_time(fade as f64 / 1000.0)));
+            }
+
+            let colour = if i == self.position { YELLOW } else { WHITE };
+            draw_text(&line, x, y + 104.0 + row as f32 * 18.0, 20.0, colour);
+        }
+
+        draw_text(
+            "LEFT/RIGHT = Previous/Next track    R = Restart track",
+            x,
+            y + 110.0 + TRACKS_SHOWN as f32 * 18.0,
+            20.0,
+            WHITE,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{debugger::AccessKind, nsf};
+
+    #[test]
+    fn init_and_play() {
+        // INIT stores the track number at $0200, PLAY counts its calls at $0201
+        let image = nsf::tests::image(&[0x8D, 0x00, 0x02, 0x60, 0xEE, 0x01, 0x02, 0x60]);
+        let mut cart = Cartridge::from_bytes("test.nsf", image).unwrap();
+        let mut cpu = Cpu::new();
+        let mut bus = Bus::new();
+        let mut ppu = Ppu::new();
+        let mut player = NsfPlayer::new(cart.nsf().cloned().unwrap(), Region::Pal);
+
+        bus.access_log.enabled = true;
+        player.start(&mut cpu, &mut bus, &mut ppu, &mut cart);
+        assert_eq!((cpu.pc, cpu.a, cpu.x), (0x8000, 1, 1));
+
+        // The sound registers are set up before INIT runs
+        let writes: Vec<(u16, u8)> = bus
+            .access_log
+            .accesses
+            .iter()
+            .filter(|access| access.kind == AccessKind::Write && access.addr >= 0x4000)
+            .map(|access| (access.addr, access.data))
+            .collect();
+        let mut expected: Vec<(u16, u8)> = (0x4000..=0x4013).map(|addr| (addr, 0x00)).collect();
+        expected.extend_from_slice(&[(0x4015, 0x0F), (0x4017, 0x40)]);
+        assert_eq!(writes, expected);
+        bus.access_log.enabled = false;
+
+        // Two PLAY periods at 20ms
+        for _ in 0..2 * 106_392 + 1000 {
+            player.clock(&mut cpu, &mut bus, &mut ppu, &mut cart);
+        }
+        assert_eq!(bus.cpu_ram[0x200], 1);
+        assert_eq!(bus.cpu_ram[0x201], 2);
+
+        // FDS tunes bank $6000-$7FFF too
+        let mut data = 0;
+        assert!(cart.cpu_read(0x6000, &mut data, true));
+        assert_eq!(data, 0x00);
+        cart.cpu_write(0x5FF6, 2);
+        assert!(cart.cpu_read(0x6000, &mut data, true));
+        assert_eq!(data, 0x8D);
+    }
+}
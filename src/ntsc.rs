@@ -0,0 +1,179 @@
+use crate::{
+    palette::{self, NtscSettings},
+    ppu::SCREEN_WIDTH,
+};
+
+// Every three dots come out as seven pixels, as with blargg's nes_ntsc
+pub const OUTPUT_WIDTH: usize = ((SCREEN_WIDTH - 1) / 3 + 1) * 7;
+
+// Signal samples a dot, at twelve to a colour cycle
+const SAMPLES_PER_DOT: usize = 8;
+const LINE_SAMPLES: usize = SCREEN_WIDTH * SAMPLES_PER_DOT;
+// Black on either side of a line, wider than the widest filter. A whole number of colour
+// cycles, so a sample's phase doesn't depend on it.
+const PADDING: usize = 24;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Preset {
+    Composite,
+    SVideo,
+    Rgb,
+}
+
+impl Preset {
+    pub fn parse(name: &str) -> Option<Preset> {
+        match name.to_ascii_lowercase().as_str() {
+            "composite" => Some(Preset::Composite),
+            "svideo" | "s-video" => Some(Preset::SVideo),
+            "rgb" => Some(Preset::Rgb),
+            _ => None,
+        }
+    }
+}
+
+// Rebuilds the video signal the PPU puts out for each line and decodes it the way a TV
+// would, with the colour fringes and dot crawl that come with that
+pub struct NtscFilter {
+    settings: NtscSettings,
+    // Composite carries luma and chroma on one wire and the TV has to separate them again,
+    // which is where the artifacts come from. S-Video and RGB keep them apart.
+    separate: bool,
+    // Samples luma and chroma are each averaged over, wider being softer
+    luma_width: usize,
+    chroma_width: usize,
+    // Signal for every 9-bit pixel at each phase, and its average, which is the luma alone
+    signal: Vec<[f32; 12]>,
+    luma: Vec<f32>,
+    carrier: [(f32, f32); 12],
+    line: Vec<f32>,
+    line_luma: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(preset: Preset, settings: NtscSettings) -> Self {
+        let (separate, luma_width, chroma_width) = match preset {
+            Preset::Composite => (false, 12, 24),
+            Preset::SVideo => (true, 6, 24),
+            Preset::Rgb => (true, 2, 12),
+        };
+
+        let signal: Vec<[f32; 12]> = (0..0x200)
+            .map(|pixel| {
+                let mut wave = [0.0; 12];
+                for (phase, v) in wave.iter_mut().enumerate() {
+                    *v = palette::signal(pixel, phase);
+                }
+                wave
+            })
+            .collect();
+        let luma = signal
+            .iter()
+            .map(|wave| wave.iter().sum::<f32>() / 12.0)
+            .collect();
+
+        let mut carrier = [(0.0, 0.0); 12];
+        for (phase, wave) in carrier.iter_mut().enumerate() {
+            *wave = palette::carrier(phase);
+        }
+
+        NtscFilter {
+            settings,
+            separate,
+            luma_width,
+            chroma_width,
+            signal,
+            luma,
+            carrier,
+            line: vec![0.0; LINE_SAMPLES + 2 * PADDING],
+            line_luma: vec![0.0; LINE_SAMPLES + 2 * PADDING],
+        }
+    }
+
+    // Filters a frame of PPU pixels into RGBA, OUTPUT_WIDTH pixels a line. The phase is the
+    // colour subcarrier's at the first pixel, from Ppu::frame_phase.
+    pub fn filter(&mut self, pixels: &[u16], phase: u8, rgba: &mut [u8]) {
+        let lines = pixels
+            .chunks_exact(SCREEN_WIDTH)
+            .zip(rgba.chunks_exact_mut(OUTPUT_WIDTH * 4));
+
+        for (y, (row, out)) in lines.enumerate() {
+            // In twelfths of a cycle. A line is 341 dots, so the next starts a third later.
+            let start = (phase as usize + y) % 3 * 4;
+            for (x, &pixel) in row.iter().enumerate() {
+                let pixel = (pixel & 0x1FF) as usize;
+                for sample in x * SAMPLES_PER_DOT..(x + 1) * SAMPLES_PER_DOT {
+                    self.line[PADDING + sample] = self.signal[pixel][(start + sample) % 12];
+                    self.line_luma[PADDING + sample] = self.luma[pixel];
+                }
+            }
+
+            for (x, colour) in out.chunks_exact_mut(4).enumerate() {
+                let centre = PADDING + (x * LINE_SAMPLES + LINE_SAMPLES / 2) / OUTPUT_WIDTH;
+
+                let luma = if self.separate {
+                    &self.line_luma
+                } else {
+                    &self.line
+                };
+                let first = centre - self.luma_width / 2;
+                let y = luma[first..first + self.luma_width].iter().sum::<f32>()
+                    / self.luma_width as f32;
+
+                let (mut i, mut q) = (0.0, 0.0);
+                let first = centre - self.chroma_width / 2;
+                for sample in first..first + self.chroma_width {
+                    let mut v = self.line[sample];
+                    if self.separate {
+                        v -= self.line_luma[sample];
+                    }
+                    let (sin, cos) = self.carrier[(start + sample) % 12];
+                    i += v * cos;
+                    q += v * sin;
+                }
+
+                let width = self.chroma_width as f32;
+                colour.copy_from_slice(&self.settings.decode(y, i / width, q / width));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::SCREEN_HEIGHT;
+
+    fn run(preset: Preset, pixels: &[u16], phase: u8) -> Vec<u8> {
+        let mut filter = NtscFilter::new(preset, NtscSettings::default());
+        let mut rgba = vec![0; OUTPUT_WIDTH * SCREEN_HEIGHT * 4];
+        filter.filter(pixels, phase, &mut rgba);
+        rgba
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(OUTPUT_WIDTH, 602);
+
+        // A flat colour comes out as the generated palette has it, whatever the connection
+        let palette = palette::generate(NtscSettings::default());
+        let flat = vec![0x16; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for preset in [Preset::Composite, Preset::SVideo, Preset::Rgb].iter() {
+            let rgba = run(*preset, &flat, 0);
+            let middle = (100 * OUTPUT_WIDTH + 300) * 4;
+            for (a, b) in rgba[middle..middle + 4].iter().zip(palette[0x16].iter()) {
+                assert!((*a as i32 - *b as i32).abs() <= 2, "{:?}", preset);
+            }
+        }
+
+        // Gray and white stripes pick up colour over composite, and it crawls from frame
+        // to frame. RGB shows neither.
+        let stripes: Vec<u16> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .map(|i| if i % 2 == 0 { 0x30 } else { 0x00 })
+            .collect();
+        let composite = run(Preset::Composite, &stripes, 0);
+        let [r, g, b] = [composite[1200], composite[1201], composite[1202]];
+        assert!(r != g || g != b);
+        assert_ne!(composite, run(Preset::Composite, &stripes, 1));
+        assert_eq!(run(Preset::Rgb, &stripes, 0), run(Preset::Rgb, &stripes, 1));
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs;
+
+// RGBA for every 9-bit pixel the PPU outputs
+pub type Palette = [[u8; 4]; 0x200];
+
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+// How much an emphasis bit darkens the channels it doesn't name, for 64 colour palettes
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// Composite signal voltages for each luma level, the low half of the wave then the high
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK_LEVEL: f32 = 0.518;
+const WHITE_LEVEL: f32 = 1.962;
+// Emphasis pulls the signal down over part of each colour cycle
+const SIGNAL_ATTENUATION: f32 = 0.746;
+// Brings saturation 1.0 in line with the built-in palette
+const CHROMA_GAIN: f32 = 1.5;
+
+// Picture controls for the generated palette, as on a TV
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NtscSettings {
+    // Degrees to rotate every hue by
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    // Decoding gamma, against the 2.2 of the output
+    pub gamma: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+pub fn builtin() -> Palette {
+    let mut pallet = [BLACK; 64];
+    pallet[0x00] = [84, 84, 84, 255];
+    pallet[0x01] = [0, 30, 116, 255];
+    pallet[0x02] = [8, 16, 144, 255];
+    pallet[0x03] = [48, 0, 136, 255];
+    pallet[0x04] = [68, 0, 100, 255];
+    pallet[0x05] = [92, 0, 48, 255];
+    pallet[0x06] = [84, 4, 0, 255];
+    pallet[0x07] = [60, 24, 0, 255];
+    pallet[0x08] = [32, 42, 0, 255];
+    pallet[0x09] = [8, 58, 0, 255];
+    pallet[0x0A] = [0, 64, 0, 255];
+    pallet[0x0B] = [0, 60, 0, 255];
+    pallet[0x0C] = [0, 50, 60, 255];
+    pallet[0x0D] = [0, 0, 0, 255];
+    pallet[0x0E] = [0, 0, 0, 255];
+    pallet[0x0F] = [0, 0, 0, 255];
+
+    pallet[0x10] = [152, 150, 152, 255];
+    pallet[0x11] = [8, 76, 196, 255];
+    pallet[0x12] = [48, 50, 236, 255];
+    pallet[0x13] = [92, 30, 228, 255];
+    pallet[0x14] = [136, 20, 176, 255];
+    pallet[0x15] = [160, 20, 100, 255];
+    pallet[0x16] = [152, 34, 32, 255];
+    pallet[0x17] = [120, 60, 0, 255];
+    pallet[0x18] = [84, 90, 0, 255];
+    pallet[0x19] = [40, 114, 0, 255];
+    pallet[0x1A] = [8, 124, 0, 255];
+    pallet[0x1B] = [0, 118, 40, 255];
+    pallet[0x1C] = [0, 102, 120, 255];
+    pallet[0x1D] = [0, 0, 0, 255];
+    pallet[0x1E] = [0, 0, 0, 255];
+    pallet[0x1F] = [0, 0, 0, 255];
+
+    pallet[0x20] = [236, 238, 236, 255];
+    pallet[0x21] = [76, 154, 236, 255];
+    pallet[0x22] = [120, 124, 236, 255];
+    pallet[0x23] = [176, 98, 236, 255];
+    pallet[0x24] = [228, 84, 236, 255];
+    pallet[0x25] = [236, 88, 180, 255];
+    pallet[0x26] = [236, 106, 100, 255];
+    pallet[0x27] = [212, 136, 32, 255];
+    pallet[0x28] = [160, 170, 0, 255];
+    pallet[0x29] = [116, 196, 0, 255];
+    pallet[0x2A] = [76, 208, 32, 255];
+    pallet[0x2B] = [56, 204, 108, 255];
+    pallet[0x2C] = [56, 180, 204, 255];
+    pallet[0x2D] = [60, 60, 60, 255];
+    pallet[0x2E] = [0, 0, 0, 255];
+    pallet[0x2F] = [0, 0, 0, 255];
+
+    pallet[0x30] = [236, 238, 236, 255];
+    pallet[0x31] = [168, 204, 236, 255];
+    pallet[0x32] = [188, 188, 236, 255];
+    pallet[0x33] = [212, 178, 236, 255];
+    pallet[0x34] = [236, 174, 236, 255];
+    pallet[0x35] = [236, 174, 212, 255];
+    pallet[0x36] = [236, 180, 176, 255];
+    pallet[0x37] = [228, 196, 144, 255];
+    pallet[0x38] = [204, 210, 120, 255];
+    pallet[0x39] = [180, 222, 120, 255];
+    pallet[0x3A] = [168, 226, 144, 255];
+    pallet[0x3B] = [152, 226, 180, 255];
+    pallet[0x3C] = [160, 214, 228, 255];
+    pallet[0x3D] = [160, 162, 160, 255];
+    pallet[0x3E] = [0, 0, 0, 255];
+    pallet[0x3F] = [0, 0, 0, 255];
+
+    emphasise(&pallet)
+}
+
+// A .pal file holds RGB triples: 64 colours, or all 512 with the emphasis variants following
+// the plain colours
+pub fn load(path: &str) -> Result<Palette, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    from_bytes(&bytes).ok_or_else(|| {
+        format!(
+            "{}: expected 192 or 1536 bytes, found {}",
+            path,
+            bytes.len()
+        )
+    })
+}
+
+fn from_bytes(bytes: &[u8]) -> Option<Palette> {
+    let mut colours = [BLACK; 0x200];
+    for (colour, rgb) in colours.iter_mut().zip(bytes.chunks_exact(3)) {
+        *colour = [rgb[0], rgb[1], rgb[2], 255];
+    }
+
+    match bytes.len() {
+        192 => {
+            let mut base = [BLACK; 0x40];
+            base.copy_from_slice(&colours[..0x40]);
+            Some(emphasise(&base))
+        }
+        1536 => Some(colours),
+        _ => None,
+    }
+}
+
+// Builds all 512 colours by producing the square wave the PPU puts out for each pixel and
+// decoding it as a TV would, after Bisqwit's palette generator
+pub fn generate(settings: NtscSettings) -> Palette {
+    let mut colours = [BLACK; 0x200];
+    for (pixel, colour) in colours.iter_mut().enumerate() {
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let v = signal(pixel as u16, phase) / 12.0;
+            let (sin, cos) = carrier(phase);
+            y += v;
+            i += v * cos;
+            q += v * sin;
+        }
+
+        *colour = settings.decode(y, i, q);
+    }
+
+    colours
+}
+
+// The PPU's output voltage for a 9-bit pixel at one of the 12 phases of the colour
+// subcarrier, scaled so black is 0 and white 1
+pub fn signal(pixel: u16, phase: usize) -> f32 {
+    // Each colour's wave is high for six phases, starting one phase later per hue
+    let in_phase = |hue: usize| (hue + phase + 8) % 12 < 6;
+    let hue = (pixel & 0x0F) as usize;
+    let emphasis = pixel >> 6;
+    // Columns $E and $F are always black. Column 0 stays high and $D low, so neither
+    // carries any colour.
+    let level = if hue > 0x0D {
+        1
+    } else {
+        (pixel as usize >> 4) & 3
+    };
+    let low = LEVELS[level + if hue == 0x00 { 4 } else { 0 }];
+    let high = LEVELS[level + if hue < 0x0D { 4 } else { 0 }];
+
+    let mut signal = if in_phase(hue) { high } else { low };
+    if (emphasis & 0b001 != 0 && in_phase(12))
+        || (emphasis & 0b010 != 0 && in_phase(4))
+        || (emphasis & 0b100 != 0 && in_phase(8))
+    {
+        signal *= SIGNAL_ATTENUATION;
+    }
+
+    (signal - BLACK_LEVEL) / (WHITE_LEVEL - BLACK_LEVEL)
+}
+
+// Sine and cosine of the colour subcarrier at a phase, for demodulating I and Q
+pub fn carrier(phase: usize) -> (f32, f32) {
+    (std::f32::consts::PI * (phase % 12) as f32 / 6.0).sin_cos()
+}
+
+impl NtscSettings {
+    // Applies the picture controls to a demodulated signal and converts it to RGB
+    pub fn decode(&self, y: f32, i: f32, q: f32) -> [u8; 4] {
+        let (hue_sin, hue_cos) = self.hue.to_radians().sin_cos();
+        let gamma = |v: f32| {
+            if v <= 0.0 {
+                0
+            } else {
+                (v.powf(2.2 / self.gamma).min(1.0) * 255.0).round() as u8
+            }
+        };
+
+        let chroma = self.contrast * self.saturation * CHROMA_GAIN;
+        let (i, q) = (
+            (i * hue_cos - q * hue_sin) * chroma,
+            (i * hue_sin + q * hue_cos) * chroma,
+        );
+        let y = y * self.contrast;
+        [
+            gamma(y + 0.946882 * i + 0.623557 * q),
+            gamma(y - 0.274788 * i - 0.635691 * q),
+            gamma(y - 1.108545 * i + 1.709007 * q),
+            255,
+        ]
+    }
+}
+
+// Every emphasis combination of the base colours. Each bit darkens the two channels it
+// doesn't name, and the blacks in columns $E and $F stay black.
+fn emphasise(base: &[[u8; 4]; 0x40]) -> Palette {
+    let mut colours = [BLACK; 0x200];
+    for (i, colour) in colours.iter_mut().enumerate() {
+        *colour = base[i & 0x3F];
+        if i & 0x0E == 0x0E {
+            continue;
+        }
+
+        let emphasis = i >> 6;
+        for (channel, others) in [0b110, 0b101, 0b011].iter().enumerate() {
+            if emphasis & others != 0 {
+                colour[channel] = (colour[channel] as f32 * EMPHASIS_ATTENUATION) as u8;
+            }
+        }
+    }
+
+    colours
+}
+
+// Converts PPU pixels to RGBA bytes, four a pixel
+pub fn to_rgba(palette: &Palette, pixels: &[u16], rgba: &mut [u8]) {
+    for (pixel, out) in pixels.iter().zip(rgba.chunks_exact_mut(4)) {
+        out.copy_from_slice(&palette[(pixel & 0x1FF) as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal_files() {
+        let bytes: Vec<u8> = (0..1536).map(|i| i as u8).collect();
+
+        // 64 colours get the emphasis variants made up
+        let colours = from_bytes(&bytes[..192]).unwrap();
+        assert_eq!(colours[0x01], [3, 4, 5, 255]);
+        assert_eq!(colours[0x41][0], colours[0x01][0]);
+        assert!(colours[0x41][1] < colours[0x01][1]);
+
+        // 512 are taken as they are
+        let colours = from_bytes(&bytes).unwrap();
+        assert_eq!(colours[0x41], [195, 196, 197, 255]);
+
+        assert!(from_bytes(&bytes[..100]).is_none());
+    }
+
+    #[test]
+    fn generated() {
+        let colours = generate(NtscSettings::default());
+        let [r, g, b, _] = colours[0x16];
+        assert!(r > g && r > b);
+        let [r, g, b, _] = colours[0x12];
+        assert!(b > r && b > g);
+        assert_eq!(colours[0x0F], BLACK);
+        assert_eq!(colours[0x30], [255, 255, 255, 255]);
+        // Red emphasis keeps red and darkens the rest
+        let [r, g, b, _] = colours[0x70];
+        assert!(g < 255 && r > b);
+
+        // Zero saturation leaves only grays
+        let gray = generate(NtscSettings {
+            saturation: 0.0,
+            ..NtscSettings::default()
+        });
+        assert_eq!(gray[0x16][0], gray[0x16][2]);
+    }
+
+    #[test]
+    fn rgba() {
+        let colours = builtin();
+        let mut rgba = [0; 12];
+        to_rgba(&colours, &[0x00, 0x16, 0x1D6], &mut rgba);
+        assert_eq!(rgba[..8], [84, 84, 84, 255, 152, 34, 32, 255]);
+        assert_eq!(rgba[8..], colours[0x1D6]);
+    }
+}
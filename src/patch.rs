@@ -0,0 +1,564 @@
+use std::{convert::TryFrom, fmt};
+
+// Far past any NES or FDS image. BPS and UPS give the output size up front, and a bigger one
+// is refused rather than allocated.
+pub const MAX_TARGET_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,
+    Overflow,
+    SourceChecksum { expected: u32, actual: u32 },
+    TargetChecksum { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 },
+    SourceSize { expected: usize, actual: usize },
+    TargetSize(usize),
+    TargetOverrun,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, BPS or UPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::Overflow => write!(f, "patch has an out of range number"),
+            PatchError::SourceChecksum { expected, actual } => write!(
+                f,
+                "patch is for a different ROM (CRC32 {:08X}, expected {:08X})",
+                actual, expected
+            ),
+            PatchError::TargetChecksum { expected, actual } => write!(
+                f,
+                "patched ROM has CRC32 {:08X}, expected {:08X}",
+                actual, expected
+            ),
+            PatchError::PatchChecksum { expected, actual } => write!(
+                f,
+                "patch is corrupt (CRC32 {:08X}, expected {:08X})",
+                actual, expected
+            ),
+            PatchError::SourceSize { expected, actual } => write!(
+                f,
+                "patch is for a different ROM ({} bytes, expected {})",
+                actual, expected
+            ),
+            PatchError::TargetSize(size) => write!(
+                f,
+                "patch makes a {} byte ROM, more than the {} byte limit",
+                size, MAX_TARGET_SIZE
+            ),
+            PatchError::TargetOverrun => write!(f, "patch writes past the end of its target"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, rom)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, rom)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(patch, rom)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// Builds an IPS patch turning `source` into `target`
+pub fn create_ips(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    let mut pos = 0;
+
+    while pos < target.len() {
+        if source.get(pos) == Some(&target[pos]) {
+            pos += 1;
+            continue;
+        }
+
+        // An offset of 0x454F46 would read as the "EOF" marker
+        let start = if pos == 0x454F46 { pos - 1 } else { pos };
+        let mut end = pos;
+        while end < target.len() && end - start < 0xFFFF && source.get(end) != Some(&target[end]) {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        pos = end;
+    }
+
+    patch.extend_from_slice(b"EOF");
+
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+
+    patch
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        PatchReader { data, pos }
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let b = *self.data.get(self.pos).ok_or(PatchError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(PatchError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &b| (value << 8) | b as usize))
+    }
+
+    // Variable length number shared by BPS and UPS
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+
+        // A corrupt patch can run on for more bytes than a usize holds
+        loop {
+            let b = self.byte()?;
+            value = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|v| value.checked_add(v))
+                .ok_or(PatchError::Overflow)?;
+
+            if (b & 0x80) > 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Overflow)?;
+            value = value.checked_add(shift).ok_or(PatchError::Overflow)?;
+        }
+    }
+}
+
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut output = rom.to_vec();
+    let mut reader = PatchReader::new(patch, 5);
+
+    loop {
+        if reader.data.get(reader.pos..reader.pos + 3) == Some(b"EOF") {
+            reader.pos += 3;
+            break;
+        }
+
+        let offset = reader.be(3)?;
+        let size = reader.be(2)?;
+
+        if size == 0 {
+            // RLE record
+            let count = reader.be(2)?;
+            let value = reader.byte()?;
+
+            if output.len() < offset + count {
+                output.resize(offset + count, 0);
+            }
+            output[offset..offset + count].fill(value);
+        } else {
+            let data = reader.bytes(size)?;
+
+            if output.len() < offset + size {
+                output.resize(offset + size, 0);
+            }
+            output[offset..offset + size].copy_from_slice(data);
+        }
+    }
+
+    // Optional truncation extension
+    if let Ok(len) = reader.be(3) {
+        output.truncate(len);
+    }
+
+    Ok(output)
+}
+
+// BPS and UPS both end with source, target and patch CRC32s
+fn check_footer(patch: &[u8], source: &[u8]) -> Result<(u32, usize), PatchError> {
+    if patch.len() < 16 {
+        return Err(PatchError::Truncated);
+    }
+
+    let footer = patch.len() - 12;
+    let crc = |i: usize| u32::from_le_bytes([patch[i], patch[i + 1], patch[i + 2], patch[i + 3]]);
+
+    let expected = crc(footer + 8);
+    let actual = crc32fast::hash(&patch[..footer + 8]);
+    if expected != actual {
+        return Err(PatchError::PatchChecksum { expected, actual });
+    }
+
+    let expected = crc(footer);
+    let actual = crc32fast::hash(source);
+    if expected != actual {
+        return Err(PatchError::SourceChecksum { expected, actual });
+    }
+
+    Ok((crc(footer + 4), footer))
+}
+
+fn check_target(output: Vec<u8>, expected: u32) -> Result<Vec<u8>, PatchError> {
+    let actual = crc32fast::hash(&output);
+
+    if expected != actual {
+        Err(PatchError::TargetChecksum { expected, actual })
+    } else {
+        Ok(output)
+    }
+}
+
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (target_crc, footer) = check_footer(patch, rom)?;
+    let mut reader = PatchReader::new(&patch[..footer], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSize {
+            expected: source_size,
+            actual: rom.len(),
+        });
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetSize(target_size));
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+
+    let relative = |reader: &mut PatchReader, offset: &mut isize| -> Result<(), PatchError> {
+        let data = reader.number()?;
+        let delta = (data >> 1) as isize;
+        *offset = if (data & 1) > 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        }
+        .ok_or(PatchError::Overflow)?;
+        Ok(())
+    };
+
+    while reader.pos < footer {
+        let data = reader.number()?;
+        let length = (data >> 2) + 1;
+
+        // Every action appends, so none may take the output past the size given up front
+        let end = output
+            .len()
+            .checked_add(length)
+            .filter(|&end| end <= target_size)
+            .ok_or(PatchError::TargetOverrun)?;
+
+        match data & 0x03 {
+            // SourceRead
+            0 => {
+                let bytes = rom.get(output.len()..end).ok_or(PatchError::Truncated)?;
+                output.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => {
+                output.extend_from_slice(reader.bytes(length)?);
+            }
+            // SourceCopy
+            2 => {
+                relative(&mut reader, &mut source_offset)?;
+                let start = usize::try_from(source_offset).map_err(|_| PatchError::Truncated)?;
+                let source_end = start.checked_add(length).ok_or(PatchError::Overflow)?;
+                let bytes = rom.get(start..source_end).ok_or(PatchError::Truncated)?;
+                output.extend_from_slice(bytes);
+                source_offset = isize::try_from(source_end).map_err(|_| PatchError::Overflow)?;
+            }
+            // TargetCopy, byte by byte as the ranges may overlap
+            _ => {
+                relative(&mut reader, &mut target_offset)?;
+                for _ in 0..length {
+                    let b = *usize::try_from(target_offset)
+                        .ok()
+                        .and_then(|i| output.get(i))
+                        .ok_or(PatchError::Truncated)?;
+                    output.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    check_target(output, target_crc)
+}
+
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (target_crc, footer) = check_footer(patch, rom)?;
+    let mut reader = PatchReader::new(&patch[..footer], 4);
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSize {
+            expected: source_size,
+            actual: rom.len(),
+        });
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetSize(target_size));
+    }
+
+    let mut output = rom.to_vec();
+    output.resize(target_size, 0);
+    let mut pos: usize = 0;
+
+    while reader.pos < footer {
+        pos = pos
+            .checked_add(reader.number()?)
+            .ok_or(PatchError::Overflow)?;
+
+        // XOR bytes in until a zero, which ends the run and XORs nothing
+        loop {
+            let x = reader.byte()?;
+            if pos < output.len() {
+                output[pos] ^= x;
+            }
+            pos = pos.checked_add(1).ok_or(PatchError::Overflow)?;
+
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(output, target_crc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes the variable length numbers BPS and UPS use
+    fn number(patch: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let x = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                patch.push(0x80 | x);
+                return;
+            }
+            patch.push(x);
+            value -= 1;
+        }
+    }
+
+    fn footer(patch: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn ips() {
+        let rom = [0_u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // Two bytes at 1, four $EE from 4 (RLE), then two bytes past the end of the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0xEE]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x09, 0x00, 0x02, 0x11, 0x22]);
+        patch.extend_from_slice(b"EOF");
+
+        let output = apply(&patch, &rom).unwrap();
+        assert_eq!(
+            output,
+            [0, 0xAA, 0xBB, 0, 0xEE, 0xEE, 0xEE, 0xEE, 0, 0x11, 0x22]
+        );
+
+        // The truncation extension cuts the output after EOF
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(apply(&patch, &rom).unwrap(), [0, 0xAA, 0xBB]);
+
+        patch.truncate(10);
+        assert!(matches!(apply(&patch, &rom), Err(PatchError::Truncated)));
+        assert!(matches!(
+            apply(b"NOPE", &rom),
+            Err(PatchError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let source: Vec<u8> = (0..0x460000).map(|i| (i % 251) as u8).collect();
+
+        let mut target = source.clone();
+        target[0] = 0xFF;
+        target[0x1000..0x1000 + 0x20000]
+            .iter_mut()
+            .for_each(|b| *b = 0);
+        // A change at $454F46 can't start a record, it would read as "EOF"
+        target[0x454F46] ^= 0xFF;
+        target.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            apply(&create_ips(&source, &target), &source).unwrap(),
+            target
+        );
+
+        // Shorter targets use the truncation extension
+        target.truncate(0x2000);
+        assert_eq!(
+            apply(&create_ips(&source, &target), &source).unwrap(),
+            target
+        );
+
+        assert_eq!(create_ips(&source, &source), b"PATCHEOF");
+    }
+
+    #[test]
+    fn bps() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 2, 9, 9, 9, 1, 2, 1, 2];
+
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, source.len());
+        number(&mut patch, target.len());
+        number(&mut patch, 0);
+        // SourceRead 2, TargetRead 3, SourceCopy 2 from 0, TargetCopy 2 from 0
+        number(&mut patch, 1 << 2);
+        number(&mut patch, (2 << 2) | 1);
+        patch.extend_from_slice(&[9, 9, 9]);
+        number(&mut patch, (1 << 2) | 2);
+        number(&mut patch, 0);
+        number(&mut patch, (1 << 2) | 3);
+        number(&mut patch, 0);
+
+        let mut good = patch.clone();
+        footer(&mut good, &source, &target);
+        assert_eq!(apply(&good, &source).unwrap(), target);
+
+        assert!(matches!(
+            apply(&good, &[1, 2, 3, 5]),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+
+        let mut wrong_target = patch.clone();
+        footer(&mut wrong_target, &source, &[0]);
+        assert!(matches!(
+            apply(&wrong_target, &source),
+            Err(PatchError::TargetChecksum { .. })
+        ));
+
+        let mut corrupt = good.clone();
+        corrupt[10] ^= 0xFF;
+        assert!(matches!(
+            apply(&corrupt, &source),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+
+        // A number longer than a usize is refused instead of overflowing
+        let mut overflow = b"BPS1".to_vec();
+        overflow.extend_from_slice(&[0x7F; 12]);
+        overflow.push(0x80);
+        footer(&mut overflow, &source, &target);
+        assert!(matches!(
+            apply(&overflow, &source),
+            Err(PatchError::Overflow)
+        ));
+
+        // Sizes and actions are checked before anything is allocated or copied
+        let bps = |target_size: usize, actions: &[usize]| {
+            let mut patch = b"BPS1".to_vec();
+            number(&mut patch, source.len());
+            number(&mut patch, target_size);
+            number(&mut patch, 0);
+            for &action in actions {
+                number(&mut patch, action);
+            }
+            footer(&mut patch, &source, &target);
+            apply(&patch, &source)
+        };
+        assert!(matches!(
+            bps(1 << 40, &[]),
+            Err(PatchError::TargetSize(size)) if size == 1 << 40
+        ));
+        // SourceRead 4 into a 3 byte target
+        assert!(matches!(bps(3, &[3 << 2]), Err(PatchError::TargetOverrun)));
+        assert!(matches!(
+            bps(4, &[usize::MAX]),
+            Err(PatchError::TargetOverrun)
+        ));
+        // SourceCopy from as far as an offset can go
+        assert!(matches!(
+            bps(4, &[2, (isize::MAX as usize) << 1]),
+            Err(PatchError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn ups() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 7, 3, 4, 5];
+
+        let mut patch = b"UPS1".to_vec();
+        number(&mut patch, source.len());
+        number(&mut patch, target.len());
+        // Skip 1 and XOR the next byte, then skip 1 more and XOR the byte after the source
+        number(&mut patch, 1);
+        patch.extend_from_slice(&[2 ^ 7, 0]);
+        number(&mut patch, 1);
+        patch.extend_from_slice(&[5, 0]);
+
+        let mut good = patch.clone();
+        footer(&mut good, &source, &target);
+        assert_eq!(apply(&good, &source).unwrap(), target);
+
+        assert!(matches!(
+            apply(&good, &[1, 2, 3]),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+
+        let mut wrong_target = patch;
+        footer(&mut wrong_target, &source, &source);
+        assert!(matches!(
+            apply(&wrong_target, &source),
+            Err(PatchError::TargetChecksum { .. })
+        ));
+
+        let mut corrupt = good;
+        corrupt[6] ^= 0x01;
+        assert!(matches!(
+            apply(&corrupt, &source),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+
+        let mut huge = b"UPS1".to_vec();
+        number(&mut huge, source.len());
+        number(&mut huge, MAX_TARGET_SIZE + 1);
+        footer(&mut huge, &source, &target);
+        assert!(matches!(
+            apply(&huge, &source),
+            Err(PatchError::TargetSize(_))
+        ));
+    }
+}
@@ -1,25 +1,51 @@
 use bitfield::bitfield;
-use macroquad::prelude::*;
+use std::cell::RefCell;
 
-use crate::{cartridge, Cartridge};
+use crate::{
+    cartridge,
+    debugger::{AccessKind, AccessLog},
+    palette::{self, Palette},
+    region::Region,
+    Cartridge,
+};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+// All four nametables, laid out as they scroll
+pub const NAME_TABLES_WIDTH: usize = SCREEN_WIDTH * 2;
+pub const NAME_TABLES_HEIGHT: usize = SCREEN_HEIGHT * 2;
+
+// Frames an undriven I/O latch bit holds its value
+const IO_LATCH_DECAY_FRAMES: u8 = 36;
 
 pub struct Ppu {
     pub tbl_name: [[u8; 1024]; 2],
     tbl_palette: [u8; 32],
     tbl_pattern: [[u8; 4096]; 2], // Javid Future
-    pallete_screen: [Color; 0x40],
-    sprite_screen: Image,
-    sprite_name_table: [Image; 2],
-    sprite_pattern_table: [Image; 2],
+    // Indexed by the 9-bit output pixel: colour, then red, green and blue emphasis
+    pallete_screen: Palette,
+    // 9-bit output pixels, see output_pixel
+    sprite_screen: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
+    // Viewer buffers hold 6-bit colours
+    sprite_name_table: [u16; NAME_TABLES_WIDTH * NAME_TABLES_HEIGHT],
+    sprite_pattern_table: [[u16; 128 * 128]; 2],
     pub frame_complete: bool,
-    scanline: i16,
-    cycle: i16,
+    // Visible line whose scroll the nametable viewer shows, for games that split the screen
+    pub scroll_capture_line: i16,
+    scroll_capture: (usize, usize),
+    pub scanline: i16,
+    pub cycle: i16,
+    // PPU bus reads and writes, for watchpoints. Reads happen through &self
+    pub access_log: RefCell<AccessLog>,
     status: Status,
     mask: Mask,
     control: PpuControl,
     address_latch: u8,
     data_buffer: u8,
-    pub nmi: bool,
+    // Last value on the CPU data lines to the PPU, which reads of write-only bits return.
+    // Each bit fades back to 0 if it isn't driven for a while.
+    io_latch: u8,
+    io_latch_age: [u8; 8],
     vram_addr: LoopyRegister,
     tram_addr: LoopyRegister,
     fine_x: u8,
@@ -31,16 +57,32 @@ pub struct Ppu {
     bg_shifter_pattern_hi: u16,
     bg_shifter_attrib_lo: u16,
     bg_shifter_attrib_hi: u16,
-    pub oam: [ObjectAttributeEntry; 64],
+    // Four bytes a sprite: Y, tile, attributes and X
+    pub oam: [u8; 256],
     oam_addr: u8,
-    sprite_scanline: [ObjectAttributeEntry; 8],
+    secondary_oam: [u8; 32],
+    secondary_oam_addr: u8,
+    // Byte read from OAM on an odd dot of evaluation, for the even dot after it
+    oam_latch: u8,
+    sprite_eval: SpriteEval,
+    // Whether the first sprite evaluated is in range, making it sprite 0 next line
+    sprite_zero_next: bool,
+    // Off draws every sprite on a line instead of the first eight. Evaluation, and with it
+    // the overflow flag, works the same either way.
+    pub sprite_limit: bool,
+    sprite_scanline: [ObjectAttributeEntry; 64],
     sprite_count: u8,
-    sprite_shifter_pattern_lo: [u8; 8],
-    sprite_shifter_pattern_hi: [u8; 8],
+    sprite_shifter_pattern_lo: [u8; 64],
+    sprite_shifter_pattern_hi: [u8; 64],
     sprite_zero_hit_possible: bool,
     sprite_zero_being_rendered: bool,
     scanline_trigger: bool,
     odd_frame: bool,
+    // Colour subcarrier phase in thirds of a cycle, advancing two thirds a dot, and its value
+    // at the first pixel of the last frame. The skipped dot on odd frames shifts it.
+    colour_phase: u8,
+    frame_phase: u8,
+    region: Region,
 }
 
 bitfield! {
@@ -94,6 +136,21 @@ bitfield! {
     unused, _: 15;
 }
 
+// Sprite evaluation progress through a scanline, see "PPU sprite evaluation" on nesdev
+#[derive(Clone, Copy, PartialEq)]
+enum SpriteEval {
+    // Checking Y coordinates while secondary OAM has room
+    Search,
+    // Copying the rest of an in-range sprite, with this many bytes to go
+    Copy(u8),
+    // Secondary OAM is full, looking for a ninth sprite with the hardware's diagonal scan
+    Overflow,
+    // Reading the rest of the sprite that overflowed
+    OverflowCopy(u8),
+    // All 64 sprites seen, or the overflow found
+    Done,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct ObjectAttributeEntry {
@@ -114,26 +171,67 @@ impl ObjectAttributeEntry {
     }
 }
 
+// Buffers of palette pixels, row by row, for palette::to_rgba
+pub struct NameTableTile {
+    pub address: u16,
+    pub id: u8,
+    pub attribute_address: u16,
+    // Background palette, 0-3
+    pub palette: u8,
+}
+
 pub trait Debug {
-    fn get_screen(&self) -> &Image;
-    fn get_name_table(&self, i: u8) -> &Image;
-    fn get_pattern_table(&mut self, i: u8, pallet: &u8, cart: &mut Cartridge) -> &Image;
+    fn get_screen(&self) -> &[u16];
+    fn get_name_tables(&mut self, cart: &mut Cartridge) -> &[u16];
+    fn get_pattern_table(&mut self, i: u8, pallet: &u8, cart: &mut Cartridge) -> &[u16];
 }
 
 impl Debug for Ppu {
-    fn get_screen(&self) -> &Image {
+    fn get_screen(&self) -> &[u16] {
         &self.sprite_screen
     }
 
-    fn get_name_table(&self, i: u8) -> &Image {
-        &self.sprite_name_table[i as usize]
+    fn get_name_tables(&mut self, cart: &mut Cartridge) -> &[u16] {
+        let pattern: u16 = if self.control.pattern_background() {
+            0x1000
+        } else {
+            0x0000
+        };
+        let backdrop = self.get_colour_from_pallet_ram(cart, 0, 0);
+
+        for y in (0..NAME_TABLES_HEIGHT).step_by(8) {
+            for x in (0..NAME_TABLES_WIDTH).step_by(8) {
+                let tile = self.name_table_tile(cart, x, y);
+
+                for row in 0..8 {
+                    let address = pattern + (tile.id as u16) * 16 + row as u16;
+                    let mut tile_lsb = self.ppu_read(cart, address, false);
+                    let mut tile_msb = self.ppu_read(cart, address + 8, false);
+
+                    for col in (0..8).rev() {
+                        let pixel = (tile_msb & 0x01).wrapping_shl(1) | (tile_lsb & 0x01);
+                        tile_lsb >>= 1;
+                        tile_msb >>= 1;
+
+                        self.sprite_name_table[(y + row) * NAME_TABLES_WIDTH + x + col] =
+                            if pixel == 0 {
+                                backdrop
+                            } else {
+                                self.get_colour_from_pallet_ram(cart, tile.palette, pixel)
+                            };
+                    }
+                }
+            }
+        }
+
+        &self.sprite_name_table
     }
 
-    fn get_pattern_table(&mut self, i: u8, pallete: &u8, cart: &mut Cartridge) -> &Image {
+    fn get_pattern_table(&mut self, i: u8, pallete: &u8, cart: &mut Cartridge) -> &[u16] {
         for tile_y in 0_u16..16 {
             for tile_x in 0_u16..16 {
                 let offset: u16 = tile_y
-                    .wrapping_mul(self.get_screen().width)
+                    .wrapping_mul(256)
                     .wrapping_add(tile_x.wrapping_mul(16));
 
                 for row in 0_u16..8 {
@@ -161,11 +259,10 @@ impl Debug for Ppu {
                         tile_lsb = tile_lsb.wrapping_shr(1);
                         tile_msb = tile_msb.wrapping_shr(1);
 
-                        self.sprite_pattern_table[i as usize].set_pixel(
-                            tile_x.wrapping_mul(8).wrapping_add(7_u16.wrapping_sub(col)) as u32,
-                            tile_y.wrapping_mul(8).wrapping_add(row) as u32,
-                            self.get_colour_from_pallet_ram(cart, pallete.clone(), pixel.clone()),
-                        );
+                        let x = tile_x.wrapping_mul(8).wrapping_add(7_u16.wrapping_sub(col));
+                        let y = tile_y.wrapping_mul(8).wrapping_add(row);
+                        self.sprite_pattern_table[i as usize][(y * 128 + x) as usize] =
+                            self.get_colour_from_pallet_ram(cart, *pallete, pixel);
                     }
                 }
             }
@@ -177,90 +274,17 @@ impl Debug for Ppu {
 
 impl Ppu {
     pub fn new() -> Self {
-        let mut pallet = [BLACK; 64];
-        pallet[0x00] = Color::from_rgba(84, 84, 84, 255);
-        pallet[0x01] = Color::from_rgba(0, 30, 116, 255);
-        pallet[0x02] = Color::from_rgba(8, 16, 144, 255);
-        pallet[0x03] = Color::from_rgba(48, 0, 136, 255);
-        pallet[0x04] = Color::from_rgba(68, 0, 100, 255);
-        pallet[0x05] = Color::from_rgba(92, 0, 48, 255);
-        pallet[0x06] = Color::from_rgba(84, 4, 0, 255);
-        pallet[0x07] = Color::from_rgba(60, 24, 0, 255);
-        pallet[0x08] = Color::from_rgba(32, 42, 0, 255);
-        pallet[0x09] = Color::from_rgba(8, 58, 0, 255);
-        pallet[0x0A] = Color::from_rgba(0, 64, 0, 255);
-        pallet[0x0B] = Color::from_rgba(0, 60, 0, 255);
-        pallet[0x0C] = Color::from_rgba(0, 50, 60, 255);
-        pallet[0x0D] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x0E] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x0F] = Color::from_rgba(0, 0, 0, 255);
-
-        pallet[0x10] = Color::from_rgba(152, 150, 152, 255);
-        pallet[0x11] = Color::from_rgba(8, 76, 196, 255);
-        pallet[0x12] = Color::from_rgba(48, 50, 236, 255);
-        pallet[0x13] = Color::from_rgba(92, 30, 228, 255);
-        pallet[0x14] = Color::from_rgba(136, 20, 176, 255);
-        pallet[0x15] = Color::from_rgba(160, 20, 100, 255);
-        pallet[0x16] = Color::from_rgba(152, 34, 32, 255);
-        pallet[0x17] = Color::from_rgba(120, 60, 0, 255);
-        pallet[0x18] = Color::from_rgba(84, 90, 0, 255);
-        pallet[0x19] = Color::from_rgba(40, 114, 0, 255);
-        pallet[0x1A] = Color::from_rgba(8, 124, 0, 255);
-        pallet[0x1B] = Color::from_rgba(0, 118, 40, 255);
-        pallet[0x1C] = Color::from_rgba(0, 102, 120, 255);
-        pallet[0x1D] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x1E] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x1F] = Color::from_rgba(0, 0, 0, 255);
-
-        pallet[0x20] = Color::from_rgba(236, 238, 236, 255);
-        pallet[0x21] = Color::from_rgba(76, 154, 236, 255);
-        pallet[0x22] = Color::from_rgba(120, 124, 236, 255);
-        pallet[0x23] = Color::from_rgba(176, 98, 236, 255);
-        pallet[0x24] = Color::from_rgba(228, 84, 236, 255);
-        pallet[0x25] = Color::from_rgba(236, 88, 180, 255);
-        pallet[0x26] = Color::from_rgba(236, 106, 100, 255);
-        pallet[0x27] = Color::from_rgba(212, 136, 32, 255);
-        pallet[0x28] = Color::from_rgba(160, 170, 0, 255);
-        pallet[0x29] = Color::from_rgba(116, 196, 0, 255);
-        pallet[0x2A] = Color::from_rgba(76, 208, 32, 255);
-        pallet[0x2B] = Color::from_rgba(56, 204, 108, 255);
-        pallet[0x2C] = Color::from_rgba(56, 180, 204, 255);
-        pallet[0x2D] = Color::from_rgba(60, 60, 60, 255);
-        pallet[0x2E] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x2F] = Color::from_rgba(0, 0, 0, 255);
-
-        pallet[0x30] = Color::from_rgba(236, 238, 236, 255);
-        pallet[0x31] = Color::from_rgba(168, 204, 236, 255);
-        pallet[0x32] = Color::from_rgba(188, 188, 236, 255);
-        pallet[0x33] = Color::from_rgba(212, 178, 236, 255);
-        pallet[0x34] = Color::from_rgba(236, 174, 236, 255);
-        pallet[0x35] = Color::from_rgba(236, 174, 212, 255);
-        pallet[0x36] = Color::from_rgba(236, 180, 176, 255);
-        pallet[0x37] = Color::from_rgba(228, 196, 144, 255);
-        pallet[0x38] = Color::from_rgba(204, 210, 120, 255);
-        pallet[0x39] = Color::from_rgba(180, 222, 120, 255);
-        pallet[0x3A] = Color::from_rgba(168, 226, 144, 255);
-        pallet[0x3B] = Color::from_rgba(152, 226, 180, 255);
-        pallet[0x3C] = Color::from_rgba(160, 214, 228, 255);
-        pallet[0x3D] = Color::from_rgba(160, 162, 160, 255);
-        pallet[0x3E] = Color::from_rgba(0, 0, 0, 255);
-        pallet[0x3F] = Color::from_rgba(0, 0, 0, 255);
-
         Ppu {
             tbl_name: [[0; 1024]; 2],
             tbl_palette: [0; 32],
             tbl_pattern: [[0; 4096]; 2],
-            pallete_screen: pallet,
-            sprite_screen: Image::gen_image_color(256, 240, WHITE),
-            sprite_name_table: [
-                Image::gen_image_color(256, 240, WHITE),
-                Image::gen_image_color(256, 240, WHITE),
-            ],
-            sprite_pattern_table: [
-                Image::gen_image_color(128, 128, WHITE),
-                Image::gen_image_color(128, 128, WHITE),
-            ],
+            pallete_screen: palette::builtin(),
+            sprite_screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            sprite_name_table: [0; NAME_TABLES_WIDTH * NAME_TABLES_HEIGHT],
+            sprite_pattern_table: [[0; 128 * 128]; 2],
             frame_complete: false,
+            scroll_capture_line: 0,
+            scroll_capture: (0, 0),
             scanline: 0,
             cycle: 0,
             status: Status(0),
@@ -268,7 +292,8 @@ impl Ppu {
             control: PpuControl(0),
             address_latch: 0x00,
             data_buffer: 0x00,
-            nmi: false,
+            io_latch: 0x00,
+            io_latch_age: [0; 8],
             vram_addr: LoopyRegister(0),
             tram_addr: LoopyRegister(0),
             fine_x: 0x00,
@@ -280,20 +305,32 @@ impl Ppu {
             bg_shifter_pattern_hi: 0x0000,
             bg_shifter_attrib_lo: 0x0000,
             bg_shifter_attrib_hi: 0x0000,
-            oam: [ObjectAttributeEntry::new(0, 0, 0, 0); 64],
+            oam: [0; 256],
             oam_addr: 0x00,
-            sprite_scanline: [ObjectAttributeEntry::new(0, 0, 0, 0); 8],
+            secondary_oam: [0xFF; 32],
+            secondary_oam_addr: 0x00,
+            oam_latch: 0x00,
+            sprite_eval: SpriteEval::Done,
+            sprite_zero_next: false,
+            sprite_limit: true,
+            sprite_scanline: [ObjectAttributeEntry::new(0, 0, 0, 0); 64],
             sprite_count: 0x00,
-            sprite_shifter_pattern_lo: [0; 8],
-            sprite_shifter_pattern_hi: [0; 8],
+            sprite_shifter_pattern_lo: [0; 64],
+            sprite_shifter_pattern_hi: [0; 64],
             sprite_zero_hit_possible: false,
             sprite_zero_being_rendered: false,
             scanline_trigger: false,
             odd_frame: false,
+            colour_phase: 0,
+            frame_phase: 0,
+            region: Region::Ntsc,
+            access_log: RefCell::new(AccessLog::default()),
         }
     }
 
     pub fn cpu_write(&mut self, cart: &mut Cartridge, addr: u16, data: u8) {
+        self.refresh_io_latch(data, 0xFF);
+
         match addr {
             0x0000 => {
                 self.control.0 = data;
@@ -307,21 +344,20 @@ impl Ppu {
             0x0003 => {
                 self.oam_addr = data;
             } // OAM Address
-            0x0004 => match self.oam_addr % 4 {
-                0 => {
-                    self.oam[(self.oam_addr / 4) as usize].y = data;
-                }
-                1 => {
-                    self.oam[(self.oam_addr / 4) as usize].id = data;
-                }
-                2 => {
-                    self.oam[(self.oam_addr / 4) as usize].attribute = data;
-                }
-                3 => {
-                    self.oam[(self.oam_addr / 4) as usize].x = data;
+            0x0004 => {
+                if self.rendering() {
+                    // Writes are ignored while rendering, but bump OAMADDR to the next sprite
+                    self.oam_addr = self.oam_addr.wrapping_add(4);
+                } else {
+                    self.oam[self.oam_addr as usize] = if self.oam_addr % 4 == 2 {
+                        // Attribute bits 2-4 aren't stored
+                        data & 0xE3
+                    } else {
+                        data
+                    };
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
                 }
-                _ => (),
-            }, // OAM Data
+            } // OAM Data
             0x0005 => {
                 if self.address_latch == 0 {
                     self.fine_x = data & 0x07;
@@ -346,10 +382,7 @@ impl Ppu {
             } // PPU Address
             0x0007 => {
                 self.ppu_write(cart, self.vram_addr.0, data);
-                self.vram_addr.0 = self
-                    .vram_addr
-                    .0
-                    .wrapping_add(if self.control.increment_mode() { 32 } else { 1 });
+                self.increment_vram_addr();
             } // PPU Data
             _ => {}
         }
@@ -377,44 +410,47 @@ impl Ppu {
                 _ => {}
             }
         } else {
+            // Write-only registers read back the I/O latch
+            data = self.io_latch;
+
             match addr {
                 0x0000 => {} // Control
                 0x0001 => {} // Mask
                 0x0002 => {
-                    data = (self.status.0 & 0xE0) | (self.data_buffer & 0x1F);
+                    data = (self.status.0 & 0xE0) | (self.io_latch & 0x1F);
+                    self.refresh_io_latch(data, 0xE0);
                     self.status.set_vertical_blank(false);
                     self.address_latch = 0;
                 } // Status
                 0x0003 => {} // OAM Address
-                0x0004 => match self.oam_addr % 4 {
-                    0 => {
-                        data = self.oam[(self.oam_addr / 4) as usize].y;
-                    }
-                    1 => {
-                        data = self.oam[(self.oam_addr / 4) as usize].id;
-                    }
-                    2 => {
-                        data = self.oam[(self.oam_addr / 4) as usize].attribute;
-                    }
-                    3 => {
-                        data = self.oam[(self.oam_addr / 4) as usize].x;
-                    }
-                    _ => (),
-                }, // OAM Data
+                0x0004 => {
+                    data = if self.rendering() {
+                        self.oam_bus()
+                    } else {
+                        self.oam[self.oam_addr as usize]
+                    };
+                    self.refresh_io_latch(data, 0xFF);
+                } // OAM Data
                 0x0005 => {} // Scroll
                 0x0006 => {} // PPU Address
                 0x0007 => {
-                    data = self.data_buffer;
-                    self.data_buffer = self.ppu_read(cart, self.vram_addr.0, false);
-
-                    if self.vram_addr.0 >= 0x3F00 {
+                    if self.vram_addr.0 & 0x3FFF >= 0x3F00 {
+                        // Palette reads skip the buffer, which picks up the nametable byte
+                        // underneath instead. Palette entries are six bits wide.
+                        let mut colour = self.ppu_read(cart, self.vram_addr.0, false) & 0x3F;
+                        if self.mask.grayscale() {
+                            colour &= 0x30;
+                        }
+                        data = colour | (self.io_latch & 0xC0);
+                        self.refresh_io_latch(data, 0x3F);
+                        self.data_buffer = self.ppu_read(cart, self.vram_addr.0 - 0x1000, false);
+                    } else {
                         data = self.data_buffer;
+                        self.refresh_io_latch(data, 0xFF);
+                        self.data_buffer = self.ppu_read(cart, self.vram_addr.0, false);
                     }
 
-                    self.vram_addr.0 = self
-                        .vram_addr
-                        .0
-                        .wrapping_add(if self.control.increment_mode() { 32 } else { 1 });
+                    self.increment_vram_addr();
                 } // PPU Data
                 _ => {}
             }
@@ -425,6 +461,9 @@ impl Ppu {
 
     pub fn ppu_write(&mut self, cart: &mut Cartridge, mut addr: u16, data: u8) {
         addr &= 0x3FFF;
+        self.access_log
+            .get_mut()
+            .record(AccessKind::Write, addr, data);
 
         if cart.ppu_write(addr, data) {
         } else if addr <= 0x1FFF {
@@ -461,8 +500,12 @@ impl Ppu {
                         self.tbl_name[1][(addr & 0x03FF) as usize] = data
                     }
                 }
-                cartridge::Mirror::OneScreenLo => todo!(),
-                cartridge::Mirror::OneScreenHi => todo!(),
+                cartridge::Mirror::OneScreenLo => {
+                    self.tbl_name[0][(addr & 0x03FF) as usize] = data;
+                }
+                cartridge::Mirror::OneScreenHi => {
+                    self.tbl_name[1][(addr & 0x03FF) as usize] = data;
+                }
                 cartridge::Mirror::Hardware => todo!(),
             }
         } else if addr >= 0x3F00 && addr <= 0x3FFF {
@@ -480,6 +523,7 @@ impl Ppu {
     pub fn ppu_read(&self, cart: &mut Cartridge, mut addr: u16, _b_read_only: bool) -> u8 {
         let mut data: u8 = 0x00;
         addr &= 0x3FFF;
+        let access_addr = addr;
 
         if cart.ppu_read(addr, &mut data) {
         } else if addr <= 0x1FFF {
@@ -516,8 +560,12 @@ impl Ppu {
                         data = self.tbl_name[1][(addr & 0x03FF) as usize];
                     }
                 }
-                cartridge::Mirror::OneScreenLo => todo!(),
-                cartridge::Mirror::OneScreenHi => todo!(),
+                cartridge::Mirror::OneScreenLo => {
+                    data = self.tbl_name[0][(addr & 0x03FF) as usize];
+                }
+                cartridge::Mirror::OneScreenHi => {
+                    data = self.tbl_name[1][(addr & 0x03FF) as usize];
+                }
                 cartridge::Mirror::Hardware => todo!(),
             }
         } else if addr >= 0x3F00 && addr <= 0x3FFF {
@@ -528,13 +576,49 @@ impl Ppu {
                 _ => (),
             }
 
-            data =
-                self.tbl_palette[addr as usize] & (if self.mask.grayscale() { 0x30 } else { 0x3F });
+            data = self.tbl_palette[addr as usize];
         }
 
+        self.access_log
+            .borrow_mut()
+            .record(AccessKind::Read, access_addr, data);
+
         data
     }
 
+    fn refresh_io_latch(&mut self, data: u8, bits: u8) {
+        self.io_latch = (self.io_latch & !bits) | (data & bits);
+        for (bit, age) in self.io_latch_age.iter_mut().enumerate() {
+            if bits & (1 << bit) != 0 {
+                *age = 0;
+            }
+        }
+    }
+
+    // Called once a frame. Bits decay after roughly 600ms.
+    fn decay_io_latch(&mut self) {
+        for (bit, age) in self.io_latch_age.iter_mut().enumerate() {
+            *age = age.saturating_add(1);
+            if *age >= IO_LATCH_DECAY_FRAMES {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    // After a $2007 access. While rendering, the PPU is using the address for fetches and
+    // the access bumps coarse X and Y at once instead.
+    fn increment_vram_addr(&mut self) {
+        if self.rendering() {
+            self.increment_scroll_x();
+            self.increment_scroll_y();
+        } else {
+            self.vram_addr.0 = self
+                .vram_addr
+                .0
+                .wrapping_add(if self.control.increment_mode() { 32 } else { 1 });
+        }
+    }
+
     fn increment_scroll_x(&mut self) {
         if self.mask.render_background() || self.mask.render_sprites() {
             if self.vram_addr.coarse_x() == 31 {
@@ -605,6 +689,153 @@ impl Ppu {
             };
     }
 
+    pub fn oam_entry(&self, index: usize) -> ObjectAttributeEntry {
+        let sprite = &self.oam[index * 4..index * 4 + 4];
+        ObjectAttributeEntry::new(sprite[0], sprite[1], sprite[2], sprite[3])
+    }
+
+    // On the pre-render and visible lines with rendering on, OAM belongs to sprite evaluation
+    fn rendering(&self) -> bool {
+        self.scanline < 240 && (self.mask.render_background() || self.mask.render_sprites())
+    }
+
+    // What $2004 reads while rendering: whatever evaluation or the sprite fetches last read
+    fn oam_bus(&self) -> u8 {
+        match self.cycle {
+            1..=256 => self.oam_latch,
+            257..=320 => {
+                let dot = (self.cycle - 257) as usize;
+                self.secondary_oam[dot / 8 * 4 + (dot % 8).min(3)]
+            }
+            _ => self.secondary_oam[0],
+        }
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let height = if self.control.sprite_size() { 16 } else { 8 };
+        let diff = self.scanline - y as i16;
+        diff >= 0 && diff < height
+    }
+
+    // One dot of sprite evaluation for the next scanline. Dots 1-64 clear secondary OAM,
+    // then each sprite takes a read on an odd dot and a write on the even one, with OAMADDR
+    // as the pointer into OAM.
+    fn evaluate_sprites(&mut self) {
+        if self.cycle <= 64 {
+            if self.cycle % 2 == 0 {
+                self.secondary_oam[(self.cycle / 2 - 1) as usize] = 0xFF;
+            }
+            self.oam_latch = 0xFF;
+            return;
+        }
+
+        if self.cycle == 65 {
+            self.secondary_oam_addr = 0;
+            self.sprite_eval = SpriteEval::Search;
+            self.sprite_zero_next = false;
+        }
+
+        if self.cycle % 2 == 1 {
+            self.oam_latch = self.oam[self.oam_addr as usize];
+            return;
+        }
+
+        match self.sprite_eval {
+            SpriteEval::Search => {
+                self.secondary_oam[self.secondary_oam_addr as usize] = self.oam_latch;
+
+                if self.sprite_in_range(self.oam_latch) {
+                    if self.cycle == 66 {
+                        self.sprite_zero_next = true;
+                    }
+                    self.secondary_oam_addr += 1;
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                    self.sprite_eval = SpriteEval::Copy(3);
+                } else {
+                    let (addr, wrapped) = self.oam_addr.overflowing_add(4);
+                    self.oam_addr = addr;
+                    if wrapped {
+                        self.sprite_eval = SpriteEval::Done;
+                    }
+                }
+            }
+            SpriteEval::Copy(left) => {
+                self.secondary_oam[self.secondary_oam_addr as usize] = self.oam_latch;
+                self.secondary_oam_addr += 1;
+                let (addr, wrapped) = self.oam_addr.overflowing_add(1);
+                self.oam_addr = addr;
+
+                self.sprite_eval = if left > 1 {
+                    SpriteEval::Copy(left - 1)
+                } else if wrapped {
+                    SpriteEval::Done
+                } else if self.secondary_oam_addr == 32 {
+                    SpriteEval::Overflow
+                } else {
+                    SpriteEval::Search
+                };
+            }
+            SpriteEval::Overflow => {
+                if self.sprite_in_range(self.oam_latch) {
+                    self.status.set_sprite_overflow(true);
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                    self.sprite_eval = SpriteEval::OverflowCopy(3);
+                } else {
+                    // The hardware bug: moving to the next sprite also moves to the next
+                    // byte within it, so later "Y" checks look at tile, attribute or X bytes
+                    let n = (self.oam_addr / 4).wrapping_add(1) & 0x3F;
+                    let m = self.oam_addr.wrapping_add(1) & 0x03;
+                    self.oam_addr = n * 4 + m;
+                    if n == 0 {
+                        self.sprite_eval = SpriteEval::Done;
+                    }
+                }
+            }
+            SpriteEval::OverflowCopy(left) => {
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+                if left > 1 {
+                    self.sprite_eval = SpriteEval::OverflowCopy(left - 1);
+                } else {
+                    self.sprite_eval = SpriteEval::Done;
+                }
+            }
+            SpriteEval::Done => {
+                self.oam_addr = self.oam_addr.wrapping_add(4);
+            }
+        }
+    }
+
+    // Takes the sprites found by evaluation for the line about to be drawn
+    fn load_sprites(&mut self) {
+        self.sprite_count = 0;
+        self.sprite_shifter_pattern_lo = [0; 64];
+        self.sprite_shifter_pattern_hi = [0; 64];
+        self.sprite_zero_hit_possible = false;
+
+        // Nothing is evaluated on the pre-render line, so the first line never has sprites
+        if self.scanline < 0 || !(self.mask.render_background() || self.mask.render_sprites()) {
+            return;
+        }
+
+        self.sprite_zero_hit_possible = self.sprite_zero_next;
+
+        if self.sprite_limit {
+            let found = (self.secondary_oam_addr / 4) as usize;
+            for sprite in self.secondary_oam[..found * 4].chunks(4) {
+                self.sprite_scanline[self.sprite_count as usize] =
+                    ObjectAttributeEntry::new(sprite[0], sprite[1], sprite[2], sprite[3]);
+                self.sprite_count += 1;
+            }
+        } else {
+            for i in 0..64 {
+                if self.sprite_in_range(self.oam[i * 4]) {
+                    self.sprite_scanline[self.sprite_count as usize] = self.oam_entry(i);
+                    self.sprite_count += 1;
+                }
+            }
+        }
+    }
+
     fn update_shifters(&mut self) {
         if self.mask.render_background() {
             self.bg_shifter_pattern_lo = self.bg_shifter_pattern_lo.wrapping_shl(1);
@@ -629,24 +860,40 @@ impl Ppu {
     }
 
     pub fn clock(&mut self, cart: &mut Cartridge) {
+        self.colour_phase = (self.colour_phase + 2) % 3;
+
+        if self.scanline == self.scroll_capture_line && self.cycle == 0 {
+            self.capture_scroll();
+        }
+
         if self.scanline >= -1 && self.scanline < 240 {
             if self.scanline == 0
                 && self.cycle == 0
                 && self.odd_frame
+                && self.region.skips_odd_dot()
             && (self.mask.render_background() || self.mask.render_sprites())
         {
                 self.cycle = 1;
             }
 
+            if self.scanline == -1
+                && self.cycle == 1
+                && self.oam_addr >= 8
+                && (self.mask.render_background() || self.mask.render_sprites())
+            {
+                // Starting to render with OAMADDR past the first two sprites copies the
+                // eight bytes around it over them
+                let row = (self.oam_addr & 0xF8) as usize;
+                self.oam.copy_within(row..row + 8, 0);
+            }
+
             if self.scanline == -1 && self.cycle == 1 {
                 self.status.set_vertical_blank(false);
                 self.status.set_sprite_overflow(false);
                 self.status.set_sprite_zero_hit(false);
 
-                for i in 0_usize..8 {
-                    self.sprite_shifter_pattern_lo[i] = 0;
-                    self.sprite_shifter_pattern_hi[i] = 0;
-                }
+                self.sprite_shifter_pattern_lo = [0; 64];
+                self.sprite_shifter_pattern_hi = [0; 64];
             }
 
             if (self.cycle >= 2 && self.cycle < 258) || (self.cycle >= 321 && self.cycle < 338) {
@@ -728,41 +975,18 @@ impl Ppu {
 
             // Foreground Rendering
 
-            if self.cycle == 257 && self.scanline >= 0 {
-                self.sprite_scanline = [ObjectAttributeEntry::new(0xFF, 0xFF, 0xFF, 0xFF); 8];
-                self.sprite_count = 0;
-
-                for i in 0_usize..8 {
-                    self.sprite_shifter_pattern_lo[i] = 0;
-                    self.sprite_shifter_pattern_hi[i] = 0;
+            if self.mask.render_background() || self.mask.render_sprites() {
+                if self.scanline >= 0 && self.cycle >= 1 && self.cycle <= 256 {
+                    self.evaluate_sprites();
                 }
 
-                let mut oam_entry: u8 = 0;
-
-                self.sprite_zero_hit_possible = false;
-
-                while oam_entry < 64 && self.sprite_count < 9 {
-                    let diff: i16 = self
-                        .scanline
-                        .wrapping_sub(self.oam[oam_entry as usize].y as i16);
-
-                    let sprite_size = if self.control.sprite_size() { 16 } else { 8 };
-                    if diff >= 0 && diff < sprite_size && self.sprite_count < 8 {
-                        if self.sprite_count < 8 {
-                            if oam_entry == 0 {
-                                self.sprite_zero_hit_possible = true;
-                            }
-
-                            self.sprite_scanline[self.sprite_count as usize] =
-                                self.oam[oam_entry as usize].clone();
-                        }
-                            self.sprite_count = self.sprite_count.wrapping_add(1);
-                    }
-
-                    oam_entry = oam_entry.wrapping_add(1);
+                if self.cycle >= 257 && self.cycle <= 320 {
+                    self.oam_addr = 0x00;
                 }
+            }
 
-                self.status.set_sprite_overflow(self.sprite_count >= 8);
+            if self.cycle == 257 {
+                self.load_sprites();
             }
 
             if self.cycle == 340 {
@@ -862,14 +1086,8 @@ impl Ppu {
 
         if self.scanline == 240 {}
 
-        if self.scanline >= 241 && self.scanline < 261 {
-            if self.scanline == 241 && self.cycle == 1 {
-                self.status.set_vertical_blank(true);
-
-                if self.control.enable_nmi() {
-                    self.nmi = true;
-                }
-            }
+        if self.scanline == self.region.vblank_line() && self.cycle == 1 {
+            self.status.set_vertical_blank(true);
         }
 
         let mut bg_pixel: u8 = 0x00;
@@ -952,16 +1170,16 @@ impl Ppu {
             }
         }
 
-        if (self.cycle <= self.sprite_screen.width as i16)
+        if (self.cycle <= SCREEN_WIDTH as i16)
             && (self.cycle >= 1)
             && (self.scanline >= 0)
-            && (self.scanline < self.sprite_screen.height as i16)
+            && (self.scanline < SCREEN_HEIGHT as i16)
         {
-            self.sprite_screen.set_pixel(
-                (self.cycle - 1) as u32,
-                self.scanline as u32,
-                self.get_colour_from_pallet_ram(cart, palette.clone(), pixel.clone()),
-            );
+            self.sprite_screen[self.scanline as usize * SCREEN_WIDTH + (self.cycle - 1) as usize] =
+                self.output_pixel(cart, palette, pixel);
+            if self.scanline == 0 && self.cycle == 1 {
+                self.frame_phase = self.colour_phase;
+            }
         }
 
         self.cycle += 1;
@@ -976,14 +1194,52 @@ impl Ppu {
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline >= 261 {
+            if self.scanline >= self.region.scanlines() - 1 {
                 self.scanline = -1;
                 self.frame_complete = true;
                 self.odd_frame = !self.odd_frame;
+                self.decay_io_latch();
             }
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // Scanline and dot last drawn, numbering the pre-render line after vertical blank (261 on
+    // NTSC, 311 on PAL and Dendy) the way trace logs do
+    pub fn position(&self) -> (i16, i16) {
+        let (mut scanline, mut dot) = (self.scanline, self.cycle - 1);
+        if dot < 0 {
+            dot = 340;
+            scanline -= 1;
+        }
+        if scanline < 0 {
+            scanline += self.region.scanlines();
+        }
+        (scanline, dot)
+    }
+
+    pub fn set_palette(&mut self, colours: Palette) {
+        self.pallete_screen = colours;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.pallete_screen
+    }
+
+    // For the NTSC filter: each line starts a third of a cycle later than the one before
+    pub fn frame_phase(&self) -> u8 {
+        self.frame_phase
+    }
+
+    // Asserted while in vertical blank with NMI enabled, so enabling NMI during vertical
+    // blank raises another NMI as it does on hardware
+    pub fn nmi_line(&self) -> bool {
+        self.status.vertical_blank() && self.control.enable_nmi()
+    }
+
     pub fn reset(&mut self) {
         self.fine_x = 0x00;
         self.address_latch = 0x00;
@@ -1007,18 +1263,309 @@ impl Ppu {
         self.odd_frame = false;
     }
 
-    pub fn get_colour_from_pallet_ram(
-        &self,
-        cart: &mut Cartridge,
-        pallete: u8,
-        pixel: u8,
-    ) -> Color {
-        self.pallete_screen[(self.ppu_read(
+    // The 9-bit pixel the PPU sends to the video encoder: the 6-bit colour from palette RAM,
+    // with grayscale applied, and the emphasis bits above it
+    fn output_pixel(&self, cart: &mut Cartridge, palette: u8, pixel: u8) -> u16 {
+        let mut colour = self.ppu_read(
+            cart,
+            0x3F00_u16
+                .wrapping_add((palette.wrapping_shl(2)) as u16)
+                .wrapping_add(pixel as u16),
+            false,
+        ) & 0x3F;
+        if self.mask.grayscale() {
+            colour &= 0x30;
+        }
+
+        let mut emphasis = (self.mask.0 >> 5) as u16;
+        if self.region != Region::Ntsc {
+            // The PAL and Dendy PPUs swap the red and green bits
+            emphasis = (emphasis & 0b100) | ((emphasis & 0b001) << 1) | ((emphasis & 0b010) >> 1);
+        }
+
+        colour as u16 | (emphasis << 6)
+    }
+
+    // The tile under a point in the nametable viewer, read through the cartridge's mirroring
+    pub fn name_table_tile(&self, cart: &mut Cartridge, x: usize, y: usize) -> NameTableTile {
+        let table = (y / SCREEN_HEIGHT * 2 + x / SCREEN_WIDTH) as u16;
+        let tile_x = ((x % SCREEN_WIDTH) / 8) as u16;
+        let tile_y = ((y % SCREEN_HEIGHT) / 8) as u16;
+
+        let address = 0x2000 + table * 0x400 + tile_y * 32 + tile_x;
+        let attribute_address = 0x23C0 + table * 0x400 + (tile_y / 4) * 8 + tile_x / 4;
+        // Each attribute byte covers 4x4 tiles, two bits to each 2x2 quarter
+        let shift = (tile_y & 0x02) << 1 | (tile_x & 0x02);
+        let attribute = self.ppu_read(cart, attribute_address, false);
+
+        NameTableTile {
+            address,
+            id: self.ppu_read(cart, address, false),
+            attribute_address,
+            palette: (attribute >> shift) & 0x03,
+        }
+    }
+
+    // The top left of the picture in the nametable viewer, from the scroll the capture line
+    // was drawn with
+    pub fn scroll(&self) -> (usize, usize) {
+        self.scroll_capture
+    }
+
+    fn capture_scroll(&mut self) {
+        let position = |addr: &LoopyRegister| {
+            (
+                addr.nametable_x() as usize * SCREEN_WIDTH
+                    + addr.coarse_x() as usize * 8
+                    + self.fine_x as usize,
+                addr.nametable_y() as usize * SCREEN_HEIGHT
+                    + addr.coarse_y() as usize * 8
+                    + addr.fine_y() as usize,
+            )
+        };
+
+        self.scroll_capture = if self.mask.render_background() || self.mask.render_sprites() {
+            // By the start of a line the first two tiles are fetched and coarse X has moved
+            // on by two. Y has moved down a line for each line drawn.
+            let (x, y) = position(&self.vram_addr);
+            let line = self.scanline as usize;
+            (
+                (x + NAME_TABLES_WIDTH - 16) % NAME_TABLES_WIDTH,
+                (y + NAME_TABLES_HEIGHT - line % NAME_TABLES_HEIGHT) % NAME_TABLES_HEIGHT,
+            )
+        } else {
+            let (x, y) = position(&self.tram_addr);
+            (x % NAME_TABLES_WIDTH, y % NAME_TABLES_HEIGHT)
+        };
+    }
+
+    pub fn get_colour_from_pallet_ram(&self, cart: &mut Cartridge, pallete: u8, pixel: u8) -> u16 {
+        (self.ppu_read(
             cart,
             0x3F00_u16
                 .wrapping_add(((pallete).wrapping_shl(2)) as u16)
                 .wrapping_add(pixel as u16),
             false,
-        ) & 0x3F) as usize]
+        ) & 0x3F) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::nestest;
+
+    // Evaluates scanline 10 for the given OAM, 8x8 sprites
+    fn evaluate(oam: &[(u8, u8, u8, u8)], sprite_limit: bool) -> (bool, u8) {
+        let mut cart = nestest();
+        let mut ppu = Ppu::new();
+
+        ppu.sprite_limit = sprite_limit;
+        ppu.cpu_write(&mut cart, 0x0001, 0x18);
+        ppu.oam = [0xFF; 256];
+        for (entry, &(y, id, attribute, x)) in ppu.oam.chunks_mut(4).zip(oam) {
+            entry.copy_from_slice(&[y, id, attribute, x]);
+        }
+
+        ppu.scanline = 10;
+        ppu.cycle = 0;
+        while ppu.scanline == 10 {
+            ppu.clock(&mut cart);
+        }
+
+        (ppu.status.sprite_overflow(), ppu.sprite_count)
+    }
+
+    #[test]
+    fn sprite_overflow_bug() {
+        let mut oam = vec![(10, 0, 0, 0); 8];
+
+        // Nine on the line: found
+        oam.push((10, 0, 0, 0));
+        assert_eq!(evaluate(&oam, true), (true, 8));
+
+        // The ninth sprite's Y is checked, but the tenth is checked at its tile number
+        oam[8] = (100, 0, 0, 0);
+        oam.push((200, 10, 0, 0));
+        assert_eq!(evaluate(&oam, true), (true, 8));
+
+        // And so a real ninth sprite is missed
+        oam[9] = (10, 100, 0, 100);
+        assert_eq!(evaluate(&oam, true), (false, 8));
+
+        // Lifting the limit draws it without telling the game
+        assert_eq!(evaluate(&oam, false), (false, 9));
+    }
+
+    #[test]
+    fn oam_access() {
+        let mut cart = nestest();
+        let mut ppu = Ppu::new();
+        ppu.scanline = 241;
+
+        // Writes increment OAMADDR and wrap, attribute bytes drop bits 2-4
+        ppu.cpu_write(&mut cart, 0x0003, 0xFD);
+        for data in [0x11, 0xFF, 0x22, 0x33].iter() {
+            ppu.cpu_write(&mut cart, 0x0004, *data);
+        }
+        assert_eq!(ppu.oam[0xFD..], [0x11, 0xE3, 0x22]);
+        assert_eq!(ppu.oam[0], 0x33);
+
+        // Reads don't increment
+        ppu.cpu_write(&mut cart, 0x0003, 0xFE);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0004, false), 0xE3);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0004, false), 0xE3);
+
+        // Rendering starts with OAMADDR at $12, copying $10-$17 over the first two sprites
+        for i in 0..8 {
+            ppu.oam[0x10 + i] = i as u8;
+        }
+        ppu.cpu_write(&mut cart, 0x0003, 0x12);
+        ppu.cpu_write(&mut cart, 0x0001, 0x18);
+        ppu.scanline = -1;
+        ppu.cycle = 1;
+        ppu.clock(&mut cart);
+        assert_eq!(ppu.oam[..8], [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // And $2004 writes while rendering only move OAMADDR along a sprite
+        ppu.cpu_write(&mut cart, 0x0003, 0x00);
+        ppu.cpu_write(&mut cart, 0x0004, 0x55);
+        assert_eq!(ppu.oam_addr, 4);
+        assert_eq!(ppu.oam[0], 0);
+    }
+
+    #[test]
+    fn io_latch() {
+        let mut cart = nestest();
+        let mut ppu = Ppu::new();
+        ppu.scanline = 241;
+
+        // Write-only registers and the low bits of $2002 read back the last value written
+        ppu.cpu_write(&mut cart, 0x0003, 0x5F);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0000, false), 0x5F);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0002, false), 0x1F);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0005, false), 0x1F);
+
+        // Palette reads fill only the low six bits
+        ppu.cpu_write(&mut cart, 0x0006, 0x3F);
+        ppu.cpu_write(&mut cart, 0x0006, 0x00);
+        ppu.cpu_write(&mut cart, 0x0007, 0xC5);
+        ppu.cpu_write(&mut cart, 0x0006, 0x3F);
+        ppu.cpu_write(&mut cart, 0x0006, 0x00);
+        ppu.cpu_write(&mut cart, 0x0000, 0x80);
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0007, false), 0x85);
+
+        // Bits fade once they go undriven for long enough
+        for _ in 0..IO_LATCH_DECAY_FRAMES - 1 {
+            ppu.decay_io_latch();
+        }
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0001, false), 0x85);
+        ppu.decay_io_latch();
+        assert_eq!(ppu.cpu_read(&mut cart, 0x0001, false), 0x00);
+    }
+
+    #[test]
+    fn emphasis() {
+        let mut cart = nestest();
+        let mut ppu = Ppu::new();
+        ppu.tbl_palette[1] = 0x16;
+
+        // Red emphasis with grayscale: $10 gray, red kept, green and blue darkened
+        ppu.cpu_write(&mut cart, 0x0001, 0x21);
+        let pixel = ppu.output_pixel(&mut cart, 0, 1);
+        assert_eq!(pixel, 0x10 | 0x40);
+        let (gray, tinted) = (ppu.pallete_screen[0x10], ppu.pallete_screen[pixel as usize]);
+        assert_eq!(tinted[0], gray[0]);
+        assert!(tinted[1] < gray[1] && tinted[2] < gray[2]);
+
+        // PAL swaps the red and green bits
+        ppu.set_region(Region::Pal);
+        assert_eq!(ppu.output_pixel(&mut cart, 0, 1), 0x10 | 0x80);
+
+        // Black stays black
+        assert_eq!(ppu.pallete_screen[0x1CF], ppu.pallete_screen[0x0F]);
+    }
+
+    #[test]
+    fn position() {
+        let mut ppu = Ppu::new();
+        ppu.scanline = 10;
+        ppu.cycle = 21;
+        assert_eq!(ppu.position(), (10, 20));
+
+        // Dot 0 of the first visible line follows the last dot of the pre-render line
+        ppu.scanline = 0;
+        ppu.cycle = 0;
+        assert_eq!(ppu.position(), (261, 340));
+        ppu.scanline = -1;
+        ppu.cycle = 5;
+        assert_eq!(ppu.position(), (261, 4));
+
+        ppu.set_region(Region::Pal);
+        assert_eq!(ppu.position(), (311, 4));
+    }
+
+    #[test]
+    fn name_tables() {
+        let mut cart = nestest();
+        let mut ppu = Ppu::new();
+        ppu.scanline = 241;
+
+        // Tile $41 at column 5, row 3, in the top right quarter of its attribute byte
+        for &(address, data) in &[(0x2065_u16, 0x41), (0x23C1, 0x20), (0x3F00, 0x0F)] {
+            ppu.cpu_write(&mut cart, 0x0006, (address >> 8) as u8);
+            ppu.cpu_write(&mut cart, 0x0006, address as u8);
+            ppu.cpu_write(&mut cart, 0x0007, data);
+        }
+        for _ in 0..3 {
+            ppu.cpu_write(&mut cart, 0x0007, 0x16);
+        }
+        for address in [0x3F09_u16, 0x3F0A, 0x3F0B].iter() {
+            ppu.cpu_write(&mut cart, 0x0006, (address >> 8) as u8);
+            ppu.cpu_write(&mut cart, 0x0006, *address as u8);
+            ppu.cpu_write(&mut cart, 0x0007, 0x16);
+        }
+
+        let tile = ppu.name_table_tile(&mut cart, 5 * 8 + 3, 3 * 8 + 1);
+        assert_eq!(
+            (tile.address, tile.id, tile.attribute_address, tile.palette),
+            (0x2065, 0x41, 0x23C1, 2)
+        );
+
+        // nestest mirrors horizontally, so the right nametable is the left one again
+        let right = ppu.name_table_tile(&mut cart, 256 + 5 * 8, 3 * 8);
+        assert_eq!((right.address, right.id), (0x2465, 0x41));
+        assert_eq!(ppu.name_table_tile(&mut cart, 5 * 8, 240 + 3 * 8).id, 0x00);
+
+        let pixels = ppu.get_name_tables(&mut cart);
+        let tile: Vec<u16> = (24..32)
+            .flat_map(|y| pixels[y * NAME_TABLES_WIDTH + 40..y * NAME_TABLES_WIDTH + 48].to_vec())
+            .collect();
+        assert!(tile.contains(&0x16) && tile.contains(&0x0F));
+        assert!(tile.iter().all(|&c| c == 0x16 || c == 0x0F));
+
+        // The scroll a line was drawn with, from the right nametable
+        ppu.cpu_write(&mut cart, 0x0000, 0x01);
+        ppu.cpu_write(&mut cart, 0x0005, 45);
+        ppu.cpu_write(&mut cart, 0x0005, 17);
+        ppu.cpu_write(&mut cart, 0x0001, 0x08);
+        ppu.scroll_capture_line = 100;
+        ppu.scanline = -1;
+        ppu.cycle = 0;
+        while ppu.scanline <= 100 {
+            ppu.clock(&mut cart);
+        }
+        assert_eq!(ppu.scroll(), (256 + 45, 17));
+
+        // A split at the start of line 101 draws it from the top of the nametable
+        ppu.cpu_write(&mut cart, 0x0005, 0);
+        ppu.cpu_write(&mut cart, 0x0005, 0);
+        ppu.cpu_write(&mut cart, 0x0006, 0x00);
+        ppu.cpu_write(&mut cart, 0x0006, 0x00);
+        ppu.scroll_capture_line = 150;
+        while ppu.scanline <= 150 {
+            ppu.clock(&mut cart);
+        }
+        assert_eq!(ppu.scroll(), (0, NAME_TABLES_HEIGHT - 101));
     }
 }
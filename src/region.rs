@@ -0,0 +1,122 @@
+// Console timing. NTSC is the Famicom and the North American NES, PAL the European NES, and
+// Dendy the Famiclone that pairs PAL frame rates with NTSC CPU speed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(name: &str) -> Option<Region> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // Lines per frame, counting the pre-render line
+    pub fn scanlines(self) -> i16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Vertical blank, and with it NMI, starts on dot 1 of this line. Dendy keeps the 20 line
+    // NTSC vertical blank and idles through the extra 50 lines before it instead.
+    pub fn vblank_line(self) -> i16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Only the NTSC PPU drops the first dot of odd frames while rendering
+    pub fn skips_odd_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    // PPU dots per CPU cycle as a fraction, 3.2 on PAL
+    pub fn dots_per_cpu_cycle(self) -> (u8, u8) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    // PPU dots per second, master clock / 4 on NTSC and / 5 otherwise
+    pub fn dot_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 5_369_318.0,
+            Region::Pal | Region::Dendy => 5_320_342.5,
+        }
+    }
+
+    // CPU cycles per second
+    pub fn cpu_rate(self) -> f64 {
+        let (dots, cycles) = self.dots_per_cpu_cycle();
+        self.dot_rate() * cycles as f64 / dots as f64
+    }
+
+    pub fn frame_rate(self) -> f64 {
+        let mut dots = self.scanlines() as f64 * 341.0;
+        if self.skips_odd_dot() {
+            dots -= 0.5;
+        }
+
+        self.dot_rate() / dots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::tests::nestest, Bus, Cpu, Ppu};
+
+    #[test]
+    fn frame_timing() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter().copied() {
+            let mut cart = nestest();
+            let mut bus = Bus::new();
+            let mut ppu = Ppu::new();
+            let mut cpu = Cpu::new();
+            bus.set_region(region);
+            ppu.set_region(region);
+            bus.reset(&mut cpu, &mut ppu, &mut cart);
+
+            while !ppu.frame_complete {
+                bus.clock(&mut cpu, &mut ppu, &mut cart);
+            }
+            ppu.frame_complete = false;
+
+            // One whole frame with rendering off, so no dot is skipped. The CPU makes one
+            // access a cycle, which the access log counts.
+            let mut dots = 0;
+            let mut vblank = None;
+            bus.access_log.enabled = true;
+            while !ppu.frame_complete {
+                bus.clock(&mut cpu, &mut ppu, &mut cart);
+                dots += 1;
+                if vblank.is_none() && ppu.cpu_read(&mut cart, 0x0002, true) & 0x80 != 0 {
+                    vblank = Some((ppu.scanline, ppu.cycle));
+                }
+            }
+
+            let cpu_cycles = bus.access_log.accesses.len() as f64;
+            let expected = dots as f64
+                / match region {
+                    Region::Pal => 3.2,
+                    _ => 3.0,
+                };
+            assert_eq!(dots, region.scanlines() as i32 * 341, "{:?}", region);
+            assert!((cpu_cycles - expected).abs() <= 1.0, "{:?}", region);
+            assert_eq!(vblank, Some((region.vblank_line(), 2)), "{:?}", region);
+        }
+
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+    }
+}
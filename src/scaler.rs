@@ -0,0 +1,428 @@
+use crate::{
+    hqx,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    xbrz,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scaler {
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+    // hq2x or hq4x
+    Hq(usize),
+    // xBRZ at 2x to 4x
+    Xbrz(usize),
+}
+
+impl Scaler {
+    pub fn parse(name: &str) -> Option<Scaler> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" | "nearest" => Some(Scaler::Nearest(1)),
+            "nearest2x" => Some(Scaler::Nearest(2)),
+            "nearest3x" => Some(Scaler::Nearest(3)),
+            "nearest4x" => Some(Scaler::Nearest(4)),
+            "scale2x" => Some(Scaler::Scale2x),
+            "scale3x" => Some(Scaler::Scale3x),
+            "hq2x" => Some(Scaler::Hq(2)),
+            "hq4x" => Some(Scaler::Hq(4)),
+            "xbrz2x" => Some(Scaler::Xbrz(2)),
+            "xbrz3x" => Some(Scaler::Xbrz(3)),
+            "xbrz4x" => Some(Scaler::Xbrz(4)),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::Nearest(n) | Scaler::Hq(n) | Scaler::Xbrz(n) => n,
+            Scaler::Scale2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+}
+
+// NES pixels to drop from each edge, where TVs hid the picture behind the bezel
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    // "top,bottom,left,right"
+    pub fn parse(spec: &str) -> Option<Overscan> {
+        let sides: Vec<usize> = spec
+            .split(',')
+            .map(|side| side.trim().parse().ok())
+            .collect::<Option<_>>()?;
+
+        match sides[..] {
+            [top, bottom, left, right]
+                if top + bottom < SCREEN_HEIGHT && left + right < SCREEN_WIDTH =>
+            {
+                Some(Overscan {
+                    top,
+                    bottom,
+                    left,
+                    right,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+// Crops, scales and corrects the aspect of a finished frame, for the window and screenshots
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pipeline {
+    pub scaler: Scaler,
+    pub overscan: Overscan,
+    // NTSC pixels are 8/7 as wide as they are tall
+    pub aspect: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            scaler: Scaler::Nearest(1),
+            overscan: Overscan::default(),
+            aspect: false,
+        }
+    }
+}
+
+impl Pipeline {
+    // Takes RGBA with any number of columns for the PPU's 256, like the NTSC filter's 602
+    pub fn process(&self, rgba: &[u8], width: usize, height: usize) -> Frame {
+        let columns = |nes: usize| nes * width / SCREEN_WIDTH;
+        let (left, right) = (columns(self.overscan.left), columns(self.overscan.right));
+        let (top, bottom) = (self.overscan.top, self.overscan.bottom);
+        let (crop_width, crop_height) = (width - left - right, height - top - bottom);
+
+        let mut pixels = Vec::with_capacity(crop_width * crop_height);
+        for y in top..height - bottom {
+            let row = &rgba[(y * width + left) * 4..(y * width + width - right) * 4];
+            pixels.extend(
+                row.chunks_exact(4)
+                    .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]])),
+            );
+        }
+
+        let image = Image {
+            width: crop_width,
+            height: crop_height,
+            pixels,
+        };
+        let mut image = match self.scaler {
+            Scaler::Nearest(n) => nearest(&image, n),
+            Scaler::Scale2x => scale2x(&image),
+            Scaler::Scale3x => scale3x(&image),
+            Scaler::Hq(n) => hqx::scale(&image, n),
+            Scaler::Xbrz(n) => xbrz::scale(&image, n),
+        };
+
+        if self.aspect {
+            let nes_columns = SCREEN_WIDTH - self.overscan.left - self.overscan.right;
+            let scale = image.height as f32 / crop_height as f32;
+            let width = (nes_columns as f32 * scale * 8.0 / 7.0).round() as usize;
+            image = stretch(&image, width);
+        }
+
+        let mut rgba = Vec::with_capacity(image.pixels.len() * 4);
+        for pixel in &image.pixels {
+            rgba.extend_from_slice(&pixel.to_le_bytes());
+        }
+
+        Frame {
+            width: image.width,
+            height: image.height,
+            rgba,
+        }
+    }
+}
+
+// Pixels packed as little-endian RGBA
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    // Edges repeat outwards
+    pub fn get(&self, x: isize, y: isize) -> u32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: u32) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+}
+
+fn nearest(image: &Image, n: usize) -> Image {
+    let mut out = Image::new(image.width * n, image.height * n);
+    for y in 0..out.height {
+        for x in 0..out.width {
+            out.set(x, y, image.pixels[(y / n) * image.width + x / n]);
+        }
+    }
+
+    out
+}
+
+// AdvMAME2x: each corner copies a neighbour when two meeting edges agree
+fn scale2x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 2, image.height * 2);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let at = |dx, dy| image.get(x as isize + dx, y as isize + dy);
+            let (b, d, e, f, h) = (at(0, -1), at(-1, 0), at(0, 0), at(1, 0), at(0, 1));
+
+            let (mut e0, mut e1, mut e2, mut e3) = (e, e, e, e);
+            if b != h && d != f {
+                if d == b {
+                    e0 = d;
+                }
+                if b == f {
+                    e1 = f;
+                }
+                if d == h {
+                    e2 = d;
+                }
+                if h == f {
+                    e3 = f;
+                }
+            }
+
+            out.set(x * 2, y * 2, e0);
+            out.set(x * 2 + 1, y * 2, e1);
+            out.set(x * 2, y * 2 + 1, e2);
+            out.set(x * 2 + 1, y * 2 + 1, e3);
+        }
+    }
+
+    out
+}
+
+// AdvMAME3x
+fn scale3x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 3, image.height * 3);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let at = |dx, dy| image.get(x as isize + dx, y as isize + dy);
+            let (a, b, c) = (at(-1, -1), at(0, -1), at(1, -1));
+            let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+            let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                let pick = |cond: bool, colour: u32| if cond { colour } else { e };
+                block = [
+                    pick(d == b, d),
+                    pick((d == b && e != c) || (b == f && e != a), b),
+                    pick(b == f, f),
+                    pick((d == b && e != g) || (d == h && e != a), d),
+                    e,
+                    pick((b == f && e != i) || (h == f && e != c), f),
+                    pick(d == h, d),
+                    pick((d == h && e != i) || (h == f && e != g), h),
+                    pick(h == f, f),
+                ];
+            }
+
+            for (n, pixel) in block.iter().enumerate() {
+                out.set(x * 3 + n % 3, y * 3 + n / 3, *pixel);
+            }
+        }
+    }
+
+    out
+}
+
+fn mix(a: u32, b: u32, weight: f32) -> u32 {
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    let mut out = [0; 4];
+    for (channel, out) in out.iter_mut().enumerate() {
+        let (a, b) = (a[channel] as f32, b[channel] as f32);
+        *out = (a + (b - a) * weight).round() as u8;
+    }
+
+    u32::from_le_bytes(out)
+}
+
+// Resamples each row to a new width, blending neighbouring pixels
+fn stretch(image: &Image, width: usize) -> Image {
+    let mut out = Image::new(width, image.height);
+    let step = image.width as f32 / width as f32;
+    for x in 0..width {
+        let source = ((x as f32 + 0.5) * step - 0.5).max(0.0);
+        let left = source as usize;
+        let weight = source - left as f32;
+        for y in 0..image.height {
+            let a = image.get(left as isize, y as isize);
+            let b = image.get(left as isize + 1, y as isize);
+            out.set(x, y, mix(a, b, weight));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pixels: &[u32]) -> Vec<u8> {
+        pixels
+            .iter()
+            .flat_map(|p| p.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn pixels(frame: &Frame) -> Vec<u32> {
+        frame
+            .rgba
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn scalers() {
+        // An inside corner: Scale2x rounds it off
+        let (o, x) = (0xFF00_0000, 0xFFFF_FFFF);
+        let image = [x, x, x, o];
+        let pipeline = |scaler| Pipeline {
+            scaler,
+            ..Pipeline::default()
+        };
+        let scaled = pipeline(Scaler::Scale2x).process(&frame(&image), 2, 2);
+        assert_eq!((scaled.width, scaled.height), (4, 4));
+        assert_eq!(
+            pixels(&scaled),
+            [x, x, x, x, x, x, x, x, x, x, x, o, x, x, o, o]
+        );
+
+        // A flat picture stays flat whatever the scaler
+        let flat = frame(&[0xFF33_6699; 16]);
+        for scaler in [
+            Scaler::Nearest(3),
+            Scaler::Scale3x,
+            Scaler::Hq(4),
+            Scaler::Xbrz(3),
+        ]
+        .iter()
+        {
+            let scaled = pipeline(*scaler).process(&flat, 4, 4);
+            assert_eq!(scaled.width, 4 * scaler.factor());
+            assert!(pixels(&scaled).iter().all(|&p| p == 0xFF33_6699));
+        }
+
+        // hq2x's pattern for the inside corner shades it 14:1:1, xBRZ rounds it a little
+        let scaled = pixels(&pipeline(Scaler::Hq(2)).process(&frame(&image), 2, 2));
+        assert_eq!(scaled[10], grey(31));
+        assert_eq!(scaled.iter().filter(|&&p| p != x && p != o).count(), 1);
+        for &(n, shade) in [(2, 53), (3, 114)].iter() {
+            let scaled = pixels(&pipeline(Scaler::Xbrz(n)).process(&frame(&image), 2, 2));
+            assert_eq!(scaled[n * 2 * n + n], grey(shade));
+            assert_eq!(scaled.iter().filter(|&&p| p != x && p != o).count(), 1);
+        }
+        let scaled = pixels(&pipeline(Scaler::Xbrz(4)).process(&frame(&image), 2, 2));
+        assert_eq!(
+            [scaled[36], scaled[37], scaled[44]],
+            [grey(173), grey(22), grey(22)]
+        );
+
+        assert_eq!(Scaler::parse("HQ4x"), Some(Scaler::Hq(4)));
+        assert_eq!(Scaler::parse("xbrz3x"), Some(Scaler::Xbrz(3)));
+        assert_eq!(Scaler::parse("hq3x"), None);
+    }
+
+    fn grey(level: u32) -> u32 {
+        0xFF00_0000 | (level * 0x01_0101)
+    }
+
+    #[test]
+    fn staircase() {
+        let (o, x) = (0xFF00_0000, 0xFFFF_FFFF);
+        #[rustfmt::skip]
+        let image = frame(&[
+            x, x, x, x,
+            x, x, x, o,
+            x, x, o, o,
+            x, o, o, o,
+        ]);
+        let block = |scaled: &[u32], width: usize, left: usize, top: usize, n: usize| {
+            let mut block = vec![];
+            for row in top..top + n {
+                block.extend_from_slice(&scaled[row * width + left..row * width + left + n]);
+            }
+            block
+        };
+
+        // The step's outer corner goes half grey and the inner one takes the line's slope,
+        // the same way for both at 2x
+        for scaler in [Scaler::Hq(2), Scaler::Xbrz(2)].iter() {
+            let process = |s: Scaler| {
+                pixels(
+                    &Pipeline {
+                        scaler: s,
+                        ..Pipeline::default()
+                    }
+                    .process(&image, 4, 4),
+                )
+            };
+            let scaled = process(*scaler);
+            assert_eq!(block(&scaled, 8, 4, 2, 2), [x, x, x, grey(127)]);
+            assert_eq!(block(&scaled, 8, 6, 2, 2), [grey(191), grey(63), o, o]);
+        }
+
+        // hq4x runs the edge through the corner of the block
+        let pipeline = Pipeline {
+            scaler: Scaler::Hq(4),
+            ..Pipeline::default()
+        };
+        let scaled = pixels(&pipeline.process(&image, 4, 4));
+        assert_eq!(block(&scaled, 16, 10, 6, 2), [x, grey(127), grey(127), o]);
+    }
+
+    #[test]
+    fn crop_and_aspect() {
+        let pipeline = Pipeline {
+            scaler: Scaler::Nearest(2),
+            overscan: Overscan::parse("8,8,0,0").unwrap(),
+            aspect: true,
+        };
+        let blank = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        let scaled = pipeline.process(&blank, SCREEN_WIDTH, SCREEN_HEIGHT);
+        assert_eq!((scaled.width, scaled.height), (585, 448));
+
+        // The NTSC filter's wider lines come out the same shape
+        let blank = vec![0; 602 * SCREEN_HEIGHT * 4];
+        let scaled = pipeline.process(&blank, 602, SCREEN_HEIGHT);
+        assert_eq!((scaled.width, scaled.height), (585, 448));
+
+        assert_eq!(Overscan::parse("8,8"), None);
+        assert_eq!(Overscan::parse("200,100,0,0"), None);
+    }
+}
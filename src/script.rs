@@ -0,0 +1,204 @@
+use std::io::Write;
+
+use crate::{
+    bus::SystemBus,
+    debugger::{Debugger, Expr},
+    Bus, Cartridge, Cpu, Ppu,
+};
+
+// Runs debugger commands against the emulator without a window, one command per line:
+//
+//   break SPEC     adds a breakpoint in Debugger::add syntax
+//   continue       runs to the next breakpoint
+//   step           runs one instruction
+//   over           runs one instruction, or a whole subroutine call
+//   out            runs until the current subroutine or interrupt handler returns
+//   scanline N     runs until the PPU reaches the start of line N
+//   reason         prints why execution last stopped
+//   print EXPR     prints a value in breakpoint condition syntax, such as "[$0300] + X"
+//
+// Each run prints where it stopped. Lines starting with # are comments.
+
+// A run that hits nothing gives up after about a second of PPU dots
+const RUN_LIMIT: u32 = 5_369_318;
+
+pub struct ScriptHost<'a, W: Write> {
+    out: W,
+    cpu: &'a mut Cpu,
+    bus: &'a mut Bus,
+    ppu: &'a mut Ppu,
+    cart: &'a mut Cartridge,
+    debugger: &'a mut Debugger,
+}
+
+impl<'a, W: Write> ScriptHost<'a, W> {
+    pub fn new(
+        out: W,
+        cpu: &'a mut Cpu,
+        bus: &'a mut Bus,
+        ppu: &'a mut Ppu,
+        cart: &'a mut Cartridge,
+        debugger: &'a mut Debugger,
+    ) -> Self {
+        ScriptHost {
+            out,
+            cpu,
+            bus,
+            ppu,
+            cart,
+            debugger,
+        }
+    }
+
+    // Stops at the first command that fails
+    pub fn run(&mut self, script: &str) -> Result<(), String> {
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.command(line)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str) -> Result<(), String> {
+        let (name, arg) = match line.split_once(' ') {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (line, ""),
+        };
+
+        match name {
+            "break" => return self.debugger.add(arg),
+            "continue" => self.debugger.resume(),
+            "step" => self.debugger.step_instruction(),
+            "over" => self.debugger.step_over(
+                self.cpu,
+                &mut SystemBus {
+                    bus: self.bus,
+                    ppu: self.ppu,
+                    cart: self.cart,
+                },
+            ),
+            "out" => self.debugger.step_out(self.cpu),
+            "scanline" => {
+                let scanline = arg.parse().map_err(|_| format!("bad scanline {}", arg))?;
+                self.debugger.run_to_scanline(scanline);
+            }
+            "reason" => {
+                let text = match self.debugger.reason() {
+                    Some(reason) => reason.to_string(),
+                    None => "running".to_string(),
+                };
+                return self.print(&text);
+            }
+            "print" => {
+                let value = Expr::parse(arg)?.eval(
+                    self.cpu,
+                    None,
+                    &mut SystemBus {
+                        bus: self.bus,
+                        ppu: self.ppu,
+                        cart: self.cart,
+                    },
+                );
+                return self.print(&format!("{} = {} (${:X})", arg, value, value));
+            }
+            _ => return Err(format!("unknown command {}", name)),
+        }
+
+        self.run_to_break()
+    }
+
+    fn run_to_break(&mut self) -> Result<(), String> {
+        for _ in 0..RUN_LIMIT {
+            if self.debugger.clock(self.cpu, self.bus, self.ppu, self.cart) {
+                let reason = self.debugger.reason().expect("stopped without a reason");
+                return self.print(&format!("{} at ${:04X}", reason, self.cpu.pc));
+            }
+        }
+
+        self.print("still running")
+    }
+
+    fn print(&mut self, text: &str) -> Result<(), String> {
+        writeln!(self.out, "{}", text).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::nestest;
+
+    #[test]
+    fn commands() {
+        let mut cart = nestest();
+        let mut bus = Bus::new();
+        let mut ppu = Ppu::new();
+        let mut cpu = Cpu::new();
+        let mut debugger = Debugger::new();
+
+        bus.reset(&mut cpu, &mut ppu, &mut cart);
+        while !cpu.complete() {
+            bus.clock(&mut cpu, &mut ppu, &mut cart);
+        }
+        cpu.pc = 0xC000;
+
+        let script = "
+            # Into nestest's first test, over JSR $C72D, into JSR $C7DB and back out
+            break C5FD
+            continue
+            over
+            step
+            out
+            print PC
+            reason
+
+            scanline 241
+            print SCANLINE
+        ";
+        let mut out = Vec::new();
+        ScriptHost::new(
+            &mut out,
+            &mut cpu,
+            &mut bus,
+            &mut ppu,
+            &mut cart,
+            &mut debugger,
+        )
+        .run(script)
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "breakpoint 0 at $C5FD\n\
+             step at $C600\n\
+             step at $C7DB\n\
+             step at $C603\n\
+             PC = 50691 ($C603)\n\
+             step\n\
+             step at $0068\n\
+             SCANLINE = 241 ($F1)\n"
+        );
+
+        let mut host = ScriptHost::new(
+            Vec::new(),
+            &mut cpu,
+            &mut bus,
+            &mut ppu,
+            &mut cart,
+            &mut debugger,
+        );
+        assert_eq!(
+            host.run("reason\nfrobnicate\nstep"),
+            Err("line 2: unknown command frobnicate".to_string())
+        );
+        assert_eq!(host.out, b"step\n");
+        assert!(host.run("scanline x").is_err());
+        assert!(host.run("break x:0000").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::audio::SAMPLE_RATE;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+// Samples waiting for the device, beyond which the oldest are dropped to keep latency down
+const MAX_QUEUED: usize = SAMPLE_RATE as usize / 10;
+
+// Streams the core's samples to the sound card from a thread of its own, holding the last
+// sample through underruns. Only ALSA on Linux is supported; elsewhere, or without a device,
+// the emulator runs silent.
+pub struct Sound {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl Sound {
+    pub fn open() -> Sound {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        #[cfg(target_os = "linux")]
+        {
+            let queue = queue.clone();
+            std::thread::spawn(move || alsa::stream(queue));
+        }
+
+        Sound { queue }
+    }
+
+    pub fn queue(&self, samples: impl Iterator<Item = f32>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+
+        if queue.len() > MAX_QUEUED {
+            let excess = queue.len() - MAX_QUEUED;
+            queue.drain(..excess);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod alsa {
+    use super::SAMPLE_RATE;
+    use quad_alsa_sys as sys;
+    use std::{
+        collections::VecDeque,
+        ptr,
+        sync::{Arc, Mutex},
+    };
+
+    const PERIOD: usize = 512;
+    const LATENCY_US: u32 = 50_000;
+    const VOLUME: f32 = 0.5;
+
+    fn open() -> Option<*mut sys::snd_pcm_t> {
+        for device in [&b"default\0"[..], &b"pipewire\0"[..]].iter() {
+            let mut pcm = ptr::null_mut();
+            unsafe {
+                if sys::snd_pcm_open(
+                    &mut pcm,
+                    device.as_ptr() as *const _,
+                    sys::SND_PCM_STREAM_PLAYBACK,
+                    0,
+                ) < 0
+                {
+                    continue;
+                }
+
+                if sys::snd_pcm_set_params(
+                    pcm,
+                    sys::SND_PCM_FORMAT_FLOAT_LE,
+                    sys::SND_PCM_ACCESS_RW_INTERLEAVED,
+                    1,
+                    SAMPLE_RATE,
+                    1,
+                    LATENCY_US,
+                ) < 0
+                {
+                    sys::snd_pcm_close(pcm);
+                    continue;
+                }
+            }
+
+            return Some(pcm);
+        }
+
+        None
+    }
+
+    pub fn stream(queue: Arc<Mutex<VecDeque<f32>>>) {
+        let pcm = match open() {
+            Some(pcm) => pcm,
+            None => {
+                println!("No sound device, running silent");
+                return;
+            }
+        };
+
+        let mut buffer = [0.0; PERIOD];
+        let mut last = 0.0;
+        loop {
+            {
+                let mut queue = queue.lock().unwrap();
+                for sample in buffer.iter_mut() {
+                    last = queue.pop_front().unwrap_or(last);
+                    *sample = (last * VOLUME).clamp(-1.0, 1.0);
+                }
+            }
+
+            // Blocks until the device has room, which paces the thread
+            let mut written = 0;
+            while written < PERIOD {
+                let frames = unsafe {
+                    sys::snd_pcm_writei(
+                        pcm,
+                        buffer[written..].as_ptr() as *const _,
+                        (PERIOD - written) as sys::snd_pcm_uframes_t,
+                    )
+                };
+
+                if frames >= 0 {
+                    written += frames as usize;
+                } else if unsafe { sys::snd_pcm_recover(pcm, frames as i32, 1) } < 0 {
+                    println!("Sound device lost, running silent");
+                    unsafe { sys::snd_pcm_close(pcm) };
+                    return;
+                }
+            }
+        }
+    }
+}
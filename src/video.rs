@@ -0,0 +1,102 @@
+use crate::scaler::Frame;
+use std::io::{self, Write};
+
+// Records frames from the display pipeline as uncompressed YUV4MPEG2, which ffmpeg and mpv
+// play or convert as is. Frames are full resolution 4:4:4 with BT.601 limited range colour.
+pub struct Recorder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Recorder<W> {
+    // The pixel aspect is width:height of one frame pixel as the window shows it
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        frame_rate: f64,
+        aspect: (usize, usize),
+    ) -> io::Result<Recorder<W>> {
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:1000 Ip A{}:{} C444",
+            width,
+            height,
+            (frame_rate * 1000.0).round() as u64,
+            aspect.0,
+            aspect.1
+        )?;
+
+        Ok(Recorder {
+            out,
+            width,
+            height,
+            planes: vec![0; width * height * 3],
+        })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> io::Result<()> {
+        if (frame.width, frame.height) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame size changed while recording",
+            ));
+        }
+
+        let size = self.width * self.height;
+        let (y, chroma) = self.planes.split_at_mut(size);
+        let (u, v) = chroma.split_at_mut(size);
+        for (i, pixel) in frame.rgba.chunks_exact(4).enumerate() {
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            y[i] = (16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8;
+            u[i] = (128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8;
+            v[i] = (128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn y4m() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            rgba: vec![0, 0, 0, 255, 255, 255, 255, 255],
+        };
+        let mut recorder = Recorder::new(Vec::new(), 2, 1, 60.0988, (8, 7)).unwrap();
+        recorder.write(&frame).unwrap();
+        recorder.write(&frame).unwrap();
+        let out = recorder.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F60099:1000 Ip A8:7 C444\n";
+        assert_eq!(&out[..header.len()], &header[..]);
+
+        // Black and white, then neutral chroma for both
+        let planes = [16, 235, 128, 128, 128, 128];
+        let frames = &out[header.len()..];
+        assert_eq!(frames.len(), 2 * (6 + planes.len()));
+        assert_eq!(&frames[..6], b"FRAME\n");
+        assert_eq!(&frames[6..12], &planes[..]);
+
+        let wrong_size = Frame {
+            width: 1,
+            height: 2,
+            rgba: frame.rgba,
+        };
+        let mut recorder = Recorder::new(Vec::new(), 2, 1, 50.0, (1, 1)).unwrap();
+        assert!(recorder.write(&wrong_size).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::scaler::Image;
+
+// xBRZ, Zenju's scaler, with its default settings. First every corner where four pixels meet
+// gets a blend decision, from which diagonal has the weaker edges. Then each pixel fills its
+// block and blends its blended corners towards a neighbour's colour, shaped as a rounded corner,
+// a diagonal, or a shallow or steep line, depending on the edges around it.
+
+const EQUAL_COLOUR_TOLERANCE: f64 = 30.0;
+const DOMINANT_DIRECTION_THRESHOLD: f64 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f64 = 2.2;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Blend {
+    None,
+    Normal,
+    Dominant,
+}
+
+// Blends for the pixels meeting at a corner:
+//   F G
+//   J K
+#[derive(Clone, Copy)]
+struct Corner {
+    f: Blend,
+    g: Blend,
+    j: Blend,
+    k: Blend,
+}
+
+const NO_BLEND: Corner = Corner {
+    f: Blend::None,
+    g: Blend::None,
+    j: Blend::None,
+    k: Blend::None,
+};
+
+enum Shape {
+    Corner,
+    Diagonal,
+    Shallow,
+    Steep,
+    SteepAndShallow,
+}
+
+// n is 2 to 4
+pub fn scale(image: &Image, n: usize) -> Image {
+    // The corner below and right of each pixel
+    let mut corners = Vec::with_capacity(image.pixels.len());
+    for y in 0..image.height {
+        for x in 0..image.width {
+            corners.push(corner(image, x, y));
+        }
+    }
+    // Corners past the top and left edges never blend
+    let corner_at = |x: usize, y: usize, dx: usize, dy: usize| {
+        if x < dx || y < dy {
+            NO_BLEND
+        } else {
+            corners[(y - dy) * image.width + x - dx]
+        }
+    };
+
+    let mut out = Image::new(image.width * n, image.height * n);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let pixel = image.pixels[y * image.width + x];
+            for row in 0..n {
+                for col in 0..n {
+                    out.set(x * n + col, y * n + row, pixel);
+                }
+            }
+
+            // Top left, top right, bottom right, bottom left
+            let blends = [
+                corner_at(x, y, 1, 1).k,
+                corner_at(x, y, 0, 1).j,
+                corner_at(x, y, 0, 0).f,
+                corner_at(x, y, 1, 0).g,
+            ];
+            for turns in 0..4 {
+                blend_pixel(image, &mut out, x, y, n, blends, turns);
+            }
+        }
+    }
+
+    out
+}
+
+// Decides the blends at the corner between the pixel at (x, y) and the three below and right
+fn corner(image: &Image, x: usize, y: usize) -> Corner {
+    let at = |dx, dy| image.get(x as isize + dx, y as isize + dy);
+    let (b, c) = (at(0, -1), at(1, -1));
+    let (e, f, g, h) = (at(-1, 0), at(0, 0), at(1, 0), at(2, 0));
+    let (i, j, k, l) = (at(-1, 1), at(0, 1), at(1, 1), at(2, 1));
+    let (n, o) = (at(0, 2), at(1, 2));
+
+    let mut corner = NO_BLEND;
+    if (f == g && j == k) || (f == j && g == k) {
+        return corner;
+    }
+
+    // Edge strength across each diagonal, weighting the corner's own pixels
+    let jg =
+        distance(i, f) + distance(f, c) + distance(n, k) + distance(k, h) + 4.0 * distance(j, g);
+    let fk =
+        distance(e, j) + distance(j, o) + distance(b, g) + distance(g, l) + 4.0 * distance(f, k);
+
+    if jg < fk {
+        let blend = if DOMINANT_DIRECTION_THRESHOLD * jg < fk {
+            Blend::Dominant
+        } else {
+            Blend::Normal
+        };
+        if f != g && f != j {
+            corner.f = blend;
+        }
+        if k != j && k != g {
+            corner.k = blend;
+        }
+    } else if fk < jg {
+        let blend = if DOMINANT_DIRECTION_THRESHOLD * fk < jg {
+            Blend::Dominant
+        } else {
+            Blend::Normal
+        };
+        if j != f && j != k {
+            corner.j = blend;
+        }
+        if g != f && g != k {
+            corner.g = blend;
+        }
+    }
+
+    corner
+}
+
+// Blends one corner of the pixel at (x, y). Everything is turned clockwise `turns` quarter turns
+// first, so the corner being blended is always the bottom right one.
+fn blend_pixel(
+    image: &Image,
+    out: &mut Image,
+    x: usize,
+    y: usize,
+    n: usize,
+    blends: [Blend; 4],
+    turns: usize,
+) {
+    let blend = |corner: usize| blends[(corner + 4 - turns) % 4];
+    if blend(2) == Blend::None {
+        return;
+    }
+
+    let at = |mut dx: isize, mut dy: isize| {
+        for _ in 0..turns {
+            let turned = (dy, -dx);
+            dx = turned.0;
+            dy = turned.1;
+        }
+        image.get(x as isize + dx, y as isize + dy)
+    };
+    let (b, c) = (at(0, -1), at(1, -1));
+    let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+    let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+    let eq = |a, b| distance(a, b) < EQUAL_COLOUR_TOLERANCE;
+
+    let line = blend(2) == Blend::Dominant
+        // Another corner blending next to this one means an isolated pixel, unless it is a
+        // 90 degree corner
+        || !((blend(1) != Blend::None && !eq(e, g))
+            || (blend(3) != Blend::None && !eq(e, c))
+            // Only the corner of an L shape
+            || (!eq(e, i) && eq(g, h) && eq(h, i) && eq(i, f) && eq(f, c)));
+
+    let colour = if distance(e, f) <= distance(e, h) {
+        f
+    } else {
+        h
+    };
+
+    let shape = if line {
+        let (fg, hc) = (distance(f, g), distance(h, c));
+        let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+        let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+        match (shallow, steep) {
+            (true, true) => Shape::SteepAndShallow,
+            (true, false) => Shape::Shallow,
+            (false, true) => Shape::Steep,
+            (false, false) => Shape::Diagonal,
+        }
+    } else {
+        Shape::Corner
+    };
+
+    for &(mut row, mut col, m, total) in cells(n, shape) {
+        for _ in 0..turns {
+            let turned = (n - 1 - col, row);
+            row = turned.0;
+            col = turned.1;
+        }
+        let (px, py) = (x * n + col, y * n + row);
+        let back = out.pixels[py * out.width + px];
+        out.set(px, py, gradient(colour, back, m, total));
+    }
+}
+
+// Output cells of the bottom right corner as (row, column) with the colour's opacity
+// as a fraction
+fn cells(n: usize, shape: Shape) -> &'static [(usize, usize, u32, u32)] {
+    match (n, shape) {
+        (2, Shape::Corner) => &[(1, 1, 21, 100)],
+        (2, Shape::Diagonal) => &[(1, 1, 1, 2)],
+        (2, Shape::Shallow) => &[(1, 0, 1, 4), (1, 1, 3, 4)],
+        (2, Shape::Steep) => &[(0, 1, 1, 4), (1, 1, 3, 4)],
+        (2, Shape::SteepAndShallow) => &[(1, 0, 1, 4), (0, 1, 1, 4), (1, 1, 5, 6)],
+        (3, Shape::Corner) => &[(2, 2, 45, 100)],
+        (3, Shape::Diagonal) => &[(1, 2, 1, 8), (2, 1, 1, 8), (2, 2, 7, 8)],
+        (3, Shape::Shallow) => &[(2, 0, 1, 4), (1, 2, 1, 4), (2, 1, 3, 4), (2, 2, 1, 1)],
+        (3, Shape::Steep) => &[(0, 2, 1, 4), (2, 1, 1, 4), (1, 2, 3, 4), (2, 2, 1, 1)],
+        (3, Shape::SteepAndShallow) => &[
+            (2, 0, 1, 4),
+            (0, 2, 1, 4),
+            (2, 1, 3, 4),
+            (1, 2, 3, 4),
+            (2, 2, 1, 1),
+        ],
+        (_, Shape::Corner) => &[(3, 3, 68, 100), (3, 2, 9, 100), (2, 3, 9, 100)],
+        (_, Shape::Diagonal) => &[(3, 2, 1, 2), (2, 3, 1, 2), (3, 3, 1, 1)],
+        (_, Shape::Shallow) => &[
+            (3, 0, 1, 4),
+            (2, 2, 1, 4),
+            (3, 1, 3, 4),
+            (2, 3, 3, 4),
+            (3, 2, 1, 1),
+            (3, 3, 1, 1),
+        ],
+        (_, Shape::Steep) => &[
+            (0, 3, 1, 4),
+            (2, 2, 1, 4),
+            (1, 3, 3, 4),
+            (3, 2, 3, 4),
+            (2, 3, 1, 1),
+            (3, 3, 1, 1),
+        ],
+        (_, Shape::SteepAndShallow) => &[
+            (3, 1, 3, 4),
+            (1, 3, 3, 4),
+            (3, 0, 1, 4),
+            (0, 3, 1, 4),
+            (2, 2, 1, 3),
+            (3, 3, 1, 1),
+            (3, 2, 1, 1),
+            (2, 3, 1, 1),
+        ],
+    }
+}
+
+// Lays the front colour at opacity m/total over the back one
+fn gradient(front: u32, back: u32, m: u32, total: u32) -> u32 {
+    let (front, mut out) = (front.to_le_bytes(), back.to_le_bytes());
+    for channel in 0..3 {
+        let (f, b) = (front[channel] as u32, out[channel] as u32);
+        out[channel] = ((f * m + b * (total - m)) / total) as u8;
+    }
+
+    u32::from_le_bytes(out)
+}
+
+// Distance in BT.2020 YCbCr, on the 0-255 scale of the channels
+fn distance(a: u32, b: u32) -> f64 {
+    const K_B: f64 = 0.0593;
+    const K_R: f64 = 0.2627;
+    const K_G: f64 = 1.0 - K_B - K_R;
+
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    let diff = |channel: usize| a[channel] as f64 - b[channel] as f64;
+    let (r, g, b) = (diff(0), diff(1), diff(2));
+
+    let y = K_R * r + K_G * g + K_B * b;
+    let c_b = 0.5 / (1.0 - K_B) * (b - y);
+    let c_r = 0.5 / (1.0 - K_R) * (r - y);
+    (y * y + c_b * c_b + c_r * c_r).sqrt()
+}